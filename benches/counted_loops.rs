@@ -0,0 +1,267 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::time::Duration;
+
+#[cfg(target_os = "macos")]
+const MEASUREMENT_TIME: u64 = 5;
+#[cfg(target_os = "linux")]
+const MEASUREMENT_TIME: u64 = 5;
+
+const SAMPLE_SIZE: usize = 20;
+
+/// Trip counts used for benchmarking.
+const TRIP_COUNTS: [usize; 5] = [10, 100, 1_000, 10_000, 100_000];
+
+const TEMPLATE: &str = r#"
+(module
+  (func (export "fun") (result i32)
+    (local $i i32) (local $sum i32)
+    i32.const 0
+    local.set $i
+    loop
+      local.get $sum
+      local.get $i
+      i32.add
+      local.set $sum
+      local.get $i
+      i32.const 1
+      i32.add
+      local.tee $i
+      i32.const <TRIP_COUNT>
+      i32.lt_s
+      br_if 0
+    end
+    local.get $sum
+  )
+)
+"#;
+
+/// Counted loop filling memory with 64-bit words, the shape of a zero-initialized buffer.
+const FILL_TEMPLATE: &str = r#"
+(module
+  (memory 16)
+  (func (export "fun") (result i32)
+    (local $i i32)
+    i32.const 0
+    local.set $i
+    loop
+      local.get $i
+      i64.const 0
+      i64.store
+      local.get $i
+      i32.const 8
+      i32.add
+      local.tee $i
+      i32.const <LIMIT>
+      i32.lt_u
+      br_if 0
+    end
+    local.get $i
+  )
+)
+"#;
+
+fn wat_source(trip_count: usize) -> String {
+  TEMPLATE.replace("<TRIP_COUNT>", &trip_count.to_string())
+}
+
+fn fill_wat_source(trip_count: usize) -> String {
+  FILL_TEMPLATE.replace("<LIMIT>", &(8 * trip_count).to_string())
+}
+
+fn make_config() -> Criterion {
+  Criterion::default()
+    .without_plots()
+    .measurement_time(Duration::new(MEASUREMENT_TIME, 0))
+    .sample_size(SAMPLE_SIZE)
+    .configure_from_args()
+}
+
+/// Variants of the benchmarked code: label, metering enabled, counted loop hoisting enabled.
+const VARIANTS: [(&str, bool, bool); 3] = [("unmetered", false, false), ("metered", true, false), ("hoisted", true, true)];
+
+/// Parses and encodes the WASM binary with the requested metering variant.
+fn instrument(wasm_bytes: &[u8], metering: bool, counted_loop_hoisting: bool) -> Vec<u8> {
+  let model = wasmarin::Parser::new().parse_wasm_bytes(wasm_bytes).unwrap();
+  let mut encoder = if metering {
+    wasmarin::Encoder::new().with_pass(wasmarin::Metering::new().with_counted_loop_hoisting(counted_loop_hoisting))
+  } else {
+    wasmarin::Encoder::new()
+  };
+  encoder.encode(model).unwrap()
+}
+
+/// Sets plenty of remaining points when the module is metered.
+fn set_remaining_points(store: &mut wasmtime::Store<()>, instance: &wasmtime::Instance) {
+  if let Some(remaining_points) = instance.get_global(&mut *store, wasmarin::REMAINING_POINTS_EXPORT_NAME) {
+    remaining_points.set(&mut *store, wasmtime::Val::I64(i64::MAX)).unwrap();
+  }
+}
+
+/// Instantiates the module and returns the benchmarked function.
+fn instantiate(wasm_bytes: &[u8]) -> (wasmtime::Store<()>, wasmtime::TypedFunc<(), i32>) {
+  let engine = wasmtime::Engine::default();
+  let module = wasmtime::Module::from_binary(&engine, wasm_bytes).unwrap();
+  let mut store = wasmtime::Store::new(&engine, ());
+  let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+  set_remaining_points(&mut store, &instance);
+  let fun = instance.get_typed_func::<(), i32>(&mut store, "fun").unwrap();
+  (store, fun)
+}
+
+/// Environment passed to the entry points of `burner.wasm` contract.
+const BURNER_ENV: &str =
+  r#"{"block":{"height":12345,"time":"1571797419879305533","chain_id":"cosmos-testnet-14002"},"transaction":{"index":3},"contract":{"address":"cosmos2contract"}}"#;
+
+/// Number of coins sent to `burner.wasm` contract, each parsed by the contract.
+const BURNER_FUNDS: usize = 100;
+
+/// Contract `burner.wasm` instantiated in a store, with the exported functions called by the benchmark.
+struct Burner {
+  store: wasmtime::Store<()>,
+  memory: wasmtime::Memory,
+  allocate: wasmtime::TypedFunc<u32, u32>,
+  deallocate: wasmtime::TypedFunc<u32, ()>,
+  instantiate: wasmtime::TypedFunc<(u32, u32, u32), u32>,
+  remaining_points: Option<wasmtime::Global>,
+}
+
+impl Burner {
+  /// Instantiates the contract, imported functions trap when called.
+  fn new(wasm_bytes: &[u8]) -> Self {
+    let engine = wasmtime::Engine::default();
+    let module = wasmtime::Module::from_binary(&engine, wasm_bytes).unwrap();
+    let mut linker = wasmtime::Linker::new(&engine);
+    linker.define_unknown_imports_as_traps(&module).unwrap();
+    let mut store = wasmtime::Store::new(&engine, ());
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    set_remaining_points(&mut store, &instance);
+    Self {
+      memory: instance.get_memory(&mut store, "memory").unwrap(),
+      allocate: instance.get_typed_func(&mut store, "allocate").unwrap(),
+      deallocate: instance.get_typed_func(&mut store, "deallocate").unwrap(),
+      instantiate: instance.get_typed_func(&mut store, "instantiate").unwrap(),
+      remaining_points: instance.get_global(&mut store, wasmarin::REMAINING_POINTS_EXPORT_NAME),
+      store,
+    }
+  }
+
+  /// Copies the data into a region allocated by the contract, returns the address of the region.
+  fn write_region(&mut self, data: &[u8]) -> u32 {
+    let region = self.allocate.call(&mut self.store, data.len() as u32).unwrap();
+    let mut offset = [0; 4];
+    self.memory.read(&self.store, region as usize, &mut offset).unwrap();
+    self.memory.write(&mut self.store, u32::from_le_bytes(offset) as usize, data).unwrap();
+    self.memory.write(&mut self.store, region as usize + 8, &(data.len() as u32).to_le_bytes()).unwrap();
+    region
+  }
+
+  /// Returns the data stored in the region with the given address.
+  fn read_region(&self, region: u32) -> Vec<u8> {
+    let mut header = [0; 12];
+    self.memory.read(&self.store, region as usize, &mut header).unwrap();
+    let offset = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    let length = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    let mut data = vec![0; length as usize];
+    self.memory.read(&self.store, offset as usize, &mut data).unwrap();
+    data
+  }
+
+  /// Calls the `instantiate` entry point, parsing the environment, the message info and the message.
+  ///
+  /// The contract can only be migrated, so it returns an error, after all inputs were parsed.
+  fn call_instantiate(&mut self, info: &str) -> Vec<u8> {
+    let env = self.write_region(BURNER_ENV.as_bytes());
+    let info = self.write_region(info.as_bytes());
+    let msg = self.write_region(b"{}");
+    let result = self.instantiate.call(&mut self.store, (env, info, msg)).unwrap();
+    let data = self.read_region(result);
+    self.deallocate.call(&mut self.store, result).unwrap();
+    data
+  }
+
+  /// Returns the remaining points, `None` when the contract is not metered.
+  fn remaining_points(&mut self) -> Option<i64> {
+    self.remaining_points.map(|global| global.get(&mut self.store).unwrap_i64())
+  }
+}
+
+/// Returns the message info sending many coins to the contract.
+fn burner_info() -> String {
+  let funds = (0..BURNER_FUNDS)
+    .map(|index| format!(r#"{{"denom":"denom{index}","amount":"{}"}}"#, 1000 + index))
+    .collect::<Vec<_>>();
+  format!(r#"{{"sender":"creator","funds":[{}]}}"#, funds.join(","))
+}
+
+/// Checks if the benchmarked Wasm code works and if the counted loops are really hoisted.
+fn precheck() {
+  for trip_count in TRIP_COUNTS {
+    for (wat_source, expected) in [
+      (wat_source(trip_count), (0..trip_count as i32).fold(0_i32, |sum, i| sum.wrapping_add(i))),
+      (fill_wat_source(trip_count), 8 * trip_count as i32),
+    ] {
+      let wasm_bytes = wat::parse_str(wat_source).unwrap();
+      // Hoisting must change the instrumentation, otherwise both metered variants measure the same code.
+      assert_ne!(instrument(&wasm_bytes, true, false), instrument(&wasm_bytes, true, true));
+      for (_, metering, counted_loop_hoisting) in VARIANTS {
+        let (mut store, fun) = instantiate(&instrument(&wasm_bytes, metering, counted_loop_hoisting));
+        assert_eq!(expected, fun.call(&mut store, ()).unwrap());
+      }
+    }
+  }
+  // The contract must behave and consume points the same way in all variants.
+  let wasm_bytes = std::fs::read("tests/contracts/burner.wasm").unwrap();
+  let info = burner_info();
+  let mut results = vec![];
+  for (label, metering, counted_loop_hoisting) in VARIANTS {
+    let instrumented = instrument(&wasm_bytes, metering, counted_loop_hoisting);
+    let mut burner = Burner::new(&instrumented);
+    let result = burner.call_instantiate(&info);
+    assert!(String::from_utf8_lossy(&result).contains("You can only use this contract for migrations"));
+    let consumed_points = burner.remaining_points().map(|remaining_points| i64::MAX - remaining_points);
+    println!("burner.wasm {label:>9}: {:>6} bytes, consumed points: {consumed_points:?}", instrumented.len());
+    results.push((result, consumed_points, instrumented.len()));
+  }
+  assert_eq!(results[0].0, results[1].0);
+  // None of the loops of the contract has statically known trip count, hoisting leaves the code unchanged.
+  assert_eq!(results[1], results[2]);
+}
+
+/// Execution time of a counted loop.
+fn _0001(c: &mut Criterion) {
+  precheck();
+  let mut group = c.benchmark_group("c.loop");
+  for trip_count in TRIP_COUNTS {
+    let wasm_bytes = wat::parse_str(wat_source(trip_count)).unwrap();
+    for (label, metering, counted_loop_hoisting) in VARIANTS {
+      let (mut store, fun) = instantiate(&instrument(&wasm_bytes, metering, counted_loop_hoisting));
+      group.bench_with_input(format!("{label}/{trip_count}"), &trip_count, |b, _| b.iter(|| fun.call(&mut store, ()).unwrap()));
+    }
+  }
+}
+
+/// Execution time of a counted loop filling memory.
+fn _0002(c: &mut Criterion) {
+  let mut group = c.benchmark_group("c.fill");
+  for trip_count in TRIP_COUNTS {
+    let wasm_bytes = wat::parse_str(fill_wat_source(trip_count)).unwrap();
+    for (label, metering, counted_loop_hoisting) in VARIANTS {
+      let (mut store, fun) = instantiate(&instrument(&wasm_bytes, metering, counted_loop_hoisting));
+      group.bench_with_input(format!("{label}/{trip_count}"), &trip_count, |b, _| b.iter(|| fun.call(&mut store, ()).unwrap()));
+    }
+  }
+}
+
+/// Execution time of the `instantiate` entry point of `burner.wasm` contract, parsing its inputs.
+fn _0003(c: &mut Criterion) {
+  let mut group = c.benchmark_group("burner");
+  let wasm_bytes = std::fs::read("tests/contracts/burner.wasm").unwrap();
+  let info = burner_info();
+  for (label, metering, counted_loop_hoisting) in VARIANTS {
+    let mut burner = Burner::new(&instrument(&wasm_bytes, metering, counted_loop_hoisting));
+    group.bench_function(format!("instantiate/{label}"), |b| b.iter(|| burner.call_instantiate(&info)));
+  }
+}
+
+criterion_group!(name = counted_loops; config = make_config(); targets = _0001, _0002, _0003);
+criterion_main!(counted_loops);
@@ -0,0 +1,94 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+#[cfg(target_os = "macos")]
+const MEASUREMENT_TIME: u64 = 5;
+#[cfg(target_os = "linux")]
+const MEASUREMENT_TIME: u64 = 5;
+
+const SAMPLE_SIZE: usize = 20;
+
+/// Contracts used for benchmarking.
+const CONTRACTS: [&str; 3] = ["tests/contracts/burner.wasm", "tests/contracts/big_locals.wat", "tests/contracts/unmetered_call_frame.wasm"];
+
+/// Allocator tracking the peak number of allocated bytes.
+struct PeakAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+    PEAK.fetch_max(allocated, Ordering::Relaxed);
+    unsafe { System.alloc(layout) }
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    unsafe { System.dealloc(ptr, layout) }
+  }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAllocator = PeakAllocator;
+
+fn make_config() -> Criterion {
+  Criterion::default()
+    .without_plots()
+    .measurement_time(Duration::new(MEASUREMENT_TIME, 0))
+    .sample_size(SAMPLE_SIZE)
+    .configure_from_args()
+}
+
+/// Reads the contract, converting text format to binary.
+fn wasm_bytes(path: &str) -> Vec<u8> {
+  wat::parse_file(path).unwrap()
+}
+
+/// Meters the contract by parsing the whole model first and encoding it.
+fn encode_model(wasm_bytes: &[u8]) -> Vec<u8> {
+  let model = wasmarin::Parser::new().parse_wasm_bytes(wasm_bytes).unwrap();
+  wasmarin::Encoder::new_with_metering().encode(model).unwrap()
+}
+
+/// Meters the contract by streaming function bodies one at a time.
+fn encode_streaming(wasm_bytes: &[u8]) -> Vec<u8> {
+  wasmarin::Encoder::new_with_metering().encode_wasm_bytes(&mut wasmarin::Parser::new(), wasm_bytes).unwrap()
+}
+
+/// Returns the peak number of bytes allocated while running the function, above the bytes allocated before.
+fn peak_memory(f: impl FnOnce() -> Vec<u8>) -> usize {
+  let allocated = ALLOCATED.load(Ordering::Relaxed);
+  PEAK.store(allocated, Ordering::Relaxed);
+  drop(f());
+  PEAK.load(Ordering::Relaxed) - allocated
+}
+
+/// Checks if both encodings produce the same output and reports their peak memory.
+fn precheck() {
+  for path in CONTRACTS {
+    let wasm_bytes = wasm_bytes(path);
+    let name = path.rsplit('/').next().unwrap();
+    assert_eq!(encode_model(&wasm_bytes), encode_streaming(&wasm_bytes));
+    println!("encode/model/{name} peak memory: {} bytes", peak_memory(|| encode_model(&wasm_bytes)));
+    println!("encode/streaming/{name} peak memory: {} bytes", peak_memory(|| encode_streaming(&wasm_bytes)));
+  }
+}
+
+/// Metering time of contracts, encoding the parsed model compared with streaming function bodies.
+fn _0001(c: &mut Criterion) {
+  precheck();
+  let mut group = c.benchmark_group("encode");
+  for path in CONTRACTS {
+    let wasm_bytes = wasm_bytes(path);
+    let name = path.rsplit('/').next().unwrap();
+    group.bench_function(format!("model/{name}"), |b| b.iter(|| encode_model(&wasm_bytes)));
+    group.bench_function(format!("streaming/{name}"), |b| b.iter(|| encode_streaming(&wasm_bytes)));
+  }
+}
+
+criterion_group!(name = encode; config = make_config(); targets = _0001);
+criterion_main!(encode);
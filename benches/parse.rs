@@ -0,0 +1,54 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::time::Duration;
+
+#[cfg(target_os = "macos")]
+const MEASUREMENT_TIME: u64 = 5;
+#[cfg(target_os = "linux")]
+const MEASUREMENT_TIME: u64 = 5;
+
+const SAMPLE_SIZE: usize = 20;
+
+/// Contracts used for benchmarking.
+const CONTRACTS: [&str; 3] = ["tests/contracts/burner.wasm", "tests/contracts/big_locals.wat", "tests/contracts/unmetered_call_frame.wasm"];
+
+fn make_config() -> Criterion {
+  Criterion::default()
+    .without_plots()
+    .measurement_time(Duration::new(MEASUREMENT_TIME, 0))
+    .sample_size(SAMPLE_SIZE)
+    .configure_from_args()
+}
+
+/// Reads the contract, converting text format to binary.
+fn wasm_bytes(path: &str) -> Vec<u8> {
+  wat::parse_file(path).unwrap()
+}
+
+/// Parsing time of contracts, compared with validation alone and with validating before parsing.
+///
+/// Parsing decodes and validates every payload, so it costs more than validation alone,
+/// but less than a separate validation pass followed by parsing.
+fn _0001(c: &mut Criterion) {
+  let mut group = c.benchmark_group("parse");
+  for path in CONTRACTS {
+    let wasm_bytes = wasm_bytes(path);
+    let name = path.rsplit('/').next().unwrap();
+    group.bench_function(format!("validate/{name}"), |b| b.iter(|| validate(&wasm_bytes)));
+    group.bench_function(format!("parse/{name}"), |b| b.iter(|| wasmarin::Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()));
+    group.bench_function(format!("validate-then-parse/{name}"), |b| {
+      b.iter(|| {
+        validate(&wasm_bytes);
+        wasmarin::Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()
+      })
+    });
+  }
+}
+
+/// Validates the whole contract in a separate pass.
+fn validate(wasm_bytes: &[u8]) {
+  let mut validator = wasmparser::Validator::new_with_features(wasmarin::Features::new().into());
+  validator.validate_all(wasm_bytes).unwrap();
+}
+
+criterion_group!(name = parse; config = make_config(); targets = _0001);
+criterion_main!(parse);
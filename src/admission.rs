@@ -0,0 +1,242 @@
+//! # Contract admission checks
+
+use crate::cfg::ControlFlowGraph;
+use crate::mappings::{map_operator, map_val_type};
+use crate::{Model, Parser, WasmarinResult};
+use std::fmt;
+
+/// Resource limit checked by [AdmissionPolicy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+  /// Number of defined functions.
+  Functions,
+  /// Number of locals declared in a single function, parameters excluded.
+  LocalsPerFunction,
+  /// Number of locals declared in all functions.
+  TotalLocals,
+  /// Size of a single function body in bytes.
+  FunctionBodySize,
+  /// Size of the whole module in bytes.
+  ModuleSize,
+  /// Initial number of pages of a memory.
+  InitialMemoryPages,
+  /// Maximum number of pages of a memory, memories without maximum may grow up to the limit of the index type.
+  MaximumMemoryPages,
+  /// Maximum number of elements of a table, tables without maximum may grow up to the limit of the index type.
+  TableSize,
+  /// Number of imports.
+  Imports,
+  /// Number of exports.
+  Exports,
+  /// Number of defined globals.
+  Globals,
+  /// Number of targets of a single `br_table` operator.
+  BrTableLength,
+  /// Nesting depth of control structures in a single function.
+  NestingDepth,
+}
+
+impl fmt::Display for Limit {
+  /// Implementation of [Display](fmt::Display) trait for [Limit].
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Self::Functions => "number of functions",
+      Self::LocalsPerFunction => "number of locals",
+      Self::TotalLocals => "total number of locals",
+      Self::FunctionBodySize => "function body size",
+      Self::ModuleSize => "module size",
+      Self::InitialMemoryPages => "initial memory pages",
+      Self::MaximumMemoryPages => "maximum memory pages",
+      Self::TableSize => "table size",
+      Self::Imports => "number of imports",
+      Self::Exports => "number of exports",
+      Self::Globals => "number of globals",
+      Self::BrTableLength => "br_table length",
+      Self::NestingDepth => "control nesting depth",
+    };
+    write!(f, "{name}")
+  }
+}
+
+impl Limit {
+  /// Returns the name of the item the limit applies to.
+  fn item(&self) -> &'static str {
+    match self {
+      Self::LocalsPerFunction | Self::FunctionBodySize | Self::BrTableLength | Self::NestingDepth => "function",
+      Self::InitialMemoryPages | Self::MaximumMemoryPages => "memory",
+      Self::TableSize => "table",
+      _ => "module",
+    }
+  }
+}
+
+/// Violation of a single resource limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+  /// Violated limit.
+  pub limit: Limit,
+  /// Index of the function, memory or table violating the limit, if applicable.
+  pub index: Option<u32>,
+  /// Actual value.
+  pub actual: u64,
+  /// Maximum allowed value.
+  pub allowed: u64,
+}
+
+impl fmt::Display for Violation {
+  /// Implementation of [Display](fmt::Display) trait for [Violation].
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.index {
+      Some(index) => write!(
+        f,
+        "{} of {} {} is {}, maximum allowed is {}",
+        self.limit,
+        self.limit.item(),
+        index,
+        self.actual,
+        self.allowed
+      ),
+      None => write!(f, "{} is {}, maximum allowed is {}", self.limit, self.actual, self.allowed),
+    }
+  }
+}
+
+/// Resource limits a contract must satisfy to be admitted.
+///
+/// Limits set to `None` are not checked.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct AdmissionPolicy {
+  /// Maximum number of defined functions.
+  pub max_functions: Option<u64>,
+  /// Maximum number of locals declared in a single function, parameters excluded.
+  pub max_locals_per_function: Option<u64>,
+  /// Maximum number of locals declared in all functions.
+  pub max_total_locals: Option<u64>,
+  /// Maximum size of a single function body in bytes.
+  pub max_function_body_size: Option<u64>,
+  /// Maximum size of the whole module in bytes.
+  pub max_module_size: Option<u64>,
+  /// Maximum initial number of pages of each memory.
+  pub max_initial_memory_pages: Option<u64>,
+  /// Maximum number of pages each memory may grow to.
+  pub max_memory_pages: Option<u64>,
+  /// Maximum number of elements each table may grow to.
+  pub max_table_size: Option<u64>,
+  /// Maximum number of imports.
+  pub max_imports: Option<u64>,
+  /// Maximum number of exports.
+  pub max_exports: Option<u64>,
+  /// Maximum number of defined globals.
+  pub max_globals: Option<u64>,
+  /// Maximum number of targets of a single `br_table` operator, the default target included.
+  pub max_br_table_length: Option<u64>,
+  /// Maximum nesting depth of control structures, the function body itself has depth 0.
+  pub max_nesting_depth: Option<u64>,
+}
+
+impl AdmissionPolicy {
+  /// Creates a new [AdmissionPolicy] with no limits.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Parses the WASM binary and checks it against all limits, the module size included.
+  ///
+  /// Returns all violations found, an empty list means the contract can be admitted.
+  pub fn check_wasm_bytes(&self, wasm_bytes: &[u8]) -> WasmarinResult<Vec<Violation>> {
+    let model = Parser::new().parse_wasm_bytes(wasm_bytes)?;
+    let mut violations = vec![];
+    check(&mut violations, Limit::ModuleSize, None, wasm_bytes.len() as u64, self.max_module_size);
+    violations.extend(self.check(&model)?);
+    Ok(violations)
+  }
+
+  /// Checks the model against all limits, except the module size.
+  ///
+  /// Returns all violations found, an empty list means the contract can be admitted.
+  /// Returns an error when control structures of a function body are malformed.
+  pub fn check(&self, model: &Model) -> WasmarinResult<Vec<Violation>> {
+    let mut violations = vec![];
+    check(&mut violations, Limit::Functions, None, model.function_indexes.len() as u64, self.max_functions);
+    check(&mut violations, Limit::Imports, None, model.imports.len() as u64, self.max_imports);
+    check(&mut violations, Limit::Exports, None, model.exports.len() as u64, self.max_exports);
+    check(&mut violations, Limit::Globals, None, model.globals.len() as u64, self.max_globals);
+    self.check_memories(model, &mut violations);
+    self.check_tables(model, &mut violations);
+    let imported_function_count = model.imported_function_count();
+    let mut total_locals = 0;
+    for (index, code_section_entry) in model.code_section_entries.iter().enumerate() {
+      let function_index = Some(imported_function_count + index as u32);
+      let locals = code_section_entry.locals.iter().map(|(count, _)| *count as u64).sum();
+      total_locals += locals;
+      check(&mut violations, Limit::LocalsPerFunction, function_index, locals, self.max_locals_per_function);
+      if self.max_function_body_size.is_some() {
+        // Only bodies without the original encoding, like the added or touched ones, are encoded to get their size.
+        let body_size = code_section_entry
+          .original_body
+          .filter(|_| !code_section_entry.touched)
+          .map(<[u8]>::len)
+          .unwrap_or_else(|| {
+            let mut function = wasm_encoder::Function::new(code_section_entry.locals.iter().map(|(count, val_type)| (*count, map_val_type(*val_type))));
+            for operator in &code_section_entry.operators {
+              function.instruction(&map_operator(operator.clone()));
+            }
+            function.byte_len()
+          });
+        check(&mut violations, Limit::FunctionBodySize, function_index, body_size as u64, self.max_function_body_size);
+      }
+      if self.max_nesting_depth.is_some() {
+        let depth = ControlFlowGraph::try_from(code_section_entry)?.max_depth() as u64;
+        check(&mut violations, Limit::NestingDepth, function_index, depth, self.max_nesting_depth);
+      }
+      let max_br_table_length = code_section_entry
+        .operators
+        .iter()
+        .filter_map(|operator| match operator {
+          wasmparser::Operator::BrTable { targets } => Some(targets.len() as u64 + 1),
+          _ => None,
+        })
+        .max();
+      if let Some(length) = max_br_table_length {
+        check(&mut violations, Limit::BrTableLength, function_index, length, self.max_br_table_length);
+      }
+    }
+    check(&mut violations, Limit::TotalLocals, None, total_locals, self.max_total_locals);
+    Ok(violations)
+  }
+
+  /// Checks imported and defined memories, indexed in the memory index space.
+  fn check_memories(&self, model: &Model, violations: &mut Vec<Violation>) {
+    let imported = model.imports.iter().filter_map(|import| match import.ty {
+      wasmparser::TypeRef::Memory(memory_type) => Some(memory_type),
+      _ => None,
+    });
+    for (index, memory_type) in imported.chain(model.memory_types.iter().copied()).enumerate() {
+      let index = Some(index as u32);
+      let limit = if memory_type.memory64 { 1 << 48 } else { 1 << 16 };
+      check(violations, Limit::InitialMemoryPages, index, memory_type.initial, self.max_initial_memory_pages);
+      check(violations, Limit::MaximumMemoryPages, index, memory_type.maximum.unwrap_or(limit), self.max_memory_pages);
+    }
+  }
+
+  /// Checks imported and defined tables, indexed in the table index space.
+  fn check_tables(&self, model: &Model, violations: &mut Vec<Violation>) {
+    let imported = model.imports.iter().filter_map(|import| match import.ty {
+      wasmparser::TypeRef::Table(table_type) => Some(table_type),
+      _ => None,
+    });
+    for (index, table_type) in imported.chain(model.tables.iter().map(|table| table.ty)).enumerate() {
+      let limit = if table_type.table64 { u64::MAX } else { u32::MAX as u64 };
+      check(violations, Limit::TableSize, Some(index as u32), table_type.maximum.unwrap_or(limit), self.max_table_size);
+    }
+  }
+}
+
+/// Records a violation when the actual value exceeds the allowed one.
+fn check(violations: &mut Vec<Violation>, limit: Limit, index: Option<u32>, actual: u64, allowed: Option<u64>) {
+  if let Some(allowed) = allowed {
+    if actual > allowed {
+      violations.push(Violation { limit, index, actual, allowed });
+    }
+  }
+}
@@ -0,0 +1,264 @@
+//! # Call graph of a module
+
+use crate::{ElementItems, Import, Model};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Call graph over the function index space of a module, imported functions included.
+///
+/// Edges are collected from `call`, `return_call`, `call_indirect` and `return_call_indirect` operators.
+/// Targets of indirect calls are resolved conservatively: any function with the matching type
+/// that is referenced from an element segment or by a `ref.func` operator may be called indirectly.
+/// When a table is imported or exported, the host may store any function in it, so every function
+/// with the matching type may be called indirectly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallGraph {
+  /// Number of imported functions, functions with lower indexes are imports.
+  pub imported_function_count: u32,
+  /// Sorted indexes of functions called by each function, indexed by function index.
+  pub callees: Vec<Vec<u32>>,
+}
+
+impl CallGraph {
+  /// Builds the call graph of the module.
+  pub fn new(model: &Model) -> Self {
+    let imported_function_count = model.imported_function_count();
+    let indirect_targets = indirect_call_targets(model);
+    let mut callees = vec![BTreeSet::new(); model.function_count() as usize];
+    for (index, code_section_entry) in model.code_section_entries.iter().enumerate() {
+      let caller = &mut callees[imported_function_count as usize + index];
+      for operator in &code_section_entry.operators {
+        match operator {
+          wasmparser::Operator::Call { function_index } | wasmparser::Operator::ReturnCall { function_index } => {
+            caller.insert(*function_index);
+          }
+          wasmparser::Operator::CallIndirect { type_index, .. } | wasmparser::Operator::ReturnCallIndirect { type_index, .. } => {
+            let func_type = model.func_type(*type_index);
+            caller.extend(indirect_targets.iter().filter(|target| model.function_type(**target) == func_type));
+          }
+          _ => {}
+        }
+      }
+    }
+    Self {
+      imported_function_count,
+      callees: callees.into_iter().map(|callees| callees.into_iter().collect()).collect(),
+    }
+  }
+
+  /// Returns `true` iff the function with the given index is imported.
+  pub fn is_import(&self, function_index: u32) -> bool {
+    function_index < self.imported_function_count
+  }
+
+  /// Returns strongly connected components of the call graph.
+  ///
+  /// Components are returned in reverse topological order, callees before callers,
+  /// and functions in each component are sorted.
+  pub fn strongly_connected_components(&self) -> Vec<Vec<u32>> {
+    Tarjan::new(self).run()
+  }
+
+  /// Returns sorted indexes of functions that may call themselves, directly or through other functions.
+  pub fn recursive_functions(&self) -> Vec<u32> {
+    let mut recursive_functions: Vec<u32> = self
+      .strongly_connected_components()
+      .into_iter()
+      .filter(|component| component.len() > 1 || self.callees[component[0] as usize].contains(&component[0]))
+      .flatten()
+      .collect();
+    recursive_functions.sort_unstable();
+    recursive_functions
+  }
+
+  /// Returns `true` iff any function in the module may call itself, directly or through other functions.
+  pub fn is_recursive(&self) -> bool {
+    !self.recursive_functions().is_empty()
+  }
+
+  /// Returns the maximum number of nested calls starting from each function, indexed by function index.
+  ///
+  /// A function calling no other function has depth 1, the depth of an imported function is 1.
+  /// Returns `None` when the call graph contains recursion.
+  pub fn call_depths(&self) -> Option<Vec<u32>> {
+    if self.is_recursive() {
+      return None;
+    }
+    let mut depths = vec![0; self.callees.len()];
+    // Callees are visited before their callers.
+    for component in self.strongly_connected_components() {
+      let function_index = component[0] as usize;
+      depths[function_index] = 1 + self.callees[function_index].iter().map(|callee| depths[*callee as usize]).max().unwrap_or_default();
+    }
+    Some(depths)
+  }
+
+  /// Returns the maximum static call depth over all functions, `None` when the call graph contains recursion.
+  pub fn max_call_depth(&self) -> Option<u32> {
+    self.call_depths().map(|depths| depths.into_iter().max().unwrap_or_default())
+  }
+
+  /// Returns sorted indexes of all functions reachable from the given function, the function itself excluded
+  /// unless it is recursive.
+  pub fn reachable_functions(&self, function_index: u32) -> Vec<u32> {
+    let mut reachable = BTreeSet::new();
+    let mut pending = self.callees[function_index as usize].clone();
+    while let Some(callee) = pending.pop() {
+      if reachable.insert(callee) {
+        pending.extend(&self.callees[callee as usize]);
+      }
+    }
+    reachable.into_iter().collect()
+  }
+
+  /// Returns sorted indexes of imported functions reachable from the given function.
+  pub fn reachable_imports(&self, function_index: u32) -> Vec<u32> {
+    let mut reachable_imports = self.reachable_functions(function_index);
+    reachable_imports.retain(|callee| self.is_import(*callee));
+    if self.is_import(function_index) {
+      reachable_imports.insert(0, function_index);
+    }
+    reachable_imports
+  }
+
+  /// Returns imports reachable from each exported function, as pairs of module and field names,
+  /// keyed by export name.
+  pub fn reachable_imports_by_export<'a>(&self, model: &'a Model) -> BTreeMap<&'a str, Vec<(&'a str, &'a str)>> {
+    let imported_functions: Vec<&Import> = model.imports.iter().filter(|import| matches!(import.ty, wasmparser::TypeRef::Func(_))).collect();
+    model
+      .exports
+      .iter()
+      .filter(|export| export.kind == wasmparser::ExternalKind::Func)
+      .map(|export| {
+        let imports = self
+          .reachable_imports(export.index)
+          .into_iter()
+          .map(|function_index| {
+            let import = imported_functions[function_index as usize];
+            (import.module.as_str(), import.name.as_str())
+          })
+          .collect();
+        (export.name.as_str(), imports)
+      })
+      .collect()
+  }
+}
+
+/// Returns `true` iff any table is imported or exported, so its content can be modified by the host.
+pub(crate) fn has_host_table(model: &Model) -> bool {
+  model.imports.iter().any(|import| matches!(import.ty, wasmparser::TypeRef::Table(_))) || model.exports.iter().any(|export| export.kind == wasmparser::ExternalKind::Table)
+}
+
+/// Returns sorted indexes of functions that may be called indirectly.
+pub(crate) fn indirect_call_targets(model: &Model) -> Vec<u32> {
+  if has_host_table(model) {
+    return (0..model.function_count()).collect();
+  }
+  let mut targets = BTreeSet::new();
+  for element in &model.elements {
+    match &element.items {
+      ElementItems::Functions(function_indexes) => {
+        targets.extend(function_indexes);
+      }
+      ElementItems::Expressions(_, const_exprs) => {
+        for const_expr in const_exprs {
+          targets.extend(const_expr.operators().unwrap_or_default().into_iter().filter_map(|operator| match operator {
+            wasmparser::Operator::RefFunc { function_index } => Some(function_index),
+            _ => None,
+          }));
+        }
+      }
+    }
+  }
+  for code_section_entry in &model.code_section_entries {
+    targets.extend(code_section_entry.operators.iter().filter_map(|operator| match operator {
+      wasmparser::Operator::RefFunc { function_index } => Some(*function_index),
+      _ => None,
+    }));
+  }
+  targets.into_iter().collect()
+}
+
+/// Tarjan's algorithm for strongly connected components, iterative to avoid stack overflows on deep call chains.
+struct Tarjan<'a> {
+  graph: &'a CallGraph,
+  index: Vec<Option<u32>>,
+  low_link: Vec<u32>,
+  on_stack: Vec<bool>,
+  stack: Vec<u32>,
+  next_index: u32,
+  components: Vec<Vec<u32>>,
+}
+
+impl<'a> Tarjan<'a> {
+  fn new(graph: &'a CallGraph) -> Self {
+    let count = graph.callees.len();
+    Self {
+      graph,
+      index: vec![None; count],
+      low_link: vec![0; count],
+      on_stack: vec![false; count],
+      stack: vec![],
+      next_index: 0,
+      components: vec![],
+    }
+  }
+
+  fn run(mut self) -> Vec<Vec<u32>> {
+    for function_index in 0..self.graph.callees.len() as u32 {
+      if self.index[function_index as usize].is_none() {
+        self.visit(function_index);
+      }
+    }
+    self.components
+  }
+
+  /// Visits the function and all functions reachable from it.
+  fn visit(&mut self, root: u32) {
+    // Each entry holds the visited function and the position of the next callee to be visited.
+    let mut work = vec![(root, 0)];
+    self.open(root);
+    while let Some((function_index, position)) = work.last_mut() {
+      let function_index = *function_index as usize;
+      if let Some(callee) = self.graph.callees[function_index].get(*position).copied() {
+        *position += 1;
+        match self.index[callee as usize] {
+          None => {
+            self.open(callee);
+            work.push((callee, 0));
+          }
+          Some(callee_index) if self.on_stack[callee as usize] => {
+            self.low_link[function_index] = self.low_link[function_index].min(callee_index);
+          }
+          _ => {}
+        }
+        continue;
+      }
+      work.pop();
+      if let Some((caller, _)) = work.last() {
+        let caller = *caller as usize;
+        self.low_link[caller] = self.low_link[caller].min(self.low_link[function_index]);
+      }
+      if Some(self.low_link[function_index]) == self.index[function_index] {
+        let mut component = vec![];
+        while let Some(member) = self.stack.pop() {
+          self.on_stack[member as usize] = false;
+          component.push(member);
+          if member as usize == function_index {
+            break;
+          }
+        }
+        component.sort_unstable();
+        self.components.push(component);
+      }
+    }
+  }
+
+  /// Assigns the next index to the function and pushes it onto the stack.
+  fn open(&mut self, function_index: u32) {
+    self.index[function_index as usize] = Some(self.next_index);
+    self.low_link[function_index as usize] = self.next_index;
+    self.next_index += 1;
+    self.stack.push(function_index);
+    self.on_stack[function_index as usize] = true;
+  }
+}
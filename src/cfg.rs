@@ -0,0 +1,575 @@
+//! # Control-flow graph of a function
+//!
+//! Splits the operators of a function body into basic blocks connected with control-flow edges,
+//! and records the nesting of structured control operators (`block`, `loop`, `if`, `try`, `try_table`).
+//! Branches (including `br_table`), legacy exception handling (`try`, `catch`, `delegate`)
+//! and `try_table` handlers are all reflected in the graph.
+
+use crate::{CodeSectionEntry, WasmarinError, WasmarinResult};
+use std::fmt::Write;
+use std::ops::Range;
+use wasmparser::{Catch, Operator};
+
+/// Kind of the control-flow edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+  /// Control falls through to the next block, also when a conditional branch is not taken.
+  Fallthrough,
+  /// Control is transferred by a branch, `if` or `else`.
+  Branch,
+  /// Control is transferred to an exception handler.
+  Exception,
+}
+
+/// Control-flow edge between two basic blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+  /// Index of the target basic block.
+  pub target: usize,
+  /// Kind of the edge.
+  pub kind: EdgeKind,
+}
+
+/// Basic block, a maximal sequence of operators executed from the beginning to the end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+  /// Range of operators belonging to this block.
+  pub operators: Range<usize>,
+  /// Outgoing edges.
+  pub successors: Vec<Edge>,
+  /// Indexes of blocks with edges leading to this block.
+  pub predecessors: Vec<usize>,
+  /// Index of the header block of the innermost loop containing this block.
+  pub innermost_loop: Option<usize>,
+  /// Index of the innermost control structure containing this block.
+  pub structure: usize,
+  /// Flag indicating if this block can be reached from the entry block.
+  pub reachable: bool,
+}
+
+/// Kind of the structured control operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructureKind {
+  /// Function body.
+  Function,
+  /// `block` operator.
+  Block,
+  /// `loop` operator.
+  Loop,
+  /// `if` operator, including its `else` arm.
+  If,
+  /// Legacy `try` operator, including its handlers.
+  Try,
+  /// `try_table` operator.
+  TryTable,
+}
+
+/// Control structure opened by a structured control operator and closed by its `end` (or `delegate`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlStructure {
+  /// Kind of the structure.
+  pub kind: StructureKind,
+  /// Range of operators from the opening operator up to and including the closing one.
+  pub operators: Range<usize>,
+  /// Index of the enclosing structure, `None` for the function body.
+  pub parent: Option<usize>,
+  /// Nesting depth, the function body has depth 0.
+  pub depth: usize,
+}
+
+/// Control-flow graph built from the operators of a single function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+  /// Basic blocks in the order of operators, the first block is the entry block.
+  pub blocks: Vec<BasicBlock>,
+  /// Control structures in the order of their opening operators, the first one is the function body.
+  pub structures: Vec<ControlStructure>,
+}
+
+/// Kind of the control frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+  Function,
+  Block,
+  Loop,
+  If,
+  Else,
+  Try,
+  Catch,
+  TryTable,
+}
+
+/// Control frame opened by a structured control operator.
+struct Frame {
+  /// Kind of the frame.
+  kind: FrameKind,
+  /// Header block, for loops only.
+  header: Option<usize>,
+  /// Block ending with `if`, waiting for its `else` or `end`.
+  condition: Option<usize>,
+  /// Blocks branching to the end of this frame.
+  branches: Vec<(usize, EdgeKind)>,
+  /// Blocks that may throw inside the body of a `try`, waiting for handlers.
+  throwing: Vec<usize>,
+  /// Labels of `catch` clauses of a `try_table`.
+  catches: Vec<Catch>,
+  /// Header block of the innermost loop containing this frame (including itself).
+  innermost_loop: Option<usize>,
+  /// Index of the control structure opened by this frame.
+  structure: usize,
+}
+
+impl Frame {
+  fn new(kind: FrameKind, innermost_loop: Option<usize>) -> Self {
+    Self {
+      kind,
+      header: None,
+      condition: None,
+      branches: vec![],
+      throwing: vec![],
+      catches: vec![],
+      innermost_loop,
+      structure: 0,
+    }
+  }
+}
+
+/// Builder of the control-flow graph.
+struct Builder {
+  blocks: Vec<BasicBlock>,
+  structures: Vec<ControlStructure>,
+  frames: Vec<Frame>,
+  /// Index of the block currently being filled with operators.
+  current: usize,
+}
+
+impl ControlFlowGraph {
+  /// Builds the control-flow graph from the operators of a function body.
+  ///
+  /// Returns an error when control structures are not properly nested and closed by the final `end` operator,
+  /// or when a branch targets a label out of scope.
+  pub fn new(operators: &[Operator]) -> WasmarinResult<Self> {
+    check_structure(operators)?;
+    let mut builder = Builder {
+      blocks: vec![],
+      structures: vec![ControlStructure {
+        kind: StructureKind::Function,
+        operators: 0..operators.len(),
+        parent: None,
+        depth: 0,
+      }],
+      frames: vec![Frame::new(FrameKind::Function, None)],
+      current: 0,
+    };
+    builder.current = builder.new_block(0);
+    for (position, operator) in operators.iter().enumerate() {
+      builder.visit(position, operator);
+    }
+    // Remove the trailing empty block opened after the final `end` operator.
+    if builder.blocks.len() > 1 && builder.blocks[builder.current].operators.is_empty() && builder.blocks[builder.current].predecessors.is_empty() {
+      builder.blocks.pop();
+    }
+    // Mark blocks reachable from the entry block.
+    let mut pending = vec![0];
+    while let Some(index) = pending.pop() {
+      if !builder.blocks[index].reachable {
+        builder.blocks[index].reachable = true;
+        pending.extend(builder.blocks[index].successors.iter().map(|edge| edge.target));
+      }
+    }
+    Ok(Self {
+      blocks: builder.blocks,
+      structures: builder.structures,
+    })
+  }
+
+  /// Returns `true` iff the given block is the header of a loop.
+  pub fn is_loop_header(&self, block: usize) -> bool {
+    self.blocks[block].innermost_loop == Some(block)
+  }
+
+  /// Returns indexes of all loop header blocks.
+  pub fn loop_headers(&self) -> Vec<usize> {
+    (0..self.blocks.len()).filter(|block| self.is_loop_header(*block)).collect()
+  }
+
+  /// Returns `true` iff the given block can be reached from the entry block.
+  ///
+  /// Blocks following unconditional control transfers (`br`, `return`, `unreachable`, ...)
+  /// that are not targeted by any branch are never executed.
+  pub fn is_reachable(&self, block: usize) -> bool {
+    self.blocks[block].reachable
+  }
+
+  /// Returns the maximum nesting depth of control structures, the function body has depth 0.
+  pub fn max_depth(&self) -> usize {
+    self.structures.iter().map(|structure| structure.depth).max().unwrap_or_default()
+  }
+
+  /// Renders the graph in Graphviz DOT format.
+  ///
+  /// Each node shows the block index and its range of operators, loop headers are drawn as double boxes,
+  /// unreachable blocks are grayed out. Branch edges are solid, fallthrough edges are dashed and
+  /// exception edges are dotted.
+  pub fn to_dot(&self) -> String {
+    let mut dot = String::new();
+    let _ = writeln!(dot, "digraph cfg {{");
+    let _ = writeln!(dot, "  node [shape=box];");
+    for (index, block) in self.blocks.iter().enumerate() {
+      let mut attributes = format!("label=\"b{}\\n{}..{}\"", index, block.operators.start, block.operators.end);
+      if self.is_loop_header(index) {
+        attributes.push_str(", peripheries=2");
+      }
+      if !self.is_reachable(index) {
+        attributes.push_str(", color=gray, fontcolor=gray");
+      }
+      let _ = writeln!(dot, "  b{index} [{attributes}];");
+    }
+    for (index, block) in self.blocks.iter().enumerate() {
+      for edge in &block.successors {
+        let style = match edge.kind {
+          EdgeKind::Fallthrough => "dashed",
+          EdgeKind::Branch => "solid",
+          EdgeKind::Exception => "dotted",
+        };
+        let _ = writeln!(dot, "  b{} -> b{} [style={}];", index, edge.target, style);
+      }
+    }
+    let _ = writeln!(dot, "}}");
+    dot
+  }
+}
+
+impl TryFrom<&CodeSectionEntry<'_>> for ControlFlowGraph {
+  type Error = WasmarinError;
+
+  /// Builds the control-flow graph of the function body.
+  fn try_from(code_section_entry: &CodeSectionEntry) -> WasmarinResult<Self> {
+    Self::new(&code_section_entry.operators)
+  }
+}
+
+/// Checks that control structures of the function body are properly nested and closed,
+/// and that all branches target labels in scope, as required for building the control-flow graph.
+pub(crate) fn check_structure(operators: &[Operator]) -> WasmarinResult<()> {
+  let mut frames = vec![FrameKind::Function];
+  for (position, operator) in operators.iter().enumerate() {
+    if frames.is_empty() {
+      return Err(WasmarinError::new(format!("operator at position {position} follows the end of the function body")));
+    }
+    let depth = frames.len();
+    let check_label = |relative_depth: u32| {
+      if (relative_depth as usize) < depth {
+        Ok(())
+      } else {
+        Err(WasmarinError::new(format!("unknown label {relative_depth} at position {position}")))
+      }
+    };
+    let mismatched = |name: &str| {
+      Err(WasmarinError::new(format!(
+        "`{name}` at position {position} does not match the enclosing control structure"
+      )))
+    };
+    match operator {
+      Operator::Block { .. } => frames.push(FrameKind::Block),
+      Operator::Loop { .. } => frames.push(FrameKind::Loop),
+      Operator::If { .. } => frames.push(FrameKind::If),
+      Operator::Try { .. } => frames.push(FrameKind::Try),
+      Operator::TryTable { try_table } => {
+        for catch in &try_table.catches {
+          let (Catch::One { label, .. } | Catch::OneRef { label, .. } | Catch::All { label } | Catch::AllRef { label }) = catch;
+          check_label(*label)?;
+        }
+        frames.push(FrameKind::TryTable);
+      }
+      Operator::Else => match frames.last_mut() {
+        Some(kind @ FrameKind::If) => *kind = FrameKind::Else,
+        _ => return mismatched("else"),
+      },
+      Operator::Catch { .. } | Operator::CatchAll => match frames.last_mut() {
+        Some(kind @ (FrameKind::Try | FrameKind::Catch)) => *kind = FrameKind::Catch,
+        _ => return mismatched(if matches!(operator, Operator::Catch { .. }) { "catch" } else { "catch_all" }),
+      },
+      Operator::Delegate { relative_depth } => {
+        if frames.last() != Some(&FrameKind::Try) {
+          return mismatched("delegate");
+        }
+        frames.pop();
+        if *relative_depth as usize >= frames.len() {
+          return Err(WasmarinError::new(format!("unknown label {relative_depth} at position {position}")));
+        }
+      }
+      Operator::End => {
+        frames.pop();
+      }
+      Operator::Br { relative_depth }
+      | Operator::BrIf { relative_depth }
+      | Operator::BrOnNull { relative_depth }
+      | Operator::BrOnNonNull { relative_depth }
+      | Operator::BrOnCast { relative_depth, .. }
+      | Operator::BrOnCastFail { relative_depth, .. }
+      | Operator::Rethrow { relative_depth } => check_label(*relative_depth)?,
+      Operator::BrTable { targets } => {
+        for relative_depth in targets.targets() {
+          check_label(relative_depth.map_err(|e| WasmarinError::new(e.to_string()))?)?;
+        }
+        check_label(targets.default())?;
+      }
+      _ => {}
+    }
+  }
+  if frames.is_empty() {
+    Ok(())
+  } else {
+    Err(WasmarinError::new("function body is not terminated with the `end` operator"))
+  }
+}
+
+impl Builder {
+  /// Opens a new block starting at the given position.
+  fn new_block(&mut self, start: usize) -> usize {
+    let frame = self.frames.last();
+    self.blocks.push(BasicBlock {
+      operators: start..start,
+      successors: vec![],
+      predecessors: vec![],
+      innermost_loop: frame.and_then(|frame| frame.innermost_loop),
+      structure: frame.map(|frame| frame.structure).unwrap_or_default(),
+      reachable: false,
+    });
+    self.blocks.len() - 1
+  }
+
+  /// Adds an edge between two blocks.
+  fn edge(&mut self, source: usize, target: usize, kind: EdgeKind) {
+    let edge = Edge { target, kind };
+    if !self.blocks[source].successors.contains(&edge) {
+      self.blocks[source].successors.push(edge);
+      self.blocks[target].predecessors.push(source);
+    }
+  }
+
+  /// Closes the current block after the operator at the given position, and opens the next one.
+  ///
+  /// When `fallthrough` is `true`, control flows from the closed block to the opened one.
+  fn split(&mut self, position: usize, fallthrough: bool) -> usize {
+    let closed = self.current;
+    self.blocks[closed].operators.end = position + 1;
+    self.current = self.new_block(position + 1);
+    if fallthrough {
+      self.edge(closed, self.current, EdgeKind::Fallthrough);
+    }
+    closed
+  }
+
+  /// Records a branch from the given block to the label at the given relative depth.
+  fn branch(&mut self, source: usize, relative_depth: u32, kind: EdgeKind) {
+    let index = self.frames.len() - 1 - relative_depth as usize;
+    self.branch_to_frame(source, index, kind);
+  }
+
+  /// Records a branch from the given block to the label of the frame at the given index.
+  fn branch_to_frame(&mut self, source: usize, index: usize, kind: EdgeKind) {
+    match self.frames[index].header {
+      Some(header) => self.edge(source, header, kind),
+      None => self.frames[index].branches.push((source, kind)),
+    }
+  }
+
+  /// Records that the given block may throw an exception, starting the search for handlers at the frame with the given index.
+  fn throw(&mut self, source: usize, mut index: usize) {
+    loop {
+      match self.frames[index].kind {
+        FrameKind::Function => return,
+        FrameKind::Try => {
+          // Handlers of the `try` are not known yet, they are connected when reached,
+          // and exceptions not caught by any of them are propagated at the end of the `try`.
+          self.frames[index].throwing.push(source);
+          return;
+        }
+        FrameKind::TryTable => {
+          let mut catches_all = false;
+          for catch in self.frames[index].catches.clone() {
+            let label = match catch {
+              Catch::One { label, .. } | Catch::OneRef { label, .. } => label,
+              Catch::All { label } | Catch::AllRef { label } => {
+                catches_all = true;
+                label
+              }
+            };
+            // Labels of catch clauses are relative to the frame enclosing the `try_table`.
+            self.branch_to_frame(source, index - 1 - label as usize, EdgeKind::Exception);
+          }
+          if catches_all {
+            return;
+          }
+        }
+        _ => {}
+      }
+      index -= 1;
+    }
+  }
+
+  /// Opens a control frame together with its control structure starting at the given position.
+  ///
+  /// Returns the index of the opened control structure.
+  fn push(&mut self, mut frame: Frame, position: usize) -> usize {
+    let parent = self.frames.last().map(|frame| frame.structure);
+    let kind = match frame.kind {
+      FrameKind::Function => StructureKind::Function,
+      FrameKind::Block => StructureKind::Block,
+      FrameKind::Loop => StructureKind::Loop,
+      FrameKind::If | FrameKind::Else => StructureKind::If,
+      FrameKind::Try | FrameKind::Catch => StructureKind::Try,
+      FrameKind::TryTable => StructureKind::TryTable,
+    };
+    self.structures.push(ControlStructure {
+      kind,
+      operators: position..position + 1,
+      parent,
+      depth: self.frames.len(),
+    });
+    frame.structure = self.structures.len() - 1;
+    self.frames.push(frame);
+    self.structures.len() - 1
+  }
+
+  /// Closes the control frame at the given position.
+  fn pop(&mut self, position: usize) -> Frame {
+    let frame = self.frames.pop().unwrap();
+    self.structures[frame.structure].operators.end = position + 1;
+    frame
+  }
+
+  /// Visits a single operator.
+  fn visit(&mut self, position: usize, operator: &Operator) {
+    let innermost_loop = self.blocks[self.current].innermost_loop;
+    match operator {
+      Operator::Block { .. } => {
+        self.push(Frame::new(FrameKind::Block, innermost_loop), position);
+      }
+      Operator::Loop { .. } => {
+        let closed = self.split(position, false);
+        let header = self.current;
+        self.edge(closed, header, EdgeKind::Fallthrough);
+        let mut frame = Frame::new(FrameKind::Loop, Some(header));
+        frame.header = Some(header);
+        self.blocks[header].innermost_loop = Some(header);
+        self.blocks[header].structure = self.push(frame, position);
+      }
+      Operator::If { .. } => {
+        let condition = self.split(position, false);
+        self.edge(condition, self.current, EdgeKind::Branch);
+        let mut frame = Frame::new(FrameKind::If, innermost_loop);
+        frame.condition = Some(condition);
+        let current = self.current;
+        self.blocks[current].structure = self.push(frame, position);
+      }
+      Operator::Else => {
+        let closed = self.split(position, false);
+        let frame = self.frames.last_mut().unwrap();
+        frame.kind = FrameKind::Else;
+        frame.branches.push((closed, EdgeKind::Fallthrough));
+        if let Some(condition) = frame.condition.take() {
+          self.edge(condition, self.current, EdgeKind::Branch);
+        }
+      }
+      Operator::Try { .. } => {
+        self.push(Frame::new(FrameKind::Try, innermost_loop), position);
+      }
+      Operator::Catch { .. } | Operator::CatchAll => {
+        // Ends the body of the `try` or the previous handler, and starts the next handler.
+        let closed = self.split(position, false);
+        let handler = self.current;
+        let frame = self.frames.last_mut().unwrap();
+        frame.kind = FrameKind::Catch;
+        frame.branches.push((closed, EdgeKind::Fallthrough));
+        let throwing = if matches!(operator, Operator::CatchAll) {
+          // All exceptions are caught, none of them is propagated to the outer handlers.
+          std::mem::take(&mut frame.throwing)
+        } else {
+          frame.throwing.clone()
+        };
+        for source in throwing {
+          self.edge(source, handler, EdgeKind::Exception);
+        }
+      }
+      Operator::TryTable { try_table } => {
+        let mut frame = Frame::new(FrameKind::TryTable, innermost_loop);
+        frame.catches = try_table.catches.clone();
+        self.push(frame, position);
+      }
+      Operator::Delegate { relative_depth } => {
+        // Ends the `try` block, exceptions from its body are delegated to the outer label.
+        let frame = self.pop(position);
+        self.split(position, true);
+        for (source, kind) in frame.branches {
+          self.edge(source, self.current, kind);
+        }
+        let index = self.frames.len() - 1 - *relative_depth as usize;
+        for source in frame.throwing {
+          self.throw(source, index);
+        }
+      }
+      Operator::End => {
+        let frame = self.pop(position);
+        if frame.kind == FrameKind::Function {
+          self.blocks[self.current].operators.end = position + 1;
+          self.current = self.new_block(position + 1);
+          return;
+        }
+        self.split(position, true);
+        for (source, kind) in frame.branches {
+          self.edge(source, self.current, kind);
+        }
+        if let Some(condition) = frame.condition {
+          // `if` without `else`, the condition may branch directly to the end.
+          self.edge(condition, self.current, EdgeKind::Branch);
+        }
+        if matches!(frame.kind, FrameKind::Try | FrameKind::Catch) {
+          // Exceptions not caught by this `try` are propagated to the outer handlers.
+          let index = self.frames.len() - 1;
+          for source in frame.throwing {
+            self.throw(source, index);
+          }
+        }
+      }
+      Operator::Br { relative_depth } => {
+        let closed = self.split(position, false);
+        self.branch(closed, *relative_depth, EdgeKind::Branch);
+      }
+      Operator::BrIf { relative_depth }
+      | Operator::BrOnNull { relative_depth }
+      | Operator::BrOnNonNull { relative_depth }
+      | Operator::BrOnCast { relative_depth, .. }
+      | Operator::BrOnCastFail { relative_depth, .. } => {
+        let closed = self.split(position, true);
+        self.branch(closed, *relative_depth, EdgeKind::Branch);
+      }
+      Operator::BrTable { targets } => {
+        let closed = self.split(position, false);
+        let mut relative_depths: Vec<u32> = targets.targets().filter_map(Result::ok).collect();
+        relative_depths.push(targets.default());
+        for relative_depth in relative_depths {
+          self.branch(closed, relative_depth, EdgeKind::Branch);
+        }
+      }
+      Operator::Return | Operator::Unreachable | Operator::ReturnCall { .. } | Operator::ReturnCallIndirect { .. } | Operator::ReturnCallRef { .. } => {
+        self.split(position, false);
+      }
+      Operator::Throw { .. } | Operator::ThrowRef | Operator::Rethrow { .. } => {
+        let closed = self.split(position, false);
+        self.throw(closed, self.frames.len() - 1);
+      }
+      Operator::Call { .. } | Operator::CallIndirect { .. } | Operator::CallRef { .. } => {
+        // The called function may throw an exception.
+        self.throw(self.current, self.frames.len() - 1);
+      }
+      _ => {}
+    }
+    let current = self.current;
+    if self.blocks[current].operators.end <= position {
+      self.blocks[current].operators.end = position + 1;
+    }
+  }
+}
@@ -0,0 +1,261 @@
+//! # Recognition of loops with statically known trip count
+
+use wasmparser::{BlockType, Operator};
+
+/// Maximum number of iterations simulated while calculating the trip count of a loop.
+///
+/// Loops running longer than that are not recognized as counted loops.
+const MAX_TRIP_COUNT: u64 = 1_000_000;
+
+/// Loop with statically known trip count.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountedLoop {
+  /// Position of the `end` operator closing the loop.
+  pub end: usize,
+  /// Number of times the loop body is executed.
+  pub trip_count: u64,
+}
+
+/// Arithmetic operation updating the loop counter.
+#[derive(Debug, Clone, Copy)]
+enum Step {
+  Add(i32),
+  Sub(i32),
+}
+
+/// Condition checked by the `br_if` operator branching back to the loop header.
+#[derive(Debug, Clone, Copy)]
+struct Condition<'o, 'a> {
+  /// Comparison of the counter with the limit, `None` when the loop continues while the counter is not zero.
+  comparison: Option<&'o Operator<'a>>,
+  /// Constant the counter is compared with.
+  limit: i32,
+  /// Flag indicating the limit is the left operand of the comparison.
+  reversed: bool,
+}
+
+/// Recognizes a loop with statically known trip count starting at the given position.
+///
+/// Recognition is deliberately conservative, only the following shape of a loop is accepted:
+///
+/// ```text
+/// i32.const <init>
+/// local.set <counter>
+/// ;; straight-line code, no control operators, no writes to <counter>
+/// loop
+///   ;; straight-line code, no control operators, no writes to <counter>
+///   i32.const <limit>              ;; optional, the limit pushed before the update
+///   local.get <counter>
+///   i32.const <step>
+///   i32.add | i32.sub
+///   local.tee <counter>            ;; or: local.set <counter> local.get <counter>
+///   i32.const <limit>              ;; optional, the limit pushed after the update
+///   i32.ne | i32.lt_s | ...        ;; optional, together with the limit
+///   br_if 0
+/// end
+/// ```
+///
+/// Both orders of operands of the comparison are accepted, as emitted by compilers. Only loops with
+/// constant initial value and limit of the counter are recognized, loops of real contracts mostly
+/// iterate up to values known at runtime, so they are charged on every iteration.
+///
+/// The trip count is calculated by simulating the counter with the exact WebAssembly semantics,
+/// so any loop that can not be proven to terminate within [MAX_TRIP_COUNT] iterations is rejected.
+pub fn counted_loop(operators: &[Operator], position: usize) -> Option<CountedLoop> {
+  let Some(Operator::Loop { blockty: BlockType::Empty }) = operators.get(position) else {
+    return None;
+  };
+  // Straight-line loop body is closed by the first `end` operator.
+  let end = position + 1 + operators[position + 1..].iter().position(|operator| matches!(operator, Operator::End))?;
+  let Some(Operator::BrIf { relative_depth: 0 }) = operators.get(end - 1) else {
+    return None;
+  };
+  let tail = &operators[position + 1..end - 1];
+  let (length, counter, step, condition) = back_edge(tail)?;
+  // The rest of the loop body must be straight-line code that does not touch the counter.
+  if !tail[..tail.len() - length].iter().all(|operator| is_straight_line_operator(operator, counter)) {
+    return None;
+  }
+  let init = init(&operators[..position], counter)?;
+  let trip_count = trip_count(init, step, condition)?;
+  Some(CountedLoop { end, trip_count })
+}
+
+/// Returns the constant initial value of the counter, set by straight-line code right before the loop.
+///
+/// The loop can be entered only by falling through from the preceding operators, so the value is known
+/// when no control operator nor write to the counter follows the initialization.
+fn init(preceding: &[Operator], counter: u32) -> Option<i32> {
+  for (index, operator) in preceding.iter().enumerate().rev() {
+    if let Operator::LocalSet { local_index } = operator {
+      if *local_index == counter {
+        return match index.checked_sub(1).map(|index| &preceding[index]) {
+          Some(Operator::I32Const { value }) => Some(*value),
+          _ => None,
+        };
+      }
+    }
+    if !is_straight_line_operator(operator, counter) {
+      return None;
+    }
+  }
+  None
+}
+
+/// Recognizes the update of the loop counter followed by the condition at the end of the loop body, `br_if` excluded.
+///
+/// Returns the number of recognized operators, the counter, its step and the condition.
+fn back_edge<'o, 'a>(tail: &'o [Operator<'a>]) -> Option<(usize, u32, Step, Condition<'o, 'a>)> {
+  let [rest @ .., comparison] = tail else {
+    return None;
+  };
+  if !is_comparison(comparison) {
+    let (length, counter, step) = update(tail)?;
+    let condition = Condition {
+      comparison: None,
+      limit: 0,
+      reversed: false,
+    };
+    return Some((length, counter, step, condition));
+  }
+  // The limit pushed after the update.
+  if let [update_operators @ .., Operator::I32Const { value: limit }] = rest {
+    if let Some((length, counter, step)) = update(update_operators) {
+      let condition = Condition {
+        comparison: Some(comparison),
+        limit: *limit,
+        reversed: false,
+      };
+      return Some((length + 2, counter, step, condition));
+    }
+  }
+  // The limit pushed before the update.
+  let (length, counter, step) = update(rest)?;
+  let Some(Operator::I32Const { value: limit }) = rest[..rest.len() - length].last() else {
+    return None;
+  };
+  let condition = Condition {
+    comparison: Some(comparison),
+    limit: *limit,
+    reversed: true,
+  };
+  Some((length + 2, counter, step, condition))
+}
+
+/// Recognizes the update of the loop counter at the end of the operators, leaving the new value on the stack.
+///
+/// Returns the number of recognized operators, the counter and its step.
+fn update(operators: &[Operator]) -> Option<(usize, u32, Step)> {
+  match operators {
+    [.., Operator::LocalGet { local_index: l1 }, Operator::I32Const { value }, step, Operator::LocalTee { local_index: l2 }] if l1 == l2 => Some((4, *l1, step_of(step, *value)?)),
+    [.., Operator::LocalGet { local_index: l1 }, Operator::I32Const { value }, step, Operator::LocalSet { local_index: l2 }, Operator::LocalGet { local_index: l3 }]
+      if l1 == l2 && l2 == l3 =>
+    {
+      Some((5, *l1, step_of(step, *value)?))
+    }
+    _ => None,
+  }
+}
+
+/// Simulates the loop counter and returns the number of executions of the loop body.
+fn trip_count(init: i32, step: Step, condition: Condition) -> Option<u64> {
+  let mut counter = init;
+  let mut trip_count = 0;
+  while trip_count < MAX_TRIP_COUNT {
+    trip_count += 1;
+    counter = match step {
+      Step::Add(value) => counter.wrapping_add(value),
+      Step::Sub(value) => counter.wrapping_sub(value),
+    };
+    let taken = match condition.comparison {
+      Some(comparison) if condition.reversed => compare(comparison, condition.limit, counter),
+      Some(comparison) => compare(comparison, counter, condition.limit),
+      None => counter != 0,
+    };
+    if !taken {
+      return Some(trip_count);
+    }
+  }
+  None
+}
+
+/// Returns the step of the loop counter update.
+fn step_of(operator: &Operator, value: i32) -> Option<Step> {
+  match operator {
+    Operator::I32Add => Some(Step::Add(value)),
+    Operator::I32Sub => Some(Step::Sub(value)),
+    _ => None,
+  }
+}
+
+/// Returns `true` iff the given operator is a supported `i32` comparison.
+fn is_comparison(operator: &Operator) -> bool {
+  matches!(
+    operator,
+    Operator::I32Eq
+      | Operator::I32Ne
+      | Operator::I32LtS
+      | Operator::I32LtU
+      | Operator::I32GtS
+      | Operator::I32GtU
+      | Operator::I32LeS
+      | Operator::I32LeU
+      | Operator::I32GeS
+      | Operator::I32GeU
+  )
+}
+
+/// Evaluates the comparison exactly like WebAssembly does.
+fn compare(operator: &Operator, lhs: i32, rhs: i32) -> bool {
+  match operator {
+    Operator::I32Eq => lhs == rhs,
+    Operator::I32Ne => lhs != rhs,
+    Operator::I32LtS => lhs < rhs,
+    Operator::I32LtU => (lhs as u32) < (rhs as u32),
+    Operator::I32GtS => lhs > rhs,
+    Operator::I32GtU => (lhs as u32) > (rhs as u32),
+    Operator::I32LeS => lhs <= rhs,
+    Operator::I32LeU => (lhs as u32) <= (rhs as u32),
+    Operator::I32GeS => lhs >= rhs,
+    Operator::I32GeU => (lhs as u32) >= (rhs as u32),
+    _ => false,
+  }
+}
+
+/// Returns `true` iff the given operator may be placed in the body of a counted loop.
+///
+/// Control operators (including calls) are rejected, so the body is always executed
+/// from the beginning to the end, and so are writes to the loop counter.
+fn is_straight_line_operator(operator: &Operator, counter: u32) -> bool {
+  match operator {
+    Operator::LocalSet { local_index } | Operator::LocalTee { local_index } => *local_index != counter,
+    Operator::Block { .. }
+    | Operator::Loop { .. }
+    | Operator::If { .. }
+    | Operator::Else
+    | Operator::End
+    | Operator::Br { .. }
+    | Operator::BrIf { .. }
+    | Operator::BrTable { .. }
+    | Operator::Return
+    | Operator::Call { .. }
+    | Operator::CallIndirect { .. }
+    | Operator::ReturnCall { .. }
+    | Operator::ReturnCallIndirect { .. }
+    | Operator::CallRef { .. }
+    | Operator::ReturnCallRef { .. }
+    | Operator::Try { .. }
+    | Operator::TryTable { .. }
+    | Operator::Catch { .. }
+    | Operator::CatchAll
+    | Operator::Throw { .. }
+    | Operator::ThrowRef
+    | Operator::Rethrow { .. }
+    | Operator::Delegate { .. }
+    | Operator::BrOnNull { .. }
+    | Operator::BrOnNonNull { .. }
+    | Operator::BrOnCast { .. }
+    | Operator::BrOnCastFail { .. } => false,
+    _ => true,
+  }
+}
@@ -0,0 +1,80 @@
+//! # Filtering of custom sections
+
+use crate::{CustomSection, Model, METERING_SECTION_NAME, NAME_SECTION_NAME};
+use std::collections::BTreeSet;
+use wasm_encoder::Encode;
+
+/// Custom sections stripped by [CustomSectionFilter].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StrippedSections {
+  /// Names of stripped sections paired with their encoded sizes in bytes, section headers included.
+  pub sections: Vec<(String, usize)>,
+}
+
+impl StrippedSections {
+  /// Returns the number of bytes saved by stripping the sections.
+  pub fn bytes_saved(&self) -> usize {
+    self.sections.iter().map(|(_, size)| size).sum()
+  }
+}
+
+/// Policy keeping only custom sections with allowed names.
+///
+/// The metering section is never stripped, it is kept in [Model::metering] and encoded regardless of the allowlist.
+/// The name section, kept in [Model::names], is stripped unless [NAME_SECTION_NAME] is allowed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CustomSectionFilter {
+  /// Names of custom sections kept in the module.
+  allowed: BTreeSet<String>,
+}
+
+impl CustomSectionFilter {
+  /// Creates a filter stripping all custom sections, except the metering section.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Allows the custom section with the given name.
+  pub fn with_allowed(mut self, name: impl Into<String>) -> Self {
+    self.allowed.insert(name.into());
+    self
+  }
+
+  /// Returns `true` iff custom sections with the given name are kept.
+  pub fn is_allowed(&self, name: &str) -> bool {
+    name == METERING_SECTION_NAME || self.allowed.contains(name)
+  }
+
+  /// Strips custom sections not allowed by the filter from the model, returning the stripped sections.
+  pub fn apply(&self, model: &mut Model) -> StrippedSections {
+    let mut stripped = StrippedSections::default();
+    if !self.is_allowed(NAME_SECTION_NAME) {
+      if let Some(names) = model.names.take() {
+        let name_section = names.encode(model.imported_function_count(), &model.code_section_entries);
+        stripped.sections.push((name_section.name.clone(), encoded_size(&name_section)));
+        // Local and label names are encoded only within the name section.
+        for code_section_entry in &mut model.code_section_entries {
+          code_section_entry.local_names.clear();
+          code_section_entry.label_names.clear();
+        }
+      }
+    }
+    model.custom_sections.retain(|custom_section| {
+      let allowed = self.is_allowed(&custom_section.name);
+      if !allowed {
+        stripped.sections.push((custom_section.name.clone(), encoded_size(custom_section)));
+      }
+      allowed
+    });
+    stripped
+  }
+}
+
+/// Returns the size of the encoded custom section, including the section identifier and size.
+fn encoded_size(custom_section: &CustomSection) -> usize {
+  let mut header = vec![];
+  custom_section.name.as_str().encode(&mut header);
+  let content_size = header.len() + custom_section.data.len();
+  (content_size as u32).encode(&mut header);
+  1 + header.len() + custom_section.data.len()
+}
@@ -0,0 +1,318 @@
+//! # Editing the model with automatic renumbering of indexes
+
+use crate::cfg::check_structure;
+use crate::mappings::map_func_type;
+use crate::remap::{IndexMap, IndexRemap};
+use crate::{CodeSectionEntry, ConstExpr, Data, DataKind, ElementItems, Export, Global, Import, Model, NameMap, WasmarinError, WasmarinResult, NAME_SECTION_NAME};
+use wasmparser::{BlockType, CompositeInnerType, ExternalKind, FuncType, GlobalType, Operator, TypeRef, ValType};
+
+/// Editing operations.
+///
+/// Inserting or removing an item shifts the indexes of the following items in the same index space,
+/// so all references to them are renumbered: calls, `ref.func`, element segments, exports, the start function,
+/// `global.get`/`global.set`, constant expressions, block types and so on. Removing an item that is still
+/// referenced fails and leaves the model unchanged. Names are renumbered as well, names of removed items are dropped.
+/// A name section that could not be parsed is dropped on renumbering, because it would name wrong items.
+impl<'a> Model<'a> {
+  /// Creates a new empty [Model].
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Appends a function type, returns its type index.
+  pub fn add_type(&mut self, func_type: FuncType) -> u32 {
+    let type_index = self.type_count();
+    self.rec_groups.push(rec_group(func_type));
+    type_index
+  }
+
+  /// Removes the type with the given type index.
+  ///
+  /// Only types defined outside of explicit recursion groups can be removed. Type indexes inside value types
+  /// (of tables, globals, locals, element segments, block types, ...) are not renumbered,
+  /// so no type can be removed from modules referencing types by value types.
+  pub fn remove_type(&mut self, type_index: u32) -> WasmarinResult<()> {
+    let mut first_type_index = 0;
+    let mut position = None;
+    for (index, rec_group) in self.rec_groups.iter().enumerate() {
+      let count = rec_group.types().len() as u32;
+      if type_index < first_type_index + count {
+        if rec_group.is_explicit_rec_group() {
+          return Err(WasmarinError::new(format!("type {type_index} belongs to an explicit recursion group")));
+        }
+        position = Some(index);
+        break;
+      }
+      first_type_index += count;
+    }
+    let position = position.ok_or_else(|| WasmarinError::new(format!("unknown type {type_index}")))?;
+    if !types_removable(self) {
+      return Err(WasmarinError::new(format!("type {type_index} can not be removed, types are referenced by value types")));
+    }
+    self.edit(
+      |model| {
+        model.rec_groups.remove(position);
+      },
+      IndexRemap {
+        types: IndexMap::Remove(type_index),
+        ..Default::default()
+      },
+    )
+  }
+
+  /// Appends an import, returns the index of the imported item in its index space.
+  ///
+  /// Imported items precede defined items in each index space, so defined items of the same kind are renumbered.
+  pub fn add_import(&mut self, module: &str, name: &str, ty: TypeRef) -> WasmarinResult<u32> {
+    let index = self.imports.iter().filter(|import| same_kind(&import.ty, &ty)).count() as u32;
+    let import = Import {
+      module: module.to_string(),
+      name: name.to_string(),
+      ty,
+    };
+    self.edit(|model| model.imports.push(import), import_remap(&ty, IndexMap::Insert(index)))?;
+    Ok(index)
+  }
+
+  /// Removes the import with the given module and field name.
+  pub fn remove_import(&mut self, module: &str, name: &str) -> WasmarinResult<()> {
+    let position = self
+      .imports
+      .iter()
+      .position(|import| import.module == module && import.name == name)
+      .ok_or_else(|| WasmarinError::new(format!("unknown import {module}::{name}")))?;
+    let ty = self.imports[position].ty;
+    let index = self.imports[..position].iter().filter(|import| same_kind(&import.ty, &ty)).count() as u32;
+    self.edit(
+      |model| {
+        model.imports.remove(position);
+      },
+      import_remap(&ty, IndexMap::Remove(index)),
+    )
+  }
+
+  /// Appends a defined function with the given type, locals and body, returns its function index.
+  ///
+  /// The body must be terminated with the `end` operator, and its control structures must be properly nested.
+  /// Operand types are not validated.
+  pub fn add_function(&mut self, type_index: u32, locals: Vec<(u32, ValType)>, operators: Vec<Operator<'a>>) -> WasmarinResult<u32> {
+    if self.func_type(type_index).is_none() {
+      return Err(WasmarinError::new(format!("unknown function type {type_index}")));
+    }
+    check_structure(&operators)?;
+    let function_index = self.function_count();
+    self.function_indexes.push(type_index);
+    self.code_section_entries.push(CodeSectionEntry {
+      locals,
+      operators,
+      operand_types: None,
+      original_body: None,
+      touched: false,
+      original_offsets: None,
+      local_names: NameMap::new(),
+      label_names: NameMap::new(),
+    });
+    Ok(function_index)
+  }
+
+  /// Removes the defined function with the given function index.
+  pub fn remove_function(&mut self, function_index: u32) -> WasmarinResult<()> {
+    let position = self.defined_position(function_index, self.imported_function_count(), self.function_indexes.len(), "function")?;
+    self.edit(
+      |model| {
+        model.function_indexes.remove(position);
+        model.code_section_entries.remove(position);
+      },
+      IndexRemap {
+        functions: IndexMap::Remove(function_index),
+        ..Default::default()
+      },
+    )
+  }
+
+  /// Appends a defined global, returns its global index.
+  pub fn add_global(&mut self, ty: GlobalType, init_expr: ConstExpr) -> u32 {
+    let global_index = self.imported_global_count() + self.globals.len() as u32;
+    self.globals.push(Global { ty, init_expr });
+    global_index
+  }
+
+  /// Removes the defined global with the given global index.
+  pub fn remove_global(&mut self, global_index: u32) -> WasmarinResult<()> {
+    let position = self.defined_position(global_index, self.imported_global_count(), self.globals.len(), "global")?;
+    self.edit(
+      |model| {
+        model.globals.remove(position);
+      },
+      IndexRemap {
+        globals: IndexMap::Remove(global_index),
+        ..Default::default()
+      },
+    )
+  }
+
+  /// Appends an export, export names must be unique.
+  pub fn add_export(&mut self, name: &str, kind: ExternalKind, index: u32) -> WasmarinResult<()> {
+    if self.exports.iter().any(|export| export.name == name) {
+      return Err(WasmarinError::new(format!("duplicate export '{name}'")));
+    }
+    self.exports.push(Export {
+      name: name.to_string(),
+      kind,
+      index,
+    });
+    Ok(())
+  }
+
+  /// Removes the export with the given name, returns the removed export.
+  pub fn remove_export(&mut self, name: &str) -> WasmarinResult<Export> {
+    let position = self
+      .exports
+      .iter()
+      .position(|export| export.name == name)
+      .ok_or_else(|| WasmarinError::new(format!("unknown export '{name}'")))?;
+    Ok(self.exports.remove(position))
+  }
+
+  /// Appends a data segment, returns its data segment index.
+  pub fn add_data(&mut self, kind: DataKind, data: Vec<u8>) -> u32 {
+    let data_index = self.data.len() as u32;
+    self.data.push(Data { kind, data });
+    if self.data_count.is_some() {
+      self.data_count = Some(self.data.len() as u32);
+    }
+    data_index
+  }
+
+  /// Removes the data segment with the given data segment index.
+  pub fn remove_data(&mut self, data_index: u32) -> WasmarinResult<()> {
+    let position = self.defined_position(data_index, 0, self.data.len(), "data segment")?;
+    self.edit(
+      |model| {
+        model.data.remove(position);
+        if model.data_count.is_some() {
+          model.data_count = Some(model.data.len() as u32);
+        }
+      },
+      IndexRemap {
+        data: IndexMap::Remove(data_index),
+        ..Default::default()
+      },
+    )
+  }
+
+  /// Returns the number of all types.
+  pub fn type_count(&self) -> u32 {
+    self.rec_groups.iter().map(|rec_group| rec_group.types().len() as u32).sum()
+  }
+
+  /// Applies the change and renumbers all references, the model is left unchanged on failure.
+  ///
+  /// References are checked before the change, so the model is changed in place without keeping a copy for rollback.
+  pub(crate) fn edit(&mut self, change: impl FnOnce(&mut Self), remap: IndexRemap) -> WasmarinResult<()> {
+    remap.check_model(self)?;
+    change(self);
+    remap.remap_model(self)?;
+    self.custom_sections.retain(|custom_section| custom_section.name != NAME_SECTION_NAME);
+    Ok(())
+  }
+
+  /// Returns the position of a defined item with the given index among defined items.
+  fn defined_position(&self, index: u32, imported_count: u32, defined_count: usize, item: &str) -> WasmarinResult<usize> {
+    match index.checked_sub(imported_count) {
+      Some(position) if (position as usize) < defined_count => Ok(position as usize),
+      Some(_) => Err(WasmarinError::new(format!("unknown {item} {index}"))),
+      None => Err(WasmarinError::new(format!("{item} {index} is imported, remove the import instead"))),
+    }
+  }
+}
+
+/// Returns `true` iff types can be removed and renumbered.
+///
+/// Type indexes inside value types are not renumbered, so types can be removed only from modules
+/// with plain function types not referenced by any value type.
+pub(crate) fn types_removable(model: &Model) -> bool {
+  let plain_func_types = model.rec_groups.iter().all(|rec_group| {
+    !rec_group.is_explicit_rec_group()
+      && rec_group.types().all(|sub_type| {
+        sub_type.is_final
+          && sub_type.supertype_idx.is_none()
+          && match &sub_type.composite_type.inner {
+            CompositeInnerType::Func(func_type) => !func_type.params().iter().chain(func_type.results()).any(is_concrete),
+            _ => false,
+          }
+      })
+  });
+  let concrete_imports = model.imports.iter().any(|import| match import.ty {
+    TypeRef::Global(global_type) => is_concrete(&global_type.content_type),
+    TypeRef::Table(table_type) => table_type.element_type.type_index().is_some(),
+    _ => false,
+  });
+  let concrete_items = model.globals.iter().any(|global| is_concrete(&global.ty.content_type))
+    || model.tables.iter().any(|table| table.ty.element_type.type_index().is_some())
+    || model
+      .elements
+      .iter()
+      .any(|element| matches!(element.items, ElementItems::Expressions(ref_type, _) if ref_type.type_index().is_some()));
+  let concrete_code = model
+    .code_section_entries
+    .iter()
+    .any(|code_section_entry| code_section_entry.locals.iter().any(|(_, val_type)| is_concrete(val_type)) || code_section_entry.operators.iter().any(references_concrete_type));
+  plain_func_types && !concrete_imports && !concrete_items && !concrete_code
+}
+
+/// Returns `true` iff the value type references a type.
+fn is_concrete(val_type: &ValType) -> bool {
+  matches!(val_type, ValType::Ref(ref_type) if ref_type.type_index().is_some())
+}
+
+/// Returns `true` iff the operator references a type inside a value type, which is not renumbered.
+fn references_concrete_type(operator: &Operator) -> bool {
+  match operator {
+    Operator::Block { blockty } | Operator::Loop { blockty } | Operator::If { blockty } | Operator::Try { blockty } => {
+      matches!(blockty, BlockType::Type(val_type) if is_concrete(val_type))
+    }
+    Operator::TryTable { try_table } => matches!(try_table.ty, BlockType::Type(val_type) if is_concrete(&val_type)),
+    Operator::TypedSelect { ty } => is_concrete(ty),
+    Operator::TypedSelectMulti { tys } => tys.iter().any(is_concrete),
+    Operator::BrOnCast { .. } | Operator::BrOnCastFail { .. } => true,
+    _ => false,
+  }
+}
+
+/// Returns `true` iff both imports belong to the same index space.
+fn same_kind(a: &TypeRef, b: &TypeRef) -> bool {
+  std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+/// Returns the renumbering of the index space the import belongs to.
+fn import_remap<'r>(ty: &TypeRef, index_map: IndexMap<'r>) -> IndexRemap<'r> {
+  let mut remap = IndexRemap::default();
+  match ty {
+    TypeRef::Func(_) => remap.functions = index_map,
+    TypeRef::Table(_) => remap.tables = index_map,
+    TypeRef::Memory(_) => remap.memories = index_map,
+    TypeRef::Global(_) => remap.globals = index_map,
+    TypeRef::Tag(_) => remap.tags = index_map,
+  }
+  remap
+}
+
+/// Returns an implicit recursion group holding the single function type.
+///
+/// Recursion groups can not be created directly, so the type is encoded and parsed back.
+fn rec_group(func_type: FuncType) -> wasmparser::RecGroup {
+  let mut type_section = wasm_encoder::TypeSection::new();
+  type_section.ty().func_type(&map_func_type(func_type));
+  let mut module = wasm_encoder::Module::new();
+  module.section(&type_section);
+  let wasm_bytes = module.finish();
+  for payload in wasmparser::Parser::new(0).parse_all(&wasm_bytes) {
+    if let Ok(wasmparser::Payload::TypeSection(reader)) = payload {
+      if let Some(Ok(rec_group)) = reader.into_iter().next() {
+        return rec_group;
+      }
+    }
+  }
+  unreachable!("encoded type section always contains a single recursion group")
+}
@@ -1,13 +1,43 @@
 use crate::mappings::*;
-use crate::metering::*;
-use crate::{Model, WasmarinResult};
+use crate::{
+  CodeSectionEntry, CostSchedule, CustomSection, CustomSectionFilter, DataKind, ElementKind, Metering, Model, OffsetMap, OwnedModel, Parser, Pass, Pipeline, SectionId,
+  StrippedSections, WasmarinError, WasmarinResult, METERING_SECTION_NAME,
+};
 use std::borrow::Cow;
-use wasmparser::{DataKind, ElementKind, TableInit};
+use std::iter::Peekable;
+
+/// Prefix of names of custom sections holding DWARF debugging information.
+pub const DWARF_SECTION_PREFIX: &str = ".debug_";
+
+/// Handling of DWARF sections, whose code offsets become invalid when functions are added, removed or re-encoded.
+///
+/// DWARF code offsets are not rewritten, the [OffsetMap] built with [Encoder::with_offset_map] translates
+/// offsets in the encoded module back to the original binary described by the DWARF sections.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DwarfSections {
+  /// DWARF sections are dropped, with a warning, when the code section differs from the input.
+  #[default]
+  Drop,
+  /// DWARF sections are copied unchanged, with a warning when the code section differs from the input.
+  Keep,
+}
 
 /// The WebAssembly encoder.
 pub struct Encoder {
-  /// Metering properties.
-  metering: Metering,
+  /// Passes run on the model before encoding.
+  pipeline: Pipeline,
+  /// Flag indicating if the offset map is built while encoding.
+  build_offset_map: bool,
+  /// Offset map built by the last encoding.
+  offset_map: Option<OffsetMap>,
+  /// Handling of DWARF sections.
+  dwarf_sections: DwarfSections,
+  /// Warnings reported by the last encoding.
+  warnings: Vec<String>,
+  /// Policy stripping custom sections, all custom sections are kept when not set.
+  custom_section_filter: Option<CustomSectionFilter>,
+  /// Custom sections stripped by the last encoding.
+  stripped_sections: Option<StrippedSections>,
 }
 
 impl Default for Encoder {
@@ -19,184 +49,558 @@ impl Default for Encoder {
 impl Encoder {
   /// Creates a new [Encoder] instance.
   pub fn new() -> Self {
-    Self { metering: Metering::new(false) }
+    Self::new_with_pipeline(Pipeline::new())
+  }
+
+  /// Creates a new [Encoder] instance running the specified passes before encoding.
+  pub fn new_with_pipeline(pipeline: Pipeline) -> Self {
+    Self {
+      pipeline,
+      build_offset_map: false,
+      offset_map: None,
+      dwarf_sections: DwarfSections::default(),
+      warnings: vec![],
+      custom_section_filter: None,
+      stripped_sections: None,
+    }
   }
 
   /// Creates a new [Encoder] instance with metering.
   pub fn new_with_metering() -> Self {
-    Self { metering: Metering::new(true) }
+    Self::new().with_pass(Metering::new())
+  }
+
+  /// Creates a new [Encoder] instance with metering using the specified cost schedule.
+  pub fn new_with_cost_schedule(cost_schedule: CostSchedule) -> Self {
+    Self::new().with_pass(Metering::new_with_cost_schedule(cost_schedule))
+  }
+
+  /// Appends a pass run on the model before encoding.
+  pub fn with_pass(mut self, pass: impl Pass + 'static) -> Self {
+    self.pipeline = self.pipeline.with_pass(pass);
+    self
+  }
+
+  /// Sets the number of worker threads instrumenting and encoding function bodies in parallel, see [Pipeline::with_workers].
+  ///
+  /// The encoded module is the same regardless of the number of workers. By default, function bodies are processed
+  /// sequentially on the calling thread. [Encoder::encode_wasm_bytes] always processes function bodies sequentially.
+  pub fn with_workers(mut self, workers: usize) -> Self {
+    self.pipeline = self.pipeline.with_workers(workers);
+    self
+  }
+
+  /// Enables or disables building the map of operator offsets in the encoded module back to the original binary.
+  ///
+  /// Original offsets are known only for functions parsed with [Parser::with_original_offsets],
+  /// [Encoder::encode_wasm_bytes] records them regardless of the parser settings. Disabled by default.
+  pub fn with_offset_map(mut self, enabled: bool) -> Self {
+    self.build_offset_map = enabled;
+    self
+  }
+
+  /// Returns the offset map built by the last encoding, when enabled with [Encoder::with_offset_map].
+  pub fn offset_map(&self) -> Option<&OffsetMap> {
+    self.offset_map.as_ref()
+  }
+
+  /// Sets the handling of DWARF sections, see [DwarfSections].
+  pub fn with_dwarf_sections(mut self, dwarf_sections: DwarfSections) -> Self {
+    self.dwarf_sections = dwarf_sections;
+    self
+  }
+
+  /// Strips custom sections not allowed by the filter before encoding, see [CustomSectionFilter].
+  pub fn with_custom_section_filter(mut self, custom_section_filter: CustomSectionFilter) -> Self {
+    self.custom_section_filter = Some(custom_section_filter);
+    self
+  }
+
+  /// Returns custom sections stripped by the last encoding, when filtered with [Encoder::with_custom_section_filter].
+  pub fn stripped_sections(&self) -> Option<&StrippedSections> {
+    self.stripped_sections.as_ref()
+  }
+
+  /// Returns warnings reported by the last encoding.
+  pub fn warnings(&self) -> &[String] {
+    &self.warnings
+  }
+
+  /// Encode the owned WebAssembly model into WASM binary, the same way as [Encoder::encode].
+  pub fn encode_owned(&mut self, model: &OwnedModel) -> WasmarinResult<Vec<u8>> {
+    self.encode(model.into())
   }
 
   /// Encode the WebAssembly model into WASM binary.
-  pub fn encode(&mut self, model: Model) -> WasmarinResult<Vec<u8>> {
-    // Prepare the WebAssembly module.
-    let mut module = wasm_encoder::Module::new();
-
-    //----------------------------------------------------------------------------------------------
-    // TYPE SECTION
-    //
-    let mut type_section = wasm_encoder::TypeSection::new();
-    for rec_group in model.rec_groups {
-      let sub_types: Vec<wasm_encoder::SubType> = rec_group.types().cloned().map(map_sub_type).collect();
-      if rec_group.is_explicit_rec_group() {
-        type_section.ty().rec(sub_types);
-      } else {
-        for sub_type in &sub_types {
-          type_section.ty().subtype(sub_type);
-        }
+  pub fn encode(&mut self, mut model: Model) -> WasmarinResult<Vec<u8>> {
+    self.warnings.clear();
+    // Run all passes before encoding.
+    self.pipeline.run(&mut model)?;
+
+    let encoded_code = encode_functions(&model.code_section_entries, self.pipeline.workers(), self.build_offset_map);
+    Ok(self.finish(model, encoded_code))
+  }
+
+  /// Parses the WASM binary with the given parser and encodes it, streaming function bodies one at a time.
+  ///
+  /// Produces the same output as parsing the model with [Parser::parse_wasm_bytes] and encoding it with [Encoder::encode],
+  /// but operators of all functions are never held in memory at once. Module-level items are updated by all passes first,
+  /// then each function is decoded and validated in a single pass, updated by all passes and encoded, before the next
+  /// function is decoded. Functions not [touched](CodeSectionEntry::touched) by the passes are copied from the input.
+  /// When no pass needs function bodies (see [Pass::needs_function_bodies]) and no offset map is built,
+  /// function bodies are only validated and copied, without being decoded into operators.
+  ///
+  /// Passes must not rely on function bodies in [Pass::update_model], function bodies are empty at that point.
+  pub fn encode_wasm_bytes(&mut self, parser: &mut Parser, wasm_bytes: &[u8]) -> WasmarinResult<Vec<u8>> {
+    self.warnings.clear();
+    let (mut model, funcs_to_validate) = parser.parse_wasm_bytes_lazily(wasm_bytes)?;
+    self.pipeline.update_model(&mut model)?;
+
+    let imported_function_count = model.imported_function_count();
+    let decode_function_bodies = self.pipeline.needs_function_bodies() || self.build_offset_map;
+    let mut encoded_code = EncodedCode::new(self.build_offset_map);
+    let mut allocations = wasmparser::FuncValidatorAllocations::default();
+    // Each function is decoded into the same code section entry, so at most one function is held in memory.
+    let mut code_section_entry = CodeSectionEntry::default();
+    for (index, (lazy_code_section_entry, func_to_validate)) in model.code_section_entries.iter_mut().zip(funcs_to_validate).enumerate() {
+      let Some(original_body) = lazy_code_section_entry.original_body else {
+        return Err(WasmarinError::new("function body was not parsed"));
+      };
+      // Function bodies are parts of the input, so their offsets are known without recording them in the parser.
+      let original_offset = original_body.as_ptr() as usize - wasm_bytes.as_ptr() as usize;
+      let body = wasmparser::FunctionBody::new(wasmparser::BinaryReader::new(original_body, original_offset));
+      let mut func_validator = func_to_validate.into_validator(std::mem::take(&mut allocations));
+      if !decode_function_bodies {
+        func_validator.validate(&body).map_err(|e| WasmarinError::new(e.to_string()))?;
+        allocations = func_validator.into_allocations();
+        encoded_code.add(EncodedFunction::copied(original_body));
+        continue;
       }
+      parser.parse_function_body(&body, &mut func_validator, self.build_offset_map, &mut code_section_entry)?;
+      allocations = func_validator.into_allocations();
+      // Names are updated by passes along with the function body, and encoded later in the name section.
+      std::mem::swap(&mut code_section_entry.local_names, &mut lazy_code_section_entry.local_names);
+      std::mem::swap(&mut code_section_entry.label_names, &mut lazy_code_section_entry.label_names);
+      self.pipeline.update_function(imported_function_count + index as u32, &mut code_section_entry)?;
+      encoded_code.add(encode_function(&code_section_entry, self.build_offset_map));
+      std::mem::swap(&mut code_section_entry.local_names, &mut lazy_code_section_entry.local_names);
+      std::mem::swap(&mut code_section_entry.label_names, &mut lazy_code_section_entry.label_names);
     }
-    module.section(&type_section);
-
-    //----------------------------------------------------------------------------------------------
-    // IMPORT SECTION
-    //
-    let mut import_section = wasm_encoder::ImportSection::new();
-    for import in model.imports {
-      import_section.import(import.module, import.name, map_type_ref(import.ty));
-    }
-    module.section(&import_section);
-
-    //----------------------------------------------------------------------------------------------
-    // FUNCTION SECTION
-    //
-    let mut function_section = wasm_encoder::FunctionSection::new();
-    for function_index in model.function_indexes {
-      function_section.function(function_index);
-    }
-    module.section(&function_section);
-
-    //----------------------------------------------------------------------------------------------
-    // TABLE SECTION
-    //
-    let mut table_section = wasm_encoder::TableSection::new();
-    for table in model.tables {
-      match table.init {
-        TableInit::RefNull => {
-          table_section.table(map_table_type(table.ty));
-        }
-        TableInit::Expr(const_expr) => {
-          table_section.table_with_init(map_table_type(table.ty), &map_const_expr(const_expr));
-        }
+    Ok(self.finish(model, encoded_code))
+  }
+
+  /// Encodes the model with already encoded function bodies, stripping custom sections and building the offset map when requested.
+  fn finish(&mut self, mut model: Model, encoded_code: EncodedCode) -> Vec<u8> {
+    self.stripped_sections = self.custom_section_filter.as_ref().map(|custom_section_filter| custom_section_filter.apply(&mut model));
+    self.update_dwarf_sections(&mut model, encoded_code.copied_bodies.as_deref());
+    let wasm_bytes = encode_module(model, encoded_code.code_section);
+    self.offset_map = encoded_code.offsets.map(|offsets| offset_map(&wasm_bytes, offsets));
+    wasm_bytes
+  }
+
+  /// Keeps or drops DWARF sections of the model when the encoded code section differs from the input, reporting a warning.
+  fn update_dwarf_sections(&mut self, model: &mut Model, copied_bodies: Option<&[&[u8]]>) {
+    let names: Vec<String> = model
+      .custom_sections
+      .iter()
+      .filter(|custom_section| custom_section.name.starts_with(DWARF_SECTION_PREFIX))
+      .map(|custom_section| custom_section.name.clone())
+      .collect();
+    if names.is_empty() || !code_section_changed(model.original_code_section, copied_bodies) {
+      return;
+    }
+    match self.dwarf_sections {
+      DwarfSections::Keep => self
+        .warnings
+        .push(format!("DWARF sections {} kept with code offsets of the original function bodies", names.join(", "))),
+      DwarfSections::Drop => {
+        model.custom_sections.retain(|custom_section| !custom_section.name.starts_with(DWARF_SECTION_PREFIX));
+        self.warnings.push(format!("DWARF sections {} dropped, the code section was changed", names.join(", ")));
       }
     }
-    module.section(&table_section);
-
-    //----------------------------------------------------------------------------------------------
-    // MEMORY SECTION
-    //
-    let mut memory_section = wasm_encoder::MemorySection::new();
-    for memory_type in model.memory_types {
-      memory_section.memory(map_memory_type(memory_type));
-    }
-    module.section(&memory_section);
-
-    //----------------------------------------------------------------------------------------------
-    // TAG SECTION
-    //
-    let mut tag_section = wasm_encoder::TagSection::new();
-    for tag_type in model.tag_types {
-      tag_section.tag(map_tag_type(tag_type));
-    }
-    if !tag_section.is_empty() {
-      module.section(&tag_section);
-    }
-
-    //----------------------------------------------------------------------------------------------
-    // GLOBAL SECTION
-    //
-    let mut global_section = wasm_encoder::GlobalSection::new();
-    for global in model.globals {
-      global_section.global(map_global_type(global.ty), &map_const_expr(global.init_expr));
-    }
-    self.metering.update_global_section(&mut global_section);
-    module.section(&global_section);
-
-    //----------------------------------------------------------------------------------------------
-    // EXPORT SECTION
-    //
-    let mut export_section = wasm_encoder::ExportSection::new();
-    for export in model.exports {
-      export_section.export(export.name, map_export_kind(export.kind), export.index);
-    }
-    self.metering.update_export_section(&mut export_section);
-    module.section(&export_section);
-
-    //----------------------------------------------------------------------------------------------
-    // START SECTION
-    //
-    if let Some(function_index) = model.start_function_index {
-      let start_section = wasm_encoder::StartSection { function_index };
-      module.section(&start_section);
-    }
-
-    //----------------------------------------------------------------------------------------------
-    // ELEMENT SECTION
-    //
-    let mut element_section = wasm_encoder::ElementSection::new();
-    for element in model.elements {
-      match element.kind {
-        ElementKind::Passive => {
-          element_section.passive(map_element_items(element.items)?);
-        }
-        ElementKind::Active { table_index, offset_expr } => {
-          element_section.active(table_index, &map_const_expr(offset_expr), map_element_items(element.items)?);
-        }
-        ElementKind::Declared => {
-          element_section.declared(map_element_items(element.items)?);
-        }
+  }
+}
+
+/// Returns `true` iff the encoded code section differs from the original one, functions added, removed and re-encoded included.
+///
+/// The code section is unchanged only when all function bodies were copied from the input and they are laid out
+/// exactly like in the original code section, in the same order and with the same sizes.
+fn code_section_changed(original_code_section: Option<&[u8]>, copied_bodies: Option<&[&[u8]]>) -> bool {
+  let Some(copied_bodies) = copied_bodies else {
+    return true;
+  };
+  let Some(original_code_section) = original_code_section else {
+    return !copied_bodies.is_empty();
+  };
+  let mut reader = wasmparser::BinaryReader::new(original_code_section, 0);
+  if reader.read_var_u32().ok() != Some(copied_bodies.len() as u32) {
+    return true;
+  }
+  // Copied bodies are compared in place, their sizes are encoded in the shortest form, like the function count.
+  let mut position = leb128_length(copied_bodies.len());
+  for body in copied_bodies {
+    position += leb128_length(body.len());
+    if original_code_section.get(position..position + body.len()) != Some(*body) {
+      return true;
+    }
+    position += body.len();
+  }
+  position != original_code_section.len()
+}
+
+/// Returns the length of the shortest LEB128 encoding of the value.
+fn leb128_length(value: usize) -> usize {
+  ((usize::BITS - value.leading_zeros()) as usize).div_ceil(7).max(1)
+}
+
+/// Encodes the WebAssembly model into WASM binary, with function bodies already encoded in the code section.
+fn encode_module(model: Model, code_section: wasm_encoder::CodeSection) -> Vec<u8> {
+  // Prepare the WebAssembly module.
+  let mut module = wasm_encoder::Module::new();
+
+  // Custom sections are encoded in their original placement, the name section precedes other custom sections
+  // with the same placement and the metering section is the last one.
+  let mut custom_sections = vec![];
+  let imported_function_count = model.imported_function_count();
+  custom_sections.extend(model.names.map(|names| names.encode(imported_function_count, &model.code_section_entries)));
+  custom_sections.extend(model.custom_sections);
+  custom_sections.extend(model.metering.map(|metadata| CustomSection::new(METERING_SECTION_NAME, metadata.encode())));
+  custom_sections.sort_by_key(|custom_section| custom_section.after);
+  let mut custom_sections = custom_sections.into_iter().peekable();
+
+  //----------------------------------------------------------------------------------------------
+  // TYPE SECTION
+  //
+  let mut type_section = wasm_encoder::TypeSection::new();
+  for rec_group in model.rec_groups {
+    let sub_types: Vec<wasm_encoder::SubType> = rec_group.types().cloned().map(map_sub_type).collect();
+    if rec_group.is_explicit_rec_group() {
+      type_section.ty().rec(sub_types);
+    } else {
+      for sub_type in &sub_types {
+        type_section.ty().subtype(sub_type);
       }
     }
-    module.section(&element_section);
-
-    //----------------------------------------------------------------------------------------------
-    // DATA COUNT SECTION
-    //
-    if let Some(count) = model.data_count {
-      let data_count_section = wasm_encoder::DataCountSection { count };
-      module.section(&data_count_section);
-    }
-
-    //----------------------------------------------------------------------------------------------
-    // CODE SECTION
-    //
-    let mut code_section = wasm_encoder::CodeSection::new();
-    for mut code_section_entry in model.code_section_entries {
-      let locals: Vec<(u32, wasm_encoder::ValType)> = code_section_entry.locals.drain(..).map(|(index, val_type)| (index, map_val_type(val_type))).collect();
-      let mut function = wasm_encoder::Function::new(locals);
-      self.metering.update_function(&mut function, code_section_entry.operators);
-      code_section.function(&function);
-    }
-    module.section(&code_section);
-
-    //----------------------------------------------------------------------------------------------
-    // DATA SECTION
-    //
-    let mut data_section = wasm_encoder::DataSection::new();
-    for data in model.data {
-      match data.kind {
-        DataKind::Passive => {
-          data_section.passive(data.data.iter().cloned());
-        }
-        DataKind::Active { memory_index, offset_expr } => {
-          data_section.active(memory_index, &map_const_expr(offset_expr), data.data.iter().cloned());
-        }
+  }
+  if !type_section.is_empty() {
+    encode_section(&mut module, &mut custom_sections, SectionId::Type, &type_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // IMPORT SECTION
+  //
+  let mut import_section = wasm_encoder::ImportSection::new();
+  for import in model.imports {
+    import_section.import(&import.module, &import.name, map_type_ref(import.ty));
+  }
+  if !import_section.is_empty() {
+    encode_section(&mut module, &mut custom_sections, SectionId::Import, &import_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // FUNCTION SECTION
+  //
+  let mut function_section = wasm_encoder::FunctionSection::new();
+  for function_index in model.function_indexes {
+    function_section.function(function_index);
+  }
+  if !function_section.is_empty() {
+    encode_section(&mut module, &mut custom_sections, SectionId::Function, &function_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // TABLE SECTION
+  //
+  let mut table_section = wasm_encoder::TableSection::new();
+  for table in model.tables {
+    match table.init_expr {
+      None => {
+        table_section.table(map_table_type(table.ty));
+      }
+      Some(const_expr) => {
+        table_section.table_with_init(map_table_type(table.ty), &map_const_expr(&const_expr));
       }
     }
-    module.section(&data_section);
+  }
+  if !table_section.is_empty() {
+    encode_section(&mut module, &mut custom_sections, SectionId::Table, &table_section);
+  }
 
-    //----------------------------------------------------------------------------------------------
-    // CUSTOM SECTIONS
-    //
-    for (name, data) in model.custom_sections {
-      let custom_section = wasm_encoder::CustomSection {
-        name: Cow::Owned(name),
-        data: Cow::Owned(data),
-      };
-      module.section(&custom_section);
+  //----------------------------------------------------------------------------------------------
+  // MEMORY SECTION
+  //
+  let mut memory_section = wasm_encoder::MemorySection::new();
+  for memory_type in model.memory_types {
+    memory_section.memory(map_memory_type(memory_type));
+  }
+  if !memory_section.is_empty() {
+    encode_section(&mut module, &mut custom_sections, SectionId::Memory, &memory_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // TAG SECTION
+  //
+  let mut tag_section = wasm_encoder::TagSection::new();
+  for tag_type in model.tag_types {
+    tag_section.tag(map_tag_type(tag_type));
+  }
+  if !tag_section.is_empty() {
+    encode_section(&mut module, &mut custom_sections, SectionId::Tag, &tag_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // GLOBAL SECTION
+  //
+  let mut global_section = wasm_encoder::GlobalSection::new();
+  for global in model.globals {
+    global_section.global(map_global_type(global.ty), &map_const_expr(&global.init_expr));
+  }
+  if !global_section.is_empty() {
+    encode_section(&mut module, &mut custom_sections, SectionId::Global, &global_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // EXPORT SECTION
+  //
+  let mut export_section = wasm_encoder::ExportSection::new();
+  for export in model.exports {
+    export_section.export(&export.name, map_export_kind(export.kind), export.index);
+  }
+  if !export_section.is_empty() {
+    encode_section(&mut module, &mut custom_sections, SectionId::Export, &export_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // START SECTION
+  //
+  if let Some(function_index) = model.start_function_index {
+    let start_section = wasm_encoder::StartSection { function_index };
+    encode_section(&mut module, &mut custom_sections, SectionId::Start, &start_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // ELEMENT SECTION
+  //
+  let mut element_section = wasm_encoder::ElementSection::new();
+  for element in model.elements {
+    match element.kind {
+      ElementKind::Passive => {
+        element_section.passive(map_element_items(&element.items));
+      }
+      ElementKind::Active { table_index, offset_expr } => {
+        element_section.active(table_index, &map_const_expr(&offset_expr), map_element_items(&element.items));
+      }
+      ElementKind::Declared => {
+        element_section.declared(map_element_items(&element.items));
+      }
+    }
+  }
+  if !element_section.is_empty() {
+    encode_section(&mut module, &mut custom_sections, SectionId::Element, &element_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // DATA COUNT SECTION
+  //
+  if let Some(count) = model.data_count {
+    let data_count_section = wasm_encoder::DataCountSection { count };
+    encode_section(&mut module, &mut custom_sections, SectionId::DataCount, &data_count_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // CODE SECTION
+  //
+  if !code_section.is_empty() {
+    encode_section(&mut module, &mut custom_sections, SectionId::Code, &code_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // DATA SECTION
+  //
+  let mut data_section = wasm_encoder::DataSection::new();
+  for data in model.data {
+    match data.kind {
+      DataKind::Passive => {
+        data_section.passive(data.data);
+      }
+      DataKind::Active { memory_index, offset_expr } => {
+        data_section.active(memory_index, &map_const_expr(&offset_expr), data.data);
+      }
+    }
+  }
+  if !data_section.is_empty() {
+    encode_section(&mut module, &mut custom_sections, SectionId::Data, &data_section);
+  }
+
+  //----------------------------------------------------------------------------------------------
+  // CUSTOM SECTIONS
+  //
+  encode_custom_sections(&mut module, &mut custom_sections, None);
+
+  // Extract the encoded Wasm bytes for this module.
+  module.finish()
+}
+
+/// Function bodies encoded into the code section, with offsets of their operators when requested.
+struct EncodedCode<'a> {
+  /// Code section with all encoded function bodies.
+  code_section: wasm_encoder::CodeSection,
+  /// Offsets of operators of each function body, `None` when not requested.
+  offsets: Option<Vec<Vec<(usize, usize)>>>,
+  /// Function bodies copied from the input, `None` when any function body was re-encoded.
+  copied_bodies: Option<Vec<&'a [u8]>>,
+}
+
+impl<'a> EncodedCode<'a> {
+  /// Creates an empty code section, recording offsets of operators when requested.
+  fn new(with_offsets: bool) -> Self {
+    Self {
+      code_section: wasm_encoder::CodeSection::new(),
+      offsets: with_offsets.then(Vec::new),
+      copied_bodies: Some(vec![]),
+    }
+  }
+
+  /// Appends the encoded function body.
+  fn add(&mut self, encoded_function: EncodedFunction<'a>) {
+    self.code_section.raw(&encoded_function.body);
+    match (&mut self.copied_bodies, encoded_function.body) {
+      (Some(copied_bodies), Cow::Borrowed(body)) => copied_bodies.push(body),
+      _ => self.copied_bodies = None,
     }
+    if let Some(offsets) = &mut self.offsets {
+      offsets.push(encoded_function.offsets);
+    }
+  }
+}
+
+/// Encoded function body.
+struct EncodedFunction<'a> {
+  /// Function body without its size, locals included.
+  body: Cow<'a, [u8]>,
+  /// Offsets of operators relative to the start of the body, paired with their offsets in the original binary.
+  ///
+  /// Empty when not requested or when the original offsets are not known.
+  offsets: Vec<(usize, usize)>,
+}
+
+impl<'a> EncodedFunction<'a> {
+  /// Returns the function body copied from the original binary, without offsets.
+  fn copied(original_body: &'a [u8]) -> Self {
+    Self {
+      body: Cow::Borrowed(original_body),
+      offsets: vec![],
+    }
+  }
+}
+
+/// Encodes function bodies into the code section, in parallel when more than one worker is requested.
+fn encode_functions<'a>(code_section_entries: &[CodeSectionEntry<'a>], workers: usize, with_offsets: bool) -> EncodedCode<'a> {
+  let mut encoded_code = EncodedCode::new(with_offsets);
+  if workers < 2 {
+    for code_section_entry in code_section_entries {
+      encoded_code.add(encode_function(code_section_entry, with_offsets));
+    }
+    return encoded_code;
+  }
+  let chunk_size = code_section_entries.len().div_ceil(workers).max(1);
+  std::thread::scope(|scope| {
+    let handles = code_section_entries
+      .chunks(chunk_size)
+      .map(|code_section_entries| {
+        scope.spawn(move || {
+          code_section_entries
+            .iter()
+            .map(|code_section_entry| encode_function(code_section_entry, with_offsets))
+            .collect::<Vec<_>>()
+        })
+      })
+      .collect::<Vec<_>>();
+    // Function bodies are appended in order of functions, so the code section is the same as when encoded sequentially.
+    for handle in handles {
+      for encoded_function in handle.join().expect("worker thread panicked") {
+        encoded_code.add(encoded_function);
+      }
+    }
+  });
+  encoded_code
+}
+
+/// Returns the encoded function body, with offsets of its operators when requested and known.
+///
+/// Functions not touched are copied, preserving their original encoding. Copied and re-encoded bodies of the same
+/// function may differ, because compilers emit padded LEB128 immediates (like relocatable call indexes),
+/// while re-encoding always uses the shortest form.
+fn encode_function<'a>(code_section_entry: &CodeSectionEntry<'a>, with_offsets: bool) -> EncodedFunction<'a> {
+  let original_offsets = code_section_entry
+    .original_offsets
+    .as_ref()
+    .filter(|original_offsets| with_offsets && original_offsets.len() == code_section_entry.operators.len());
+  if let Some(original_body) = code_section_entry.original_body.filter(|_| !code_section_entry.touched) {
+    let mut encoded_function = EncodedFunction::copied(original_body);
+    if let Some(original_offsets) = original_offsets {
+      encoded_function.offsets = operator_positions(original_body).into_iter().zip(original_offsets.iter().copied()).collect();
+    }
+    return encoded_function;
+  }
+  let locals: Vec<(u32, wasm_encoder::ValType)> = code_section_entry.locals.iter().map(|(index, val_type)| (*index, map_val_type(*val_type))).collect();
+  let mut function = wasm_encoder::Function::new(locals);
+  let mut offsets = vec![];
+  for (position, operator) in code_section_entry.operators.iter().enumerate() {
+    if let Some(original_offsets) = original_offsets {
+      offsets.push((function.byte_len(), original_offsets[position]));
+    }
+    function.instruction(&map_operator(operator.clone()));
+  }
+  EncodedFunction {
+    body: Cow::Owned(function.into_raw_body()),
+    offsets,
+  }
+}
+
+/// Returns positions of operators relative to the start of the function body.
+fn operator_positions(body: &[u8]) -> Vec<usize> {
+  let mut positions = vec![];
+  if let Ok(mut operators_reader) = wasmparser::FunctionBody::new(wasmparser::BinaryReader::new(body, 0)).get_operators_reader() {
+    while !operators_reader.eof() {
+      positions.push(operators_reader.original_position());
+      if operators_reader.read().is_err() {
+        break;
+      }
+    }
+  }
+  positions
+}
+
+/// Returns the offset map of the encoded module, from offsets of operators relative to their function bodies.
+fn offset_map(wasm_bytes: &[u8], offsets: Vec<Vec<(usize, usize)>>) -> OffsetMap {
+  let mut offset_map = OffsetMap::default();
+  let bodies = wasmparser::Parser::new(0).parse_all(wasm_bytes).filter_map(|payload| match payload {
+    Ok(wasmparser::Payload::CodeSectionEntry(body)) => Some(body.range()),
+    _ => None,
+  });
+  for (body, offsets) in bodies.zip(offsets) {
+    let operators = offsets.into_iter().map(|(position, original_offset)| (body.start + position, original_offset)).collect();
+    offset_map.add_function(body, operators);
+  }
+  offset_map
+}
+
+/// Remaining custom sections, ordered by their placement.
+type CustomSections = Peekable<std::vec::IntoIter<CustomSection>>;
+
+/// Encodes the known section, preceded by the custom sections placed before it.
+fn encode_section(module: &mut wasm_encoder::Module, custom_sections: &mut CustomSections, section_id: SectionId, section: &impl wasm_encoder::Section) {
+  encode_custom_sections(module, custom_sections, Some(section_id));
+  module.section(section);
+}
 
-    // Extract the encoded Wasm bytes for this module.
-    Ok(module.finish())
+/// Encodes custom sections placed before the given known section, or all remaining custom sections.
+fn encode_custom_sections(module: &mut wasm_encoder::Module, custom_sections: &mut CustomSections, before: Option<SectionId>) {
+  while let Some(custom_section) = custom_sections.next_if(|custom_section| before.is_none_or(|before| custom_section.after < Some(before))) {
+    module.section(&wasm_encoder::CustomSection {
+      name: Cow::Owned(custom_section.name),
+      data: Cow::Owned(custom_section.data),
+    });
   }
 }
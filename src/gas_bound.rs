@@ -0,0 +1,208 @@
+//! # Worst-case static gas bound
+
+use crate::call_graph::{has_host_table, indirect_call_targets};
+use crate::cfg::ControlFlowGraph;
+use crate::metering::Metering;
+use crate::{CallGraph, CostSchedule, Model, WasmarinResult};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use wasmparser::Operator;
+
+/// Reason why the points consumed by a function can not be bounded statically.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnboundedReason {
+  /// Loop with statically unknown trip count.
+  Loop { function_index: u32, position: usize },
+  /// Function calling itself, directly or through other functions.
+  Recursion { function_index: u32 },
+  /// Bulk operation whose cost depends on runtime data.
+  BulkOperation { function_index: u32, position: usize },
+  /// Indirect call through a table that can be modified by the host.
+  HostTable { function_index: u32, position: usize },
+  /// Bound does not fit in the range of points.
+  Overflow { function_index: u32 },
+}
+
+impl fmt::Display for UnboundedReason {
+  /// Implementation of [Display](fmt::Display) trait for [UnboundedReason].
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Loop { function_index, position } => write!(f, "loop at operator {position} in function {function_index}"),
+      Self::Recursion { function_index } => write!(f, "recursion in function {function_index}"),
+      Self::BulkOperation { function_index, position } => write!(f, "data-dependent bulk operation at operator {position} in function {function_index}"),
+      Self::HostTable { function_index, position } => {
+        write!(
+          f,
+          "indirect call through a table accessible to the host at operator {position} in function {function_index}"
+        )
+      }
+      Self::Overflow { function_index } => write!(f, "bound overflow in function {function_index}"),
+    }
+  }
+}
+
+/// Worst-case number of points consumed by a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GasBound {
+  /// Maximum number of points any execution path can consume.
+  Bounded(i64),
+  /// Consumed points can not be bounded statically.
+  Unbounded(UnboundedReason),
+}
+
+/// Returns the worst-case number of points consumed by each exported function, keyed by export name.
+///
+/// Points are calculated exactly as charged by the metering injected by the encoder, so the bound is the maximum
+/// over all execution paths, including paths leaving functions by exceptions. Loops with statically known trip count
+/// are bounded whether or not counted loop hoisting is enabled, because hoisting does not change the charged points.
+/// Imported functions are assumed to consume no points. The cost of instantiation is not included,
+/// see [CostSchedule::instantiation_cost].
+pub fn export_gas_bounds<'a>(model: &'a Model, cost_schedule: &CostSchedule) -> WasmarinResult<BTreeMap<&'a str, GasBound>> {
+  let bounds = function_gas_bounds(model, cost_schedule)?;
+  Ok(
+    model
+      .exports
+      .iter()
+      .filter(|export| export.kind == wasmparser::ExternalKind::Func)
+      .map(|export| (export.name.as_str(), bounds[export.index as usize].clone()))
+      .collect(),
+  )
+}
+
+/// Returns the worst-case number of points consumed by each function, indexed by function index.
+///
+/// Returns an error when control structures of a function body are malformed.
+pub fn function_gas_bounds(model: &Model, cost_schedule: &CostSchedule) -> WasmarinResult<Vec<GasBound>> {
+  let mut metering = Metering::new_with_cost_schedule(cost_schedule.clone()).with_counted_loop_hoisting(true);
+  metering.update_segments(&model.data, &model.elements);
+  let call_graph = CallGraph::new(model);
+  let indirect_targets = indirect_call_targets(model);
+  let host_table = has_host_table(model);
+  let mut bounds = vec![GasBound::Bounded(0); call_graph.callees.len()];
+  // Callees are analyzed before their callers.
+  for component in call_graph.strongly_connected_components() {
+    let function_index = component[0];
+    if call_graph.is_import(function_index) {
+      continue;
+    }
+    if component.len() > 1 || call_graph.callees[function_index as usize].contains(&function_index) {
+      for function_index in component {
+        bounds[function_index as usize] = GasBound::Unbounded(UnboundedReason::Recursion { function_index });
+      }
+      continue;
+    }
+    let operators = &model.code_section_entries[(function_index - call_graph.imported_function_count) as usize].operators;
+    let analysis = FunctionAnalysis {
+      model,
+      metering: &metering,
+      bounds: &bounds,
+      indirect_targets: &indirect_targets,
+      host_table,
+      function_index,
+    };
+    bounds[function_index as usize] = match analysis.run(operators)? {
+      Ok(bound) => GasBound::Bounded(bound),
+      Err(reason) => GasBound::Unbounded(reason),
+    };
+  }
+  Ok(bounds)
+}
+
+/// Analysis of a single function, all called functions are already analyzed.
+struct FunctionAnalysis<'a, 'b> {
+  model: &'a Model<'b>,
+  metering: &'a Metering,
+  bounds: &'a [GasBound],
+  indirect_targets: &'a [u32],
+  host_table: bool,
+  function_index: u32,
+}
+
+impl FunctionAnalysis<'_, '_> {
+  /// Returns the maximum number of points consumed on any path through the function,
+  /// or an error when the control structures of the function body are malformed.
+  fn run(&self, operators: &[Operator]) -> WasmarinResult<Result<i64, UnboundedReason>> {
+    let cfg = ControlFlowGraph::new(operators)?;
+    let charges = self.metering.charges(operators)?;
+    Ok(self.bound(operators, &cfg, &charges))
+  }
+
+  /// Returns the maximum number of points consumed on any path through the control-flow graph.
+  fn bound(&self, operators: &[Operator], cfg: &ControlFlowGraph, charges: &HashMap<usize, i64>) -> Result<i64, UnboundedReason> {
+    let hoisted_loops = self.metering.hoisted_loops(operators);
+    let overflow = UnboundedReason::Overflow {
+      function_index: self.function_index,
+    };
+    // Blocks are ordered so that all edges except branches back to loop headers lead forward.
+    let mut maximums: Vec<Option<i64>> = vec![None; cfg.blocks.len()];
+    let mut bound = 0;
+    for (index, block) in cfg.blocks.iter().enumerate() {
+      if !block.reachable {
+        continue;
+      }
+      if cfg.is_loop_header(index) {
+        let hoisted = block.operators.start.checked_sub(1).is_some_and(|position| hoisted_loops.contains_key(&position));
+        if !hoisted && block.predecessors.iter().any(|predecessor| *predecessor >= index) {
+          return Err(UnboundedReason::Loop {
+            function_index: self.function_index,
+            position: block.operators.start - 1,
+          });
+        }
+      }
+      let entry = block
+        .predecessors
+        .iter()
+        .filter(|predecessor| **predecessor < index)
+        .filter_map(|predecessor| maximums[*predecessor])
+        .max()
+        .unwrap_or_default();
+      let mut cost = charges.get(&block.operators.start).copied().unwrap_or_default();
+      for position in block.operators.clone() {
+        cost = cost.checked_add(self.operator_bound(&operators[position], position)?).ok_or(overflow.clone())?;
+      }
+      let maximum = entry.checked_add(cost).ok_or(overflow.clone())?;
+      maximums[index] = Some(maximum);
+      bound = bound.max(maximum);
+    }
+    Ok(bound)
+  }
+
+  /// Returns the points consumed by functions called by the operator.
+  fn operator_bound(&self, operator: &Operator, position: usize) -> Result<i64, UnboundedReason> {
+    match operator {
+      Operator::Call { function_index } | Operator::ReturnCall { function_index } => self.callee_bound(*function_index),
+      Operator::CallIndirect { type_index, .. } | Operator::ReturnCallIndirect { type_index, .. } => {
+        if self.host_table {
+          return Err(UnboundedReason::HostTable {
+            function_index: self.function_index,
+            position,
+          });
+        }
+        let func_type = self.model.func_type(*type_index);
+        let mut bound = 0;
+        for target in self.indirect_targets.iter().filter(|target| self.model.function_type(**target) == func_type) {
+          bound = bound.max(self.callee_bound(*target)?);
+        }
+        Ok(bound)
+      }
+      Operator::MemoryInit { .. }
+      | Operator::MemoryCopy { .. }
+      | Operator::MemoryFill { .. }
+      | Operator::TableInit { .. }
+      | Operator::TableCopy { .. }
+      | Operator::TableFill { .. } => Err(UnboundedReason::BulkOperation {
+        function_index: self.function_index,
+        position,
+      }),
+      _ => Ok(0),
+    }
+  }
+
+  /// Returns the bound of the called function, or the reason why the called function is unbounded.
+  fn callee_bound(&self, callee: u32) -> Result<i64, UnboundedReason> {
+    match &self.bounds[callee as usize] {
+      GasBound::Bounded(bound) => Ok(*bound),
+      GasBound::Unbounded(reason) => Err(reason.clone()),
+    }
+  }
+}
@@ -0,0 +1,147 @@
+//! # Host ABI import checks
+
+use crate::{Model, WasmarinError, WasmarinResult};
+use wasmparser::{FuncType, GlobalType, MemoryType, TableType, TypeRef, ValType};
+
+/// Prefix of module names used by WASI imports.
+const WASI_MODULE_PREFIX: &str = "wasi";
+
+/// Host ABI, the set of imports a contract is allowed to use.
+///
+/// Each import is identified by its module and field name. A contract may import only the items
+/// declared here, and the type of each import must match the declared one:
+/// - functions must have exactly the same signature,
+/// - globals must have exactly the same type and mutability,
+/// - memories and tables must fit within the limits provided by the host.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HostAbi {
+  /// Functions provided by the host.
+  pub functions: Vec<(String, String, FuncType)>,
+  /// Globals provided by the host.
+  pub globals: Vec<(String, String, GlobalType)>,
+  /// Memories provided by the host.
+  pub memories: Vec<(String, String, MemoryType)>,
+  /// Tables provided by the host.
+  pub tables: Vec<(String, String, TableType)>,
+}
+
+impl HostAbi {
+  /// Creates a new [HostAbi] allowing no imports.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds a function provided by the host.
+  pub fn with_function(mut self, module: &str, name: &str, params: &[ValType], results: &[ValType]) -> Self {
+    self
+      .functions
+      .push((module.to_string(), name.to_string(), FuncType::new(params.iter().copied(), results.iter().copied())));
+    self
+  }
+
+  /// Adds a global provided by the host.
+  pub fn with_global(mut self, module: &str, name: &str, global_type: GlobalType) -> Self {
+    self.globals.push((module.to_string(), name.to_string(), global_type));
+    self
+  }
+
+  /// Adds a memory provided by the host.
+  pub fn with_memory(mut self, module: &str, name: &str, memory_type: MemoryType) -> Self {
+    self.memories.push((module.to_string(), name.to_string(), memory_type));
+    self
+  }
+
+  /// Adds a table provided by the host.
+  pub fn with_table(mut self, module: &str, name: &str, table_type: TableType) -> Self {
+    self.tables.push((module.to_string(), name.to_string(), table_type));
+    self
+  }
+
+  /// Checks all imports of the model against this ABI.
+  ///
+  /// Returns an error naming the first import that is not provided by the host or has a mismatched type.
+  pub fn check(&self, model: &Model) -> WasmarinResult<()> {
+    for import in &model.imports {
+      let (module, name) = (import.module.as_str(), import.name.as_str());
+      let err_mismatch = |expected: String, actual: String| WasmarinError::new(format!("import {module}::{name} has type {actual}, expected {expected}"));
+      let known = match import.ty {
+        TypeRef::Func(type_index) => find(&self.functions, module, name).map(|expected| {
+          let actual = model
+            .func_type(type_index)
+            .ok_or_else(|| WasmarinError::new(format!("import {module}::{name} has invalid type index {type_index}")))?;
+          if actual != expected {
+            return Err(err_mismatch(expected.to_string(), actual.to_string()));
+          }
+          Ok(())
+        }),
+        TypeRef::Global(actual) => find(&self.globals, module, name).map(|expected| {
+          if actual != *expected {
+            return Err(err_mismatch(global_type_to_string(expected), global_type_to_string(&actual)));
+          }
+          Ok(())
+        }),
+        TypeRef::Memory(actual) => find(&self.memories, module, name).map(|expected| {
+          if !memory_matches(expected, &actual) {
+            return Err(err_mismatch(format!("{expected:?}"), format!("{actual:?}")));
+          }
+          Ok(())
+        }),
+        TypeRef::Table(actual) => find(&self.tables, module, name).map(|expected| {
+          if !table_matches(expected, &actual) {
+            return Err(err_mismatch(format!("{expected:?}"), format!("{actual:?}")));
+          }
+          Ok(())
+        }),
+        TypeRef::Tag(_) => None,
+      };
+      match known {
+        Some(result) => result?,
+        None if module.starts_with(WASI_MODULE_PREFIX) => {
+          return Err(WasmarinError::new(format!("WASI imports are not supported, contract imports {module}::{name}")));
+        }
+        None => return Err(WasmarinError::new(format!("unknown import {module}::{name}"))),
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Returns the type of the item with the given module and field name.
+fn find<'a, T>(items: &'a [(String, String, T)], module: &str, name: &str) -> Option<&'a T> {
+  items.iter().find(|(m, n, _)| m == module && n == name).map(|(_, _, ty)| ty)
+}
+
+/// Returns the global type in text format.
+fn global_type_to_string(global_type: &GlobalType) -> String {
+  if global_type.mutable {
+    format!("(mut {})", global_type.content_type)
+  } else {
+    global_type.content_type.to_string()
+  }
+}
+
+/// Returns `true` iff the memory provided by the host can be imported with the given type.
+fn memory_matches(provided: &MemoryType, imported: &MemoryType) -> bool {
+  provided.memory64 == imported.memory64
+    && provided.shared == imported.shared
+    && provided.page_size_log2 == imported.page_size_log2
+    && limits_match(provided.initial, provided.maximum, imported.initial, imported.maximum)
+}
+
+/// Returns `true` iff the table provided by the host can be imported with the given type.
+fn table_matches(provided: &TableType, imported: &TableType) -> bool {
+  provided.element_type == imported.element_type
+    && provided.table64 == imported.table64
+    && provided.shared == imported.shared
+    && limits_match(provided.initial, provided.maximum, imported.initial, imported.maximum)
+}
+
+/// Returns `true` iff the provided limits fit within the imported ones, like defined by import matching rules.
+fn limits_match(provided_initial: u64, provided_maximum: Option<u64>, imported_initial: u64, imported_maximum: Option<u64>) -> bool {
+  provided_initial >= imported_initial
+    && match (provided_maximum, imported_maximum) {
+      (_, None) => true,
+      (Some(provided_maximum), Some(imported_maximum)) => provided_maximum <= imported_maximum,
+      (None, Some(_)) => false,
+    }
+}
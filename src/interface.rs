@@ -0,0 +1,149 @@
+//! # Contract interface checks
+
+use crate::{Export, Model, REMAINING_POINTS_EXPORT_NAME, START_FUNCTION_EXPORT_NAME};
+use std::fmt;
+use wasmparser::{ExternalKind, FuncType, ValType};
+
+/// Export names reserved for items injected by metering.
+const RESERVED_EXPORT_NAMES: [&str; 2] = [REMAINING_POINTS_EXPORT_NAME, START_FUNCTION_EXPORT_NAME];
+
+/// Violation of the contract interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceViolation {
+  /// Required export is missing.
+  Missing { name: String, kind: ExternalKind },
+  /// Export has a different kind than required.
+  WrongKind { name: String, expected: ExternalKind, actual: ExternalKind },
+  /// Exported function has a different signature than required.
+  WrongSignature { name: String, expected: FuncType, actual: FuncType },
+  /// Export is neither required nor optional.
+  Forbidden { name: String, kind: ExternalKind },
+  /// Export uses a name reserved for metering.
+  Reserved { name: String, kind: ExternalKind },
+}
+
+impl fmt::Display for InterfaceViolation {
+  /// Implementation of [Display](fmt::Display) trait for [InterfaceViolation].
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Missing { name, kind } => write!(f, "missing {} export '{}'", kind_name(*kind), name),
+      Self::WrongKind { name, expected, actual } => write!(f, "export '{}' is a {}, expected a {}", name, kind_name(*actual), kind_name(*expected)),
+      Self::WrongSignature { name, expected, actual } => write!(f, "exported function '{name}' has type {actual}, expected {expected}"),
+      Self::Forbidden { name, kind } => write!(f, "forbidden {} export '{}'", kind_name(*kind), name),
+      Self::Reserved { name, kind } => write!(f, "{} export '{}' uses a name reserved for metering", kind_name(*kind), name),
+    }
+  }
+}
+
+/// Interface a contract must expose, the set of required and optional exports.
+///
+/// Exports using names reserved for metering ([REMAINING_POINTS_EXPORT_NAME], [START_FUNCTION_EXPORT_NAME])
+/// are rejected in modules not instrumented yet, so they can not collide with items injected during instrumentation.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ContractInterface {
+  /// Required exported functions with their signatures.
+  pub functions: Vec<(String, FuncType)>,
+  /// Optional exported functions with their signatures.
+  pub optional_functions: Vec<(String, FuncType)>,
+  /// Names of required exported memories.
+  pub memories: Vec<String>,
+  /// Flag indicating if exports neither required nor optional are allowed.
+  pub allow_extra_exports: bool,
+}
+
+impl ContractInterface {
+  /// Creates a new [ContractInterface] with no exports.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds a required exported function.
+  pub fn with_function(mut self, name: &str, params: &[ValType], results: &[ValType]) -> Self {
+    self.functions.push((name.to_string(), FuncType::new(params.iter().copied(), results.iter().copied())));
+    self
+  }
+
+  /// Adds an optional exported function.
+  pub fn with_optional_function(mut self, name: &str, params: &[ValType], results: &[ValType]) -> Self {
+    self
+      .optional_functions
+      .push((name.to_string(), FuncType::new(params.iter().copied(), results.iter().copied())));
+    self
+  }
+
+  /// Adds a required exported memory.
+  pub fn with_memory(mut self, name: &str) -> Self {
+    self.memories.push(name.to_string());
+    self
+  }
+
+  /// Allows or forbids exports that are neither required nor optional.
+  pub fn with_extra_exports(mut self, allowed: bool) -> Self {
+    self.allow_extra_exports = allowed;
+    self
+  }
+
+  /// Checks the exports of the model against this interface.
+  ///
+  /// Returns all violations found, an empty list means the contract exposes the required interface.
+  pub fn check(&self, model: &Model) -> Vec<InterfaceViolation> {
+    let mut violations = vec![];
+    let required = self.functions.iter().map(|(name, func_type)| (name, ExternalKind::Func, Some(func_type)));
+    let memories = self.memories.iter().map(|name| (name, ExternalKind::Memory, None));
+    for (name, expected, func_type) in required.chain(memories) {
+      match model.exports.iter().find(|export| export.name == *name) {
+        None => violations.push(InterfaceViolation::Missing {
+          name: name.clone(),
+          kind: expected,
+        }),
+        Some(export) => self.check_export(model, export, expected, func_type, &mut violations),
+      }
+    }
+    for export in &model.exports {
+      let name = export.name.clone();
+      if RESERVED_EXPORT_NAMES.contains(&export.name.as_str()) {
+        if model.metering.is_none() {
+          violations.push(InterfaceViolation::Reserved { name, kind: export.kind });
+        }
+      } else if let Some((_, func_type)) = self.optional_functions.iter().find(|(optional, _)| *optional == name) {
+        self.check_export(model, export, ExternalKind::Func, Some(func_type), &mut violations);
+      } else if !self.allow_extra_exports && !self.functions.iter().any(|(required, _)| *required == name) && !self.memories.contains(&name) {
+        violations.push(InterfaceViolation::Forbidden { name, kind: export.kind });
+      }
+    }
+    violations
+  }
+
+  /// Checks the kind and the signature of a single export.
+  fn check_export(&self, model: &Model, export: &Export, expected: ExternalKind, func_type: Option<&FuncType>, violations: &mut Vec<InterfaceViolation>) {
+    let name = export.name.clone();
+    if export.kind != expected {
+      violations.push(InterfaceViolation::WrongKind {
+        name,
+        expected,
+        actual: export.kind,
+      });
+    } else if let Some(expected) = func_type {
+      if let Some(actual) = model.function_type(export.index) {
+        if actual != expected {
+          violations.push(InterfaceViolation::WrongSignature {
+            name,
+            expected: expected.clone(),
+            actual: actual.clone(),
+          });
+        }
+      }
+    }
+  }
+}
+
+/// Returns the name of the external kind, as used in text format.
+fn kind_name(kind: ExternalKind) -> &'static str {
+  match kind {
+    ExternalKind::Func => "func",
+    ExternalKind::Table => "table",
+    ExternalKind::Memory => "memory",
+    ExternalKind::Global => "global",
+    ExternalKind::Tag => "tag",
+  }
+}
@@ -1,16 +1,49 @@
 //! # WebAssembly runtime for decision contracts
 
+mod admission;
+mod call_graph;
+pub mod cfg;
+mod counted_loops;
+mod custom_sections;
+mod editing;
 mod encoder;
 mod errors;
 mod features;
+mod gas_bound;
+mod host_abi;
+mod interface;
 mod mappings;
+mod metadata;
 mod metering;
 mod model;
+mod names;
+mod offset_map;
+mod operators;
+mod owned;
 mod parser;
+mod pass;
+mod remap;
+mod schedule;
+mod tree_shaking;
+mod uninstrument;
 
-pub use encoder::Encoder;
+pub use admission::{AdmissionPolicy, Limit, Violation};
+pub use call_graph::CallGraph;
+pub use custom_sections::{CustomSectionFilter, StrippedSections};
+pub use encoder::{DwarfSections, Encoder, DWARF_SECTION_PREFIX};
 pub use errors::{WasmarinError, WasmarinResult};
 pub use features::Features;
-pub use metering::REMAINING_POINTS_EXPORT_NAME;
-pub use model::{CodeSectionEntry, Model};
-pub use parser::Parser;
+pub use gas_bound::{export_gas_bounds, function_gas_bounds, GasBound, UnboundedReason};
+pub use host_abi::HostAbi;
+pub use interface::{ContractInterface, InterfaceViolation};
+pub use metadata::{MeteringMetadata, METERING_SECTION_NAME};
+pub use metering::{Metering, REMAINING_POINTS_EXPORT_NAME, REMAINING_POINTS_GLOBAL_NAME, START_FUNCTION_EXPORT_NAME};
+pub use model::{CodeSectionEntry, ConstExpr, CustomSection, Data, DataKind, Element, ElementItems, ElementKind, Export, Global, Import, Model, OperandTypes, SectionId, Table};
+pub use names::{NameMap, Names, NAME_SECTION_NAME};
+pub use offset_map::OffsetMap;
+pub use owned::{OwnedBrTable, OwnedCodeSectionEntry, OwnedModel, OwnedOperator};
+pub use parser::{InstrumentedInput, Parser};
+pub use pass::{Pass, Pipeline};
+pub use schedule::CostSchedule;
+pub use tree_shaking::{tree_shake, TreeShaking, TreeShakingReport};
+pub use uninstrument::uninstrument;
@@ -1,27 +1,12 @@
-use crate::{WasmarinError, WasmarinResult};
 use std::borrow::Cow;
 use wasmparser::types::TypeIdentifier;
 
-pub fn map_element_items(element_items: wasmparser::ElementItems) -> WasmarinResult<wasm_encoder::Elements> {
+pub fn map_element_items(element_items: &crate::ElementItems) -> wasm_encoder::Elements<'_> {
   match element_items {
-    wasmparser::ElementItems::Functions(function_identifiers) => Ok(wasm_encoder::Elements::Functions(Cow::Owned(
-      function_identifiers
-        .into_iter()
-        .collect::<Result<Vec<u32>, _>>()
-        .map_err(|e| WasmarinError::new(e.to_string()))?,
-    ))),
-    wasmparser::ElementItems::Expressions(ref_type, const_expressions) => Ok(wasm_encoder::Elements::Expressions(
-      map_ref_type(ref_type),
-      Cow::Owned(
-        const_expressions
-          .into_iter()
-          .collect::<Result<Vec<wasmparser::ConstExpr>, _>>()
-          .map_err(|e| WasmarinError::new(e.to_string()))?
-          .iter()
-          .map(|const_expr| map_const_expr(const_expr.clone()))
-          .collect(),
-      ),
-    )),
+    crate::ElementItems::Functions(function_indexes) => wasm_encoder::Elements::Functions(Cow::Borrowed(function_indexes)),
+    crate::ElementItems::Expressions(ref_type, const_exprs) => {
+      wasm_encoder::Elements::Expressions(map_ref_type(*ref_type), Cow::Owned(const_exprs.iter().map(map_const_expr).collect()))
+    }
   }
 }
 
@@ -66,16 +51,10 @@ pub fn map_handle(handle: wasmparser::Handle) -> wasm_encoder::Handle {
   }
 }
 
-pub fn map_const_expr(const_expr: wasmparser::ConstExpr) -> wasm_encoder::ConstExpr {
-  let mut instructions = vec![];
-  let mut reader = const_expr.get_operators_reader();
-  while let Ok(operator) = reader.read() {
-    instructions.push(map_operator(operator));
-    if reader.is_end_then_eof() {
-      break;
-    }
-  }
-  wasm_encoder::ConstExpr::extended(instructions)
+pub fn map_const_expr(const_expr: &crate::ConstExpr) -> wasm_encoder::ConstExpr {
+  let bytes = const_expr.bytes();
+  // Encoded constant expression is terminated with `end` operator, which is added by the encoder.
+  wasm_encoder::ConstExpr::raw(bytes[..bytes.len() - 1].iter().copied())
 }
 
 pub fn map_export_kind(external_kind: wasmparser::ExternalKind) -> wasm_encoder::ExportKind {
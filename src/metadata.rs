@@ -0,0 +1,63 @@
+//! # Metering metadata embedded in instrumented modules
+
+use crate::{WasmarinError, WasmarinResult};
+use wasm_encoder::Encode;
+
+/// Name of the custom section holding metering metadata of an instrumented module.
+pub const METERING_SECTION_NAME: &str = "wasmarin.metering";
+
+/// Version of the metering section layout.
+const METERING_SECTION_VERSION: u8 = 1;
+
+/// Metering metadata, recorded by the encoder in the [METERING_SECTION_NAME] custom section.
+///
+/// The metadata identifies the configuration a module was instrumented with,
+/// so an instrumented module can be recognized and is never instrumented twice.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MeteringMetadata {
+  /// Identifier of the cost schedule used for instrumentation.
+  pub schedule_id: String,
+  /// Hash of the complete metering configuration, the cost schedule included.
+  pub config_hash: u64,
+  /// Index of the global variable storing remaining points.
+  pub counter_global_index: u32,
+}
+
+impl MeteringMetadata {
+  /// Encodes the metadata into the content of the metering section.
+  pub fn encode(&self) -> Vec<u8> {
+    let mut data = vec![METERING_SECTION_VERSION];
+    self.schedule_id.as_str().encode(&mut data);
+    data.extend_from_slice(&self.config_hash.to_le_bytes());
+    self.counter_global_index.encode(&mut data);
+    data
+  }
+
+  /// Decodes the metadata from the content of the metering section.
+  pub fn decode(data: &[u8]) -> WasmarinResult<Self> {
+    let err_malformed = |e: wasmparser::BinaryReaderError| WasmarinError::new(format!("malformed metering section: {}", e.message()));
+    let mut reader = wasmparser::BinaryReader::new(data, 0);
+    let version = reader.read_u8().map_err(err_malformed)?;
+    if version != METERING_SECTION_VERSION {
+      return Err(WasmarinError::new(format!("unsupported metering section version {version}")));
+    }
+    let schedule_id = reader.read_string().map_err(err_malformed)?.to_string();
+    let config_hash = u64::from_le_bytes(reader.read_bytes(8).map_err(err_malformed)?.try_into().unwrap());
+    let counter_global_index = reader.read_var_u32().map_err(err_malformed)?;
+    if !reader.eof() {
+      return Err(WasmarinError::new("malformed metering section: unexpected trailing bytes"));
+    }
+    Ok(Self {
+      schedule_id,
+      config_hash,
+      counter_global_index,
+    })
+  }
+}
+
+/// Calculates the 64-bit FNV-1a hash of the given bytes.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+  bytes
+    .iter()
+    .fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
@@ -1,171 +1,333 @@
-use crate::mappings::map_operator;
+use crate::cfg::{ControlFlowGraph, EdgeKind};
+use crate::counted_loops::counted_loop;
+use crate::metadata::fnv1a;
+use crate::names::{introduces_label, renumber_labels};
+use crate::schedule::element_segment_length;
+use crate::{CodeSectionEntry, ConstExpr, CostSchedule, Data, Element, Export, Global, MeteringMetadata, Model, Pass, WasmarinResult};
+use std::collections::HashMap;
 
 /// Exported name of the global variable for keeping track of the remaining points.
 pub const REMAINING_POINTS_EXPORT_NAME: &str = "wasmarin_metering_remaining_points";
 
-/// Metering properties.
+/// Name of the global variable for keeping track of the remaining points, recorded in the name section.
+pub const REMAINING_POINTS_GLOBAL_NAME: &str = "wasmarin_remaining_points";
+
+/// Exported name of the original start function of a metered module.
+///
+/// The start function is executed during instantiation, before the host is able to set
+/// the remaining points, so in a metered module it is exported under this name instead.
+/// The host sets the remaining points and calls this function right after instantiation.
+pub const START_FUNCTION_EXPORT_NAME: &str = "wasmarin_metering_start";
+
+/// Number of operators in a single charge sequence.
+pub(crate) const CHARGE_LENGTH: usize = 10;
+
+/// Metering pass, instrumenting the module with charges for executed operators.
+///
+/// The pass adds and exports a global variable storing the remaining points, exports the original start
+/// function instead of starting it, charges the cost of every basic block on its entry
+/// and records the [MeteringMetadata] in the model. Already instrumented modules are left unchanged.
+/// When the module has a name section, the global variable is named [REMAINING_POINTS_GLOBAL_NAME]
+/// and names of labels are renumbered after the blocks of inserted charges.
+#[derive(Clone)]
 pub struct Metering {
-  /// Enables metering functionality.
-  enabled: bool,
   /// Index of a global variable storing remaining points.
   remaining_points_global_index: u32,
-  /// The size of the memory unit (in bytes) for bulk-memory operations.
-  bulk_memory_operation_unit: i64,
+  /// Cost schedule.
+  cost_schedule: CostSchedule,
+  /// Enables charging loops with statically known trip count once, before entering the loop.
+  counted_loop_hoisting: bool,
+  /// Lengths (in bytes) of data segments, indexed by data segment index.
+  data_segment_lengths: Vec<u64>,
+  /// Lengths (in elements) of element segments, indexed by element segment index.
+  element_segment_lengths: Vec<u64>,
+  /// Flag indicating if the currently processed module was already instrumented.
+  instrumented: bool,
 }
 
 impl Default for Metering {
   /// Creates a default [Metering] instance.
   fn default() -> Self {
-    Self::new(false)
+    Self::new()
+  }
+}
+
+impl Pass for Metering {
+  fn name(&self) -> &str {
+    "metering"
+  }
+
+  /// Adds and exports the global variable storing remaining points and takes over the start function.
+  fn update_model(&mut self, model: &mut Model) -> WasmarinResult<()> {
+    self.instrumented = model.metering.is_some();
+    if self.instrumented {
+      return Ok(());
+    }
+    self.update_segments(&model.data, &model.elements);
+    // Imported globals precede defined globals in the global index space.
+    self.remaining_points_global_index = model.imported_global_count() + model.globals.len() as u32;
+    model.globals.push(Global {
+      ty: wasmparser::GlobalType {
+        content_type: wasmparser::ValType::I64,
+        mutable: true,
+        shared: false,
+      },
+      init_expr: ConstExpr::i64_const(0),
+    });
+    if let Some(names) = &mut model.names {
+      names.globals.insert(self.remaining_points_global_index, REMAINING_POINTS_GLOBAL_NAME.to_string());
+    }
+    model.exports.push(Export {
+      name: REMAINING_POINTS_EXPORT_NAME.to_string(),
+      kind: wasmparser::ExternalKind::Global,
+      index: self.remaining_points_global_index,
+    });
+    if let Some(start_function_index) = model.start_function_index.take() {
+      model.exports.push(Export {
+        name: START_FUNCTION_EXPORT_NAME.to_string(),
+        kind: wasmparser::ExternalKind::Func,
+        index: start_function_index,
+      });
+    }
+    model.metering = Some(self.metadata());
+    Ok(())
+  }
+
+  /// Inserts charges at the beginning of basic blocks.
+  fn update_function(&mut self, _function_index: u32, code_section_entry: &mut CodeSectionEntry) -> WasmarinResult<()> {
+    if self.instrumented {
+      return Ok(());
+    }
+    let charges = self.charges(&code_section_entry.operators)?;
+    let operators = std::mem::take(&mut code_section_entry.operators);
+    if !charges.is_empty() {
+      code_section_entry.operand_types = None;
+    }
+    for (position, operator) in operators.into_iter().enumerate() {
+      if let Some(cost) = charges.get(&position) {
+        code_section_entry.operators.extend(self.charge(*cost));
+      }
+      code_section_entry.operators.push(operator);
+    }
+    if let Some(original_offsets) = code_section_entry.original_offsets.as_mut().filter(|_| !charges.is_empty()) {
+      // Charges are mapped to the original operator they precede.
+      let mut offsets = Vec::with_capacity(code_section_entry.operators.len());
+      for (position, offset) in original_offsets.iter().enumerate() {
+        if charges.contains_key(&position) {
+          offsets.extend([*offset; CHARGE_LENGTH]);
+        }
+        offsets.push(*offset);
+      }
+      *original_offsets = offsets;
+    }
+    if !charges.is_empty() && !code_section_entry.label_names.is_empty() {
+      let labels = Self::charge_labels(&code_section_entry.operators, self.remaining_points_global_index);
+      renumber_labels(&mut code_section_entry.label_names, labels.into_iter().map(|charge| (!charge, true)));
+    }
+    Ok(())
+  }
+
+  /// Functions are instrumented independently, so the pass can be copied to other threads.
+  fn fork(&self) -> Option<Box<dyn Pass + Send>> {
+    Some(Box::new(self.clone()))
   }
 }
 
 impl Metering {
   /// Creates a new [Metering] instance.
-  pub fn new(enabled: bool) -> Self {
+  pub fn new() -> Self {
+    Self::new_with_cost_schedule(CostSchedule::default())
+  }
+
+  /// Creates a new [Metering] instance with the specified cost schedule.
+  pub fn new_with_cost_schedule(cost_schedule: CostSchedule) -> Self {
     Self {
-      enabled,
       remaining_points_global_index: 0,
-      bulk_memory_operation_unit: 32,
+      cost_schedule,
+      counted_loop_hoisting: false,
+      data_segment_lengths: vec![],
+      element_segment_lengths: vec![],
+      instrumented: false,
     }
   }
 
-  /// Adds a global variable to keep track of remaining points.
-  pub fn update_global_section(&mut self, global_section: &mut wasm_encoder::GlobalSection) {
-    if self.enabled {
-      self.remaining_points_global_index = global_section.len();
-      global_section.global(
-        wasm_encoder::GlobalType {
-          val_type: wasm_encoder::ValType::I64,
-          mutable: true,
-          shared: false,
-        },
-        &wasm_encoder::ConstExpr::i64_const(0),
-      );
-    }
+  /// Enables or disables charging loops with statically known trip count once, before entering the loop.
+  ///
+  /// Disabled by default, only loops with constant initial value and limit of the counter are recognized.
+  /// Loops emitted by compilers mostly iterate up to values known at runtime or exit early, none of the loops
+  /// of the benchmarked contract `burner.wasm` is recognized, so real contracts are usually instrumented
+  /// the same way with hoisting enabled.
+  pub fn with_counted_loop_hoisting(mut self, enabled: bool) -> Self {
+    self.counted_loop_hoisting = enabled;
+    self
   }
 
-  /// Adds and exports a name of the global variable that keeps track of the remaining points.
-  pub fn update_export_section(&mut self, export_section: &mut wasm_encoder::ExportSection) {
-    if self.enabled {
-      export_section.export(REMAINING_POINTS_EXPORT_NAME, wasm_encoder::ExportKind::Global, self.remaining_points_global_index);
+  /// Returns the metering metadata describing this configuration.
+  pub fn metadata(&self) -> MeteringMetadata {
+    let mut config = vec![];
+    config.extend_from_slice(self.cost_schedule.id.as_bytes());
+    config.push(0);
+    config.extend_from_slice(&self.cost_schedule.bulk_memory_operation_unit.get().to_le_bytes());
+    config.extend_from_slice(&self.cost_schedule.element_operation_unit.get().to_le_bytes());
+    config.extend_from_slice(&self.cost_schedule.instantiation_base_cost.to_le_bytes());
+    config.push(self.counted_loop_hoisting as u8);
+    MeteringMetadata {
+      schedule_id: self.cost_schedule.id.clone(),
+      config_hash: fnv1a(&config),
+      counter_global_index: self.remaining_points_global_index,
     }
   }
 
-  /// Updates function's operator with metering code.
-  pub fn update_function(&mut self, function: &mut wasm_encoder::Function, operators: Vec<wasmparser::Operator>) {
-    if self.enabled {
-      let mut accumulated_cost = 0;
-      for operator in operators {
-        // Accumulate the cost of the operator before taking any other action.
-        accumulated_cost += self.cost(&operator);
-        for op in self.feed(operator, &mut accumulated_cost) {
-          function.instruction(&map_operator(op));
-        }
+  /// Records the sizes of data and element segments, used to calculate static costs of `data.drop` and `elem.drop`.
+  pub(crate) fn update_segments(&mut self, data: &[Data], elements: &[Element]) {
+    self.data_segment_lengths = data.iter().map(|data| data.data.len() as u64).collect();
+    self.element_segment_lengths = elements.iter().map(|element| element_segment_length(&element.items)).collect();
+  }
+
+  /// Returns the costs charged before operators, keyed by the position of the operator.
+  ///
+  /// Charges are placed only at the beginning of basic blocks that can be entered
+  /// from more than one place, like loop headers, branch targets and the function entry.
+  /// A basic block entered only by falling through from the preceding block in the same loop
+  /// is always executed right after that block, so its cost is merged into the charge of the preceding block.
+  /// This way every path through the function is charged at least its exact cost, and the cost of operators
+  /// skipped by a conditional branch leaving a merged region early is charged in advance.
+  pub(crate) fn charges(&self, operators: &[wasmparser::Operator]) -> WasmarinResult<HashMap<usize, i64>> {
+    let cfg = ControlFlowGraph::new(operators)?;
+    let hoisted_loops = self.hoisted_loops(operators);
+    let mut charges = HashMap::new();
+    let mut region: Option<usize> = None;
+    for (index, block) in cfg.blocks.iter().enumerate() {
+      if !cfg.is_reachable(index) || block.operators.start.checked_sub(1).is_some_and(|position| hoisted_loops.contains_key(&position)) {
+        // Unreachable blocks are never executed, and the header of a hoisted loop was already charged before entering the loop.
+        region = None;
+        continue;
       }
-    } else {
-      for operator in operators {
-        function.instruction(&map_operator(operator));
+      let mut cost: i64 = operators[block.operators.clone()].iter().map(|operator| self.cost(operator)).sum();
+      if let Some((_, hoisted_cost)) = hoisted_loops.get(&(block.operators.end - 1)) {
+        cost += hoisted_cost;
+      }
+      match region {
+        Some(start) if self.is_merged(&cfg, index) => *charges.entry(start).or_insert(0) += cost,
+        _ => {
+          region = Some(block.operators.start);
+          charges.insert(block.operators.start, cost);
+        }
       }
     }
+    charges.retain(|_, cost| *cost > 0);
+    Ok(charges)
   }
 
-  fn feed<'a>(&self, operator: wasmparser::Operator<'a>, accumulated_cost: &mut i64) -> Vec<wasmparser::Operator<'a>> {
-    match &operator {
-      wasmparser::Operator::Call { function_index } => {
-        _ = function_index;
-        // println!("DDD: Call, function_index = {}", function_index);
-      }
-      wasmparser::Operator::CallIndirect { type_index, table_index } => {
-        _ = (type_index, table_index)
-        // println!("DDD: CallIndirect, type_index = {}, table_index = {}", type_index, table_index);
+  /// Returns `true` iff the given block is entered only by falling through from the preceding block in the same loop.
+  fn is_merged(&self, cfg: &ControlFlowGraph, index: usize) -> bool {
+    if index == 0 {
+      return false;
+    }
+    let (previous, block) = (&cfg.blocks[index - 1], &cfg.blocks[index]);
+    block.predecessors == [index - 1]
+      && previous.innermost_loop == block.innermost_loop
+      && previous.successors.iter().any(|edge| edge.target == index && edge.kind == EdgeKind::Fallthrough)
+  }
+
+  /// Returns loops charged once before entering them, keyed by the position of the `loop` operator.
+  ///
+  /// Each loop is described by the position of its closing `end` operator and the total cost of all its iterations.
+  /// Loops whose total cost can not be calculated are charged on every iteration as usual.
+  pub(crate) fn hoisted_loops(&self, operators: &[wasmparser::Operator]) -> HashMap<usize, (usize, i64)> {
+    let mut hoisted_loops = HashMap::new();
+    if self.counted_loop_hoisting {
+      for position in 0..operators.len() {
+        if let Some(counted_loop) = counted_loop(operators, position) {
+          let iteration_cost: i64 = operators[position + 1..counted_loop.end].iter().map(|operator| self.cost(operator)).sum();
+          if let Some(cost) = i64::try_from(counted_loop.trip_count).ok().and_then(|trip_count| trip_count.checked_mul(iteration_cost)) {
+            hoisted_loops.insert(position, (counted_loop.end, cost));
+          }
+        }
       }
-      _ => {}
     }
-    if self.is_branching_operator(&operator) && *accumulated_cost > 0 {
-      return vec![
-        wasmparser::Operator::GlobalGet {
-          global_index: self.remaining_points_global_index,
-        },
-        wasmparser::Operator::I64Const { value: *accumulated_cost },
-        wasmparser::Operator::I64Sub,
-        wasmparser::Operator::GlobalSet {
-          global_index: self.remaining_points_global_index,
-        },
-        wasmparser::Operator::GlobalGet {
-          global_index: self.remaining_points_global_index,
-        },
-        wasmparser::Operator::I64Const { value: 0 },
-        wasmparser::Operator::I64LtS,
-        wasmparser::Operator::If {
-          blockty: wasmparser::BlockType::Empty,
+    hoisted_loops
+  }
+
+  /// Returns operators subtracting the given cost from remaining points and trapping when points are exhausted.
+  fn charge<'a>(&self, cost: i64) -> [wasmparser::Operator<'a>; CHARGE_LENGTH] {
+    [
+      wasmparser::Operator::GlobalGet {
+        global_index: self.remaining_points_global_index,
+      },
+      wasmparser::Operator::I64Const { value: cost },
+      wasmparser::Operator::I64Sub,
+      wasmparser::Operator::GlobalSet {
+        global_index: self.remaining_points_global_index,
+      },
+      wasmparser::Operator::GlobalGet {
+        global_index: self.remaining_points_global_index,
+      },
+      wasmparser::Operator::I64Const { value: 0 },
+      wasmparser::Operator::I64LtS,
+      wasmparser::Operator::If {
+        blockty: wasmparser::BlockType::Empty,
+      },
+      wasmparser::Operator::Unreachable,
+      wasmparser::Operator::End,
+    ]
+  }
+
+  /// Returns `true` iff the operators start with a charge sequence built by [Metering::charge]
+  /// using the global variable with the given index.
+  pub(crate) fn is_charge(operators: &[wasmparser::Operator], global_index: u32) -> bool {
+    use wasmparser::Operator::*;
+    matches!(
+      operators,
+      [
+        GlobalGet { global_index: g1 },
+        I64Const { .. },
+        I64Sub,
+        GlobalSet { global_index: g2 },
+        GlobalGet { global_index: g3 },
+        I64Const { value: 0 },
+        I64LtS,
+        If {
+          blockty: wasmparser::BlockType::Empty
         },
-        wasmparser::Operator::Unreachable,
-        wasmparser::Operator::End,
-        operator,
-      ];
-    }
-    if self.is_bulk_memory_operator(&operator) {
-      // more operators
-      // *accumulated_cost = 0;
-      _ = self.bulk_memory_operation_unit;
-      return vec![operator];
+        Unreachable,
+        End,
+        ..
+      ] if [*g1, *g2, *g3] == [global_index; 3]
+    )
+  }
+
+  /// Returns a flag for each label introduced by the operators, in order, indicating whether the label
+  /// belongs to a charge sequence using the global variable with the given index.
+  pub(crate) fn charge_labels(operators: &[wasmparser::Operator], global_index: u32) -> Vec<bool> {
+    let mut labels = vec![];
+    let mut position = 0;
+    while position < operators.len() {
+      if Self::is_charge(&operators[position..], global_index) {
+        labels.push(true);
+        position += CHARGE_LENGTH;
+        continue;
+      }
+      if introduces_label(&operators[position]) {
+        labels.push(false);
+      }
+      position += 1;
     }
-    vec![operator]
+    labels
   }
 
   fn cost(&self, operator: &wasmparser::Operator) -> i64 {
     match operator {
       wasmparser::Operator::End => 0,
+      wasmparser::Operator::DataDrop { data_index } => {
+        let length = self.data_segment_lengths.get(*data_index as usize).copied().unwrap_or_default();
+        self.cost_schedule.segment_cost(length)
+      }
+      wasmparser::Operator::ElemDrop { elem_index } => {
+        let count = self.element_segment_lengths.get(*elem_index as usize).copied().unwrap_or_default();
+        self.cost_schedule.element_segment_cost(count)
+      }
       _ => 1,
     }
   }
-
-  /// Returns `true` iff the given operator is a `branching` operator.
-  fn is_branching_operator(&self, operator: &wasmparser::Operator) -> bool {
-    matches!(
-      operator,
-      wasmparser::Operator::Loop { .. } // loop headers are branch targets
-            | wasmparser::Operator::End // block ends are branch targets
-            | wasmparser::Operator::If { .. } // branch source, "if" can branch to else branch
-            | wasmparser::Operator::Else // 'else' is the end of 'if' branch
-            | wasmparser::Operator::Br { .. } // branch source
-            | wasmparser::Operator::BrTable { .. } // branch source
-            | wasmparser::Operator::BrIf { .. } // branch source
-            | wasmparser::Operator::Call { .. } // function call is a branch source
-            | wasmparser::Operator::CallIndirect { .. } // function call is a branch source
-            | wasmparser::Operator::Return // end of function is a branch source
-            // exceptions proposal
-            | wasmparser::Operator::Throw { .. } // branch source
-            | wasmparser::Operator::ThrowRef // branch source
-            | wasmparser::Operator::Rethrow { .. } // branch source
-            | wasmparser::Operator::Delegate { .. } // branch source
-            | wasmparser::Operator::Catch { .. } // branch target
-            // tail_call proposal
-            | wasmparser::Operator::ReturnCall { .. } // branch source
-            | wasmparser::Operator::ReturnCallIndirect { .. } // branch source
-            // gc proposal
-            | wasmparser::Operator::BrOnCast { .. } // branch source
-            | wasmparser::Operator::BrOnCastFail { .. } // branch source
-            // function_references proposal
-            | wasmparser::Operator::CallRef { .. } // branch source
-            | wasmparser::Operator::ReturnCallRef { .. } // branch source
-            | wasmparser::Operator::BrOnNull { .. } // branch source
-            | wasmparser::Operator::BrOnNonNull { .. } // branch source
-    )
-  }
-
-  /// Returns `true` iff the given operator is a `bulk-memory` operator.
-  fn is_bulk_memory_operator(&self, operator: &wasmparser::Operator) -> bool {
-    matches!(
-      operator,
-      wasmparser::Operator::MemoryInit { .. }
-        | wasmparser::Operator::MemoryFill { .. }
-        | wasmparser::Operator::MemoryCopy { .. }
-        | wasmparser::Operator::TableInit { .. }
-        | wasmparser::Operator::TableCopy { .. }
-        | wasmparser::Operator::DataDrop { .. }
-        | wasmparser::Operator::ElemDrop { .. }
-    )
-  }
 }
@@ -1,25 +1,508 @@
-/// # Intermediate model for parsed WebAssembly code
+//! # Intermediate model for parsed WebAssembly code
 
-#[derive(Default)]
+use crate::mappings::map_operator;
+use crate::{NameMap, Names, WasmarinError, WasmarinResult};
+use wasm_encoder::Encode;
+
+#[derive(Default, Clone)]
 pub struct CodeSectionEntry<'a> {
   pub locals: Vec<(u32, wasmparser::ValType)>,
   pub operators: Vec<wasmparser::Operator<'a>>,
+  /// Types of operands on the stack before each operator, recorded by the parser when requested.
+  ///
+  /// The types describe the operators as parsed, passes changing the operators clear them.
+  pub operand_types: Option<OperandTypes>,
+  /// Original binary encoding of the function body, locals included.
+  ///
+  /// The encoder copies the original encoding of functions not [touched](CodeSectionEntry::touched).
+  pub original_body: Option<&'a [u8]>,
+  /// Flag indicating the locals or operators were changed since the function body was parsed.
+  ///
+  /// The [Pipeline](crate::Pipeline) sets the flag for functions changed by passes, and editing operations set it
+  /// for functions they change, so the encoder does not have to compare the body with its original encoding.
+  /// Code changing the locals or operators directly, outside of passes, must set the flag as well,
+  /// otherwise the original encoding is copied.
+  pub touched: bool,
+  /// Offsets of operators in the original binary, recorded by the parser when requested.
+  ///
+  /// Passes inserting operators keep the offsets in sync, inserted operators get the offset of the operator they precede.
+  /// The offsets are cleared when a pass changes the number of operators without updating them.
+  pub original_offsets: Option<Vec<usize>>,
+  /// Names of locals, from the name section.
+  pub local_names: NameMap,
+  /// Names of labels, from the name section, keyed by the label index in order of block operators.
+  ///
+  /// Passes inserting or removing blocks renumber the names.
+  pub label_names: NameMap,
+}
+
+impl CodeSectionEntry<'_> {
+  /// Clears offsets of operators in the original binary when their number no longer matches the operators.
+  pub(crate) fn clear_stale_original_offsets(&mut self) {
+    if self
+      .original_offsets
+      .as_ref()
+      .is_some_and(|original_offsets| original_offsets.len() != self.operators.len())
+    {
+      self.original_offsets = None;
+    }
+  }
+}
+
+/// Types of operands on the stack before each operator of a function, as seen by the validator.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OperandTypes {
+  /// Start of the operand types before each operator in `types`.
+  starts: Vec<usize>,
+  /// Operand types before all operators, from the bottom to the top of the stack.
+  types: Vec<Option<wasmparser::ValType>>,
+}
+
+impl OperandTypes {
+  /// Returns the number of operators.
+  pub fn len(&self) -> usize {
+    self.starts.len()
+  }
+
+  /// Returns `true` iff there are no operators.
+  pub fn is_empty(&self) -> bool {
+    self.starts.is_empty()
+  }
+
+  /// Returns the types of operands on the stack before the operator at the given position,
+  /// from the bottom to the top of the stack.
+  ///
+  /// The type is `None` for operands of unknown type, pushed in unreachable code.
+  pub fn at(&self, position: usize) -> Option<&[Option<wasmparser::ValType>]> {
+    let start = *self.starts.get(position)?;
+    let end = self.starts.get(position + 1).copied().unwrap_or(self.types.len());
+    Some(&self.types[start..end])
+  }
+
+  /// Appends the types of operands on the stack before the next operator, from the bottom to the top of the stack.
+  pub(crate) fn push(&mut self, types: impl IntoIterator<Item = Option<wasmparser::ValType>>) {
+    self.starts.push(self.types.len());
+    self.types.extend(types);
+  }
+}
+
+/// Constant expression, stored in its binary encoding terminated with the `end` operator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstExpr {
+  bytes: Vec<u8>,
+}
+
+impl ConstExpr {
+  /// Creates a constant expression from the given operators, the final `end` operator excluded.
+  pub fn new(operators: &[wasmparser::Operator]) -> Self {
+    let mut bytes = vec![];
+    for operator in operators {
+      map_operator(operator.clone()).encode(&mut bytes);
+    }
+    wasm_encoder::Instruction::End.encode(&mut bytes);
+    Self { bytes }
+  }
+
+  /// Creates a constant expression producing the given `i32` value.
+  pub fn i32_const(value: i32) -> Self {
+    Self::new(&[wasmparser::Operator::I32Const { value }])
+  }
+
+  /// Creates a constant expression producing the given `i64` value.
+  pub fn i64_const(value: i64) -> Self {
+    Self::new(&[wasmparser::Operator::I64Const { value }])
+  }
+
+  /// Creates a constant expression producing the value of the global with the given index.
+  pub fn global_get(global_index: u32) -> Self {
+    Self::new(&[wasmparser::Operator::GlobalGet { global_index }])
+  }
+
+  /// Creates a constant expression producing a reference to the function with the given index.
+  pub fn ref_func(function_index: u32) -> Self {
+    Self::new(&[wasmparser::Operator::RefFunc { function_index }])
+  }
+
+  /// Returns the binary encoding of the expression, terminated with the `end` operator.
+  pub fn bytes(&self) -> &[u8] {
+    &self.bytes
+  }
+
+  /// Returns the operators of the expression, the final `end` operator excluded.
+  pub fn operators(&self) -> WasmarinResult<Vec<wasmparser::Operator<'_>>> {
+    let mut operators = vec![];
+    let mut reader = wasmparser::ConstExpr::new(wasmparser::BinaryReader::new(&self.bytes, 0)).get_operators_reader();
+    while !reader.is_end_then_eof() {
+      operators.push(reader.read().map_err(|e| WasmarinError::new(e.to_string()))?);
+    }
+    Ok(operators)
+  }
 }
 
-#[derive(Default)]
+impl TryFrom<wasmparser::ConstExpr<'_>> for ConstExpr {
+  type Error = WasmarinError;
+
+  fn try_from(const_expr: wasmparser::ConstExpr) -> WasmarinResult<Self> {
+    let mut reader = const_expr.get_binary_reader();
+    let bytes = reader.read_bytes(reader.bytes_remaining()).map_err(|e| WasmarinError::new(e.to_string()))?;
+    Ok(Self { bytes: bytes.to_vec() })
+  }
+}
+
+/// Imported item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+  /// Name of the module the item is imported from.
+  pub module: String,
+  /// Name of the imported item.
+  pub name: String,
+  /// Type of the imported item.
+  pub ty: wasmparser::TypeRef,
+}
+
+impl From<wasmparser::Import<'_>> for Import {
+  fn from(import: wasmparser::Import) -> Self {
+    Self {
+      module: import.module.to_string(),
+      name: import.name.to_string(),
+      ty: import.ty,
+    }
+  }
+}
+
+/// Exported item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+  /// Name of the export.
+  pub name: String,
+  /// Kind of the exported item.
+  pub kind: wasmparser::ExternalKind,
+  /// Index of the exported item in its index space.
+  pub index: u32,
+}
+
+impl From<wasmparser::Export<'_>> for Export {
+  fn from(export: wasmparser::Export) -> Self {
+    Self {
+      name: export.name.to_string(),
+      kind: export.kind,
+      index: export.index,
+    }
+  }
+}
+
+/// Defined global variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Global {
+  /// Type of the global.
+  pub ty: wasmparser::GlobalType,
+  /// Initial value of the global.
+  pub init_expr: ConstExpr,
+}
+
+impl TryFrom<wasmparser::Global<'_>> for Global {
+  type Error = WasmarinError;
+
+  fn try_from(global: wasmparser::Global) -> WasmarinResult<Self> {
+    Ok(Self {
+      ty: global.ty,
+      init_expr: global.init_expr.try_into()?,
+    })
+  }
+}
+
+/// Defined table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+  /// Type of the table.
+  pub ty: wasmparser::TableType,
+  /// Initial value of the table elements, `None` for null references.
+  pub init_expr: Option<ConstExpr>,
+}
+
+impl TryFrom<wasmparser::Table<'_>> for Table {
+  type Error = WasmarinError;
+
+  fn try_from(table: wasmparser::Table) -> WasmarinResult<Self> {
+    Ok(Self {
+      ty: table.ty,
+      init_expr: match table.init {
+        wasmparser::TableInit::RefNull => None,
+        wasmparser::TableInit::Expr(const_expr) => Some(const_expr.try_into()?),
+      },
+    })
+  }
+}
+
+/// Kind of an element segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElementKind {
+  /// Passive segment.
+  Passive,
+  /// Active segment, initializing the table at the given offset during instantiation.
+  Active { table_index: Option<u32>, offset_expr: ConstExpr },
+  /// Declared segment.
+  Declared,
+}
+
+/// Items of an element segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElementItems {
+  /// Indexes of functions.
+  Functions(Vec<u32>),
+  /// Constant expressions producing references of the given type.
+  Expressions(wasmparser::RefType, Vec<ConstExpr>),
+}
+
+impl ElementItems {
+  /// Returns the number of elements.
+  pub fn len(&self) -> usize {
+    match self {
+      Self::Functions(function_indexes) => function_indexes.len(),
+      Self::Expressions(_, const_exprs) => const_exprs.len(),
+    }
+  }
+
+  /// Returns `true` iff there are no elements.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+/// Element segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element {
+  /// Kind of the segment.
+  pub kind: ElementKind,
+  /// Items of the segment.
+  pub items: ElementItems,
+}
+
+impl TryFrom<wasmparser::Element<'_>> for Element {
+  type Error = WasmarinError;
+
+  fn try_from(element: wasmparser::Element) -> WasmarinResult<Self> {
+    let err = |e: wasmparser::BinaryReaderError| WasmarinError::new(e.to_string());
+    Ok(Self {
+      kind: match element.kind {
+        wasmparser::ElementKind::Passive => ElementKind::Passive,
+        wasmparser::ElementKind::Active { table_index, offset_expr } => ElementKind::Active {
+          table_index,
+          offset_expr: offset_expr.try_into()?,
+        },
+        wasmparser::ElementKind::Declared => ElementKind::Declared,
+      },
+      items: match element.items {
+        wasmparser::ElementItems::Functions(reader) => ElementItems::Functions(reader.into_iter().collect::<Result<_, _>>().map_err(err)?),
+        wasmparser::ElementItems::Expressions(ref_type, reader) => ElementItems::Expressions(
+          ref_type,
+          reader
+            .into_iter()
+            .map(|const_expr| const_expr.map_err(err).and_then(ConstExpr::try_from))
+            .collect::<WasmarinResult<_>>()?,
+        ),
+      },
+    })
+  }
+}
+
+/// Kind of a data segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataKind {
+  /// Passive segment.
+  Passive,
+  /// Active segment, initializing the memory at the given offset during instantiation.
+  Active { memory_index: u32, offset_expr: ConstExpr },
+}
+
+/// Data segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Data {
+  /// Kind of the segment.
+  pub kind: DataKind,
+  /// Content of the segment.
+  pub data: Vec<u8>,
+}
+
+impl TryFrom<wasmparser::Data<'_>> for Data {
+  type Error = WasmarinError;
+
+  fn try_from(data: wasmparser::Data) -> WasmarinResult<Self> {
+    Ok(Self {
+      kind: match data.kind {
+        wasmparser::DataKind::Passive => DataKind::Passive,
+        wasmparser::DataKind::Active { memory_index, offset_expr } => DataKind::Active {
+          memory_index,
+          offset_expr: offset_expr.try_into()?,
+        },
+      },
+      data: data.data.to_vec(),
+    })
+  }
+}
+
+/// Known sections of a module, in the order they appear in a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SectionId {
+  Type,
+  Import,
+  Function,
+  Table,
+  Memory,
+  Tag,
+  Global,
+  Export,
+  Start,
+  Element,
+  DataCount,
+  Code,
+  Data,
+}
+
+/// Custom section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomSection {
+  /// Name of the section.
+  pub name: String,
+  /// Content of the section.
+  pub data: Vec<u8>,
+  /// Known section this custom section follows, `None` when it precedes all known sections.
+  ///
+  /// The custom section is encoded right after the known section or, when the known section is not encoded,
+  /// at the same position relative to other encoded sections.
+  pub after: Option<SectionId>,
+}
+
+impl CustomSection {
+  /// Creates a new custom section placed after all known sections.
+  pub fn new(name: impl Into<String>, data: Vec<u8>) -> Self {
+    Self {
+      name: name.into(),
+      data,
+      after: Some(SectionId::Data),
+    }
+  }
+}
+
+#[derive(Default, Clone)]
 pub struct Model<'a> {
-  pub custom_sections: Vec<(String, Vec<u8>)>,
+  pub custom_sections: Vec<CustomSection>,
   pub rec_groups: Vec<wasmparser::RecGroup>,
-  pub imports: Vec<wasmparser::Import<'a>>,
+  pub imports: Vec<Import>,
   pub function_indexes: Vec<u32>,
-  pub tables: Vec<wasmparser::Table<'a>>,
+  pub tables: Vec<Table>,
   pub memory_types: Vec<wasmparser::MemoryType>,
-  pub globals: Vec<wasmparser::Global<'a>>,
-  pub exports: Vec<wasmparser::Export<'a>>,
+  pub globals: Vec<Global>,
+  pub exports: Vec<Export>,
   pub start_function_index: Option<u32>,
   pub tag_types: Vec<wasmparser::TagType>,
-  pub elements: Vec<wasmparser::Element<'a>>,
-  pub data: Vec<wasmparser::Data<'a>>,
+  pub elements: Vec<Element>,
+  pub data: Vec<Data>,
   pub data_count: Option<u32>,
   pub code_section_entries: Vec<CodeSectionEntry<'a>>,
+  /// Original binary encoding of the code section content, without the section identifier and size.
+  ///
+  /// The encoder compares the encoded code section with it to detect changed function bodies.
+  pub original_code_section: Option<&'a [u8]>,
+  /// Metering metadata of an instrumented module, read from the input or set by [Metering](crate::Metering).
+  ///
+  /// Encoded as the custom section [METERING_SECTION_NAME](crate::METERING_SECTION_NAME), following all other custom sections.
+  pub metering: Option<crate::MeteringMetadata>,
+  /// Decoded name section, `None` when the input has no name section.
+  ///
+  /// Local and label names of defined functions are kept with their bodies, see [CodeSectionEntry::local_names]
+  /// and [CodeSectionEntry::label_names].
+  pub names: Option<Names>,
+}
+
+impl Model<'_> {
+  /// Returns the number of imported functions.
+  ///
+  /// Imported functions precede defined functions in the function index space.
+  pub fn imported_function_count(&self) -> u32 {
+    self.imports.iter().filter(|import| matches!(import.ty, wasmparser::TypeRef::Func(_))).count() as u32
+  }
+
+  /// Returns the number of imported globals.
+  ///
+  /// Imported globals precede defined globals in the global index space.
+  pub fn imported_global_count(&self) -> u32 {
+    self.imports.iter().filter(|import| matches!(import.ty, wasmparser::TypeRef::Global(_))).count() as u32
+  }
+
+  /// Returns the number of all functions, imported and defined.
+  pub fn function_count(&self) -> u32 {
+    self.imported_function_count() + self.function_indexes.len() as u32
+  }
+
+  /// Returns the function type with the given type index.
+  pub fn func_type(&self, type_index: u32) -> Option<&wasmparser::FuncType> {
+    let sub_type = self.rec_groups.iter().flat_map(|rec_group| rec_group.types()).nth(type_index as usize)?;
+    match &sub_type.composite_type.inner {
+      wasmparser::CompositeInnerType::Func(func_type) => Some(func_type),
+      _ => None,
+    }
+  }
+
+  /// Returns the type index of the function with the given function index, imported functions included.
+  pub fn function_type_index(&self, function_index: u32) -> Option<u32> {
+    let mut imported_functions = self.imports.iter().filter_map(|import| match import.ty {
+      wasmparser::TypeRef::Func(type_index) => Some(type_index),
+      _ => None,
+    });
+    match imported_functions.nth(function_index as usize) {
+      Some(type_index) => Some(type_index),
+      None => self.function_indexes.get((function_index - self.imported_function_count()) as usize).copied(),
+    }
+  }
+
+  /// Returns the type of the function with the given function index, imported functions included.
+  pub fn function_type(&self, function_index: u32) -> Option<&wasmparser::FuncType> {
+    self.func_type(self.function_type_index(function_index)?)
+  }
+
+  /// Returns the type of the memory with the given memory index, imported memories included.
+  pub fn memory_type(&self, memory_index: u32) -> Option<&wasmparser::MemoryType> {
+    self
+      .imports
+      .iter()
+      .filter_map(|import| match &import.ty {
+        wasmparser::TypeRef::Memory(memory_type) => Some(memory_type),
+        _ => None,
+      })
+      .chain(&self.memory_types)
+      .nth(memory_index as usize)
+  }
+
+  /// Returns the type of the table with the given table index, imported tables included.
+  pub fn table_type(&self, table_index: u32) -> Option<&wasmparser::TableType> {
+    self
+      .imports
+      .iter()
+      .filter_map(|import| match &import.ty {
+        wasmparser::TypeRef::Table(table_type) => Some(table_type),
+        _ => None,
+      })
+      .chain(self.tables.iter().map(|table| &table.ty))
+      .nth(table_index as usize)
+  }
+
+  /// Returns the type of addresses in the memory with the given memory index, `i64` for 64-bit memories.
+  pub fn memory_index_type(&self, memory_index: u32) -> Option<wasmparser::ValType> {
+    self.memory_type(memory_index).map(|memory_type| index_type(memory_type.memory64))
+  }
+
+  /// Returns the type of element indexes in the table with the given table index, `i64` for 64-bit tables.
+  pub fn table_index_type(&self, table_index: u32) -> Option<wasmparser::ValType> {
+    self.table_type(table_index).map(|table_type| index_type(table_type.table64))
+  }
+}
+
+/// Returns the index type of a 32-bit or 64-bit memory or table.
+fn index_type(is_64: bool) -> wasmparser::ValType {
+  if is_64 {
+    wasmparser::ValType::I64
+  } else {
+    wasmparser::ValType::I32
+  }
 }
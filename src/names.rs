@@ -0,0 +1,217 @@
+//! # Names of the module and its items, from the name custom section
+
+use crate::{CodeSectionEntry, CustomSection, SectionId, WasmarinError, WasmarinResult};
+use std::collections::BTreeMap;
+use wasmparser::{IndirectNameMap, Name, Operator};
+
+/// Name of the custom section holding names of the module and its items.
+pub const NAME_SECTION_NAME: &str = "name";
+
+/// Names of items, keyed by the index of the named item.
+pub type NameMap = BTreeMap<u32, String>;
+
+/// Names of the module and its items, parsed from the [NAME_SECTION_NAME] custom section.
+///
+/// Local and label names of defined functions are kept with their function bodies, see [CodeSectionEntry::local_names]
+/// and [CodeSectionEntry::label_names], so they stay attached to the function when functions are added, removed or
+/// instrumented. Local and label names of imported functions are dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Names {
+  /// Name of the module.
+  pub module: Option<String>,
+  /// Names of functions, imported and defined.
+  pub functions: NameMap,
+  /// Names of types.
+  pub types: NameMap,
+  /// Names of tables.
+  pub tables: NameMap,
+  /// Names of memories.
+  pub memories: NameMap,
+  /// Names of globals.
+  pub globals: NameMap,
+  /// Names of element segments.
+  pub elements: NameMap,
+  /// Names of data segments.
+  pub data: NameMap,
+  /// Names of tags.
+  pub tags: NameMap,
+  /// Names of fields of struct types, keyed by the type index.
+  pub fields: BTreeMap<u32, NameMap>,
+  /// Subsections not known to the parser, with their identifiers, encoded after all known subsections.
+  pub unknown: Vec<(u8, Vec<u8>)>,
+  /// Known section the name section follows, see [CustomSection::after].
+  ///
+  /// The name section is encoded before other custom sections with the same placement.
+  pub after: Option<SectionId>,
+}
+
+impl Default for Names {
+  /// Creates empty names, placed after all known sections.
+  fn default() -> Self {
+    Self {
+      module: None,
+      functions: NameMap::new(),
+      types: NameMap::new(),
+      tables: NameMap::new(),
+      memories: NameMap::new(),
+      globals: NameMap::new(),
+      elements: NameMap::new(),
+      data: NameMap::new(),
+      tags: NameMap::new(),
+      fields: BTreeMap::new(),
+      unknown: vec![],
+      after: Some(SectionId::Data),
+    }
+  }
+}
+
+impl Names {
+  /// Creates empty names, placed after all known sections.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Decodes names from the content of the name section.
+  ///
+  /// Local and label names of defined functions are moved to their function bodies,
+  /// the function bodies are left unchanged when the name section is malformed.
+  pub(crate) fn decode(data: &[u8], imported_function_count: u32, code_section_entries: &mut [CodeSectionEntry]) -> WasmarinResult<Self> {
+    let err_malformed = |e: wasmparser::BinaryReaderError| WasmarinError::new(format!("malformed name section: {}", e.message()));
+    let mut names = Self::default();
+    let mut local_names = BTreeMap::new();
+    let mut label_names = BTreeMap::new();
+    for subsection in wasmparser::NameSectionReader::new(wasmparser::BinaryReader::new(data, 0)) {
+      match subsection.map_err(err_malformed)? {
+        Name::Module { name, .. } => names.module = Some(name.to_string()),
+        Name::Function(name_map) => names.functions = decode_name_map(name_map).map_err(err_malformed)?,
+        Name::Local(indirect_name_map) => local_names = decode_indirect_name_map(indirect_name_map).map_err(err_malformed)?,
+        Name::Label(indirect_name_map) => label_names = decode_indirect_name_map(indirect_name_map).map_err(err_malformed)?,
+        Name::Type(name_map) => names.types = decode_name_map(name_map).map_err(err_malformed)?,
+        Name::Table(name_map) => names.tables = decode_name_map(name_map).map_err(err_malformed)?,
+        Name::Memory(name_map) => names.memories = decode_name_map(name_map).map_err(err_malformed)?,
+        Name::Global(name_map) => names.globals = decode_name_map(name_map).map_err(err_malformed)?,
+        Name::Element(name_map) => names.elements = decode_name_map(name_map).map_err(err_malformed)?,
+        Name::Data(name_map) => names.data = decode_name_map(name_map).map_err(err_malformed)?,
+        Name::Field(indirect_name_map) => names.fields = decode_indirect_name_map(indirect_name_map).map_err(err_malformed)?,
+        Name::Tag(name_map) => names.tags = decode_name_map(name_map).map_err(err_malformed)?,
+        Name::Unknown { ty, data, .. } => names.unknown.push((ty, data.to_vec())),
+      }
+    }
+    for (index, code_section_entry) in code_section_entries.iter_mut().enumerate() {
+      let function_index = imported_function_count + index as u32;
+      code_section_entry.local_names = local_names.remove(&function_index).unwrap_or_default();
+      code_section_entry.label_names = label_names.remove(&function_index).unwrap_or_default();
+    }
+    Ok(names)
+  }
+
+  /// Encodes the names, including local and label names of defined functions, into the name section.
+  pub(crate) fn encode(&self, imported_function_count: u32, code_section_entries: &[CodeSectionEntry]) -> CustomSection {
+    let function_indexes = (imported_function_count..).zip(code_section_entries);
+    let local_names = encode_indirect_name_map(
+      function_indexes
+        .clone()
+        .map(|(function_index, code_section_entry)| (function_index, &code_section_entry.local_names)),
+    );
+    let label_names = encode_indirect_name_map(function_indexes.map(|(function_index, code_section_entry)| (function_index, &code_section_entry.label_names)));
+    let fields = encode_indirect_name_map(self.fields.iter().map(|(type_index, name_map)| (*type_index, name_map)));
+    // Subsections are encoded in order of their identifiers, empty subsections are omitted.
+    let mut name_section = wasm_encoder::NameSection::new();
+    if let Some(module) = &self.module {
+      name_section.module(module);
+    }
+    encode_subsection(&self.functions, |name_map| name_section.functions(name_map));
+    if let Some(local_names) = &local_names {
+      name_section.locals(local_names);
+    }
+    if let Some(label_names) = &label_names {
+      name_section.labels(label_names);
+    }
+    encode_subsection(&self.types, |name_map| name_section.types(name_map));
+    encode_subsection(&self.tables, |name_map| name_section.tables(name_map));
+    encode_subsection(&self.memories, |name_map| name_section.memories(name_map));
+    encode_subsection(&self.globals, |name_map| name_section.globals(name_map));
+    encode_subsection(&self.elements, |name_map| name_section.elements(name_map));
+    encode_subsection(&self.data, |name_map| name_section.data(name_map));
+    if let Some(fields) = &fields {
+      name_section.fields(fields);
+    }
+    encode_subsection(&self.tags, |name_map| name_section.tag(name_map));
+    for (id, data) in &self.unknown {
+      name_section.raw(*id, data);
+    }
+    CustomSection {
+      name: NAME_SECTION_NAME.to_string(),
+      data: name_section.as_custom().data.into_owned(),
+      after: self.after,
+    }
+  }
+}
+
+/// Returns `true` iff the operator introduces a label, labels are numbered in order of these operators.
+pub(crate) fn introduces_label(operator: &Operator) -> bool {
+  matches!(
+    operator,
+    Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } | Operator::Try { .. } | Operator::TryTable { .. }
+  )
+}
+
+/// Renumbers label names of a function after labels were inserted or removed.
+///
+/// Each item describes a single label, in order of appearance, by flags indicating whether the label existed
+/// before and whether it exists after the change. Names of removed labels are dropped.
+pub(crate) fn renumber_labels(label_names: &mut NameMap, labels: impl IntoIterator<Item = (bool, bool)>) {
+  let mut renumbered = NameMap::new();
+  let (mut old_index, mut new_index) = (0, 0);
+  for (before, after) in labels {
+    if before && after {
+      if let Some(name) = label_names.remove(&old_index) {
+        renumbered.insert(new_index, name);
+      }
+    }
+    old_index += before as u32;
+    new_index += after as u32;
+  }
+  *label_names = renumbered;
+}
+
+/// Decodes names of items, keyed by item indexes.
+fn decode_name_map(name_map: wasmparser::NameMap) -> Result<NameMap, wasmparser::BinaryReaderError> {
+  name_map.into_iter().map(|naming| naming.map(|naming| (naming.index, naming.name.to_string()))).collect()
+}
+
+/// Decodes names of nested items, keyed by indexes of the outer items.
+fn decode_indirect_name_map(indirect_name_map: IndirectNameMap) -> Result<BTreeMap<u32, NameMap>, wasmparser::BinaryReaderError> {
+  indirect_name_map
+    .into_iter()
+    .map(|indirect_naming| {
+      let indirect_naming = indirect_naming?;
+      Ok((indirect_naming.index, decode_name_map(indirect_naming.names)?))
+    })
+    .collect()
+}
+
+/// Returns names of items encoded in order of item indexes.
+fn encode_name_map(name_map: &NameMap) -> wasm_encoder::NameMap {
+  let mut encoded = wasm_encoder::NameMap::new();
+  for (index, name) in name_map {
+    encoded.append(*index, name);
+  }
+  encoded
+}
+
+/// Returns names of nested items encoded in order of outer item indexes, or `None` when no items are named.
+fn encode_indirect_name_map<'n>(name_maps: impl Iterator<Item = (u32, &'n NameMap)>) -> Option<wasm_encoder::IndirectNameMap> {
+  let mut encoded = None;
+  for (index, name_map) in name_maps.filter(|(_, name_map)| !name_map.is_empty()) {
+    encoded.get_or_insert_with(wasm_encoder::IndirectNameMap::new).append(index, &encode_name_map(name_map));
+  }
+  encoded
+}
+
+/// Encodes the subsection only when it names any items.
+fn encode_subsection(name_map: &NameMap, mut encode: impl FnMut(&wasm_encoder::NameMap)) {
+  if !name_map.is_empty() {
+    encode(&encode_name_map(name_map));
+  }
+}
@@ -0,0 +1,102 @@
+//! # Mapping of code offsets in the encoded module back to the original binary
+
+use crate::{WasmarinError, WasmarinResult};
+use std::ops::Range;
+use wasm_encoder::Encode;
+
+/// Version of the encoded offset map layout.
+const OFFSET_MAP_VERSION: u8 = 1;
+
+/// Offsets of operators of a single function body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FunctionOffsets {
+  /// Range of the function body in the encoded module, locals included.
+  body: Range<usize>,
+  /// Offsets of operators in the encoded module paired with their offsets in the original binary, ordered by offsets in the encoded module.
+  operators: Vec<(usize, usize)>,
+}
+
+/// Mapping of operator offsets in the encoded module back to operator offsets in the original binary.
+///
+/// Built by the encoder when requested with [Encoder::with_offset_map](crate::Encoder::with_offset_map),
+/// so the offsets reported by engines, for example in trap backtraces of an instrumented module,
+/// can be translated to offsets in the module uploaded by the contract author.
+/// All offsets are counted from the beginning of the module.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OffsetMap {
+  /// Offsets of functions with known original offsets, ordered by their bodies in the encoded module.
+  functions: Vec<FunctionOffsets>,
+}
+
+impl OffsetMap {
+  /// Records offsets of operators of a function body, function bodies must be added in order of their offsets.
+  pub(crate) fn add_function(&mut self, body: Range<usize>, operators: Vec<(usize, usize)>) {
+    if !operators.is_empty() {
+      self.functions.push(FunctionOffsets { body, operators });
+    }
+  }
+
+  /// Returns the offset in the original binary of the operator at the given offset in the encoded module.
+  ///
+  /// Offsets inside the encoding of an operator are mapped as the offset of the operator.
+  /// Operators inserted by instrumentation are mapped to the original operator they precede.
+  /// Returns `None` for offsets outside of operators of functions with known original offsets.
+  pub fn original_offset(&self, offset: usize) -> Option<usize> {
+    let index = self.functions.partition_point(|function| function.body.end <= offset);
+    let function = self.functions.get(index).filter(|function| function.body.contains(&offset))?;
+    let position = function.operators.partition_point(|(encoded, _)| *encoded <= offset).checked_sub(1)?;
+    Some(function.operators[position].1)
+  }
+
+  /// Returns all operator offsets in the encoded module paired with their offsets in the original binary, in order.
+  pub fn offsets(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+    self.functions.iter().flat_map(|function| function.operators.iter().copied())
+  }
+
+  /// Returns `true` iff no offsets are mapped.
+  pub fn is_empty(&self) -> bool {
+    self.functions.is_empty()
+  }
+
+  /// Encodes the offset map, so it can be stored along with the encoded module.
+  pub fn encode(&self) -> Vec<u8> {
+    let mut data = vec![OFFSET_MAP_VERSION];
+    (self.functions.len() as u32).encode(&mut data);
+    for function in &self.functions {
+      (function.body.start as u32).encode(&mut data);
+      (function.body.len() as u32).encode(&mut data);
+      (function.operators.len() as u32).encode(&mut data);
+      for (encoded, original) in &function.operators {
+        ((encoded - function.body.start) as u32).encode(&mut data);
+        (*original as u32).encode(&mut data);
+      }
+    }
+    data
+  }
+
+  /// Decodes the offset map encoded by [OffsetMap::encode].
+  pub fn decode(data: &[u8]) -> WasmarinResult<Self> {
+    let err_malformed = |e: wasmparser::BinaryReaderError| WasmarinError::new(format!("malformed offset map: {}", e.message()));
+    let mut reader = wasmparser::BinaryReader::new(data, 0);
+    let version = reader.read_u8().map_err(err_malformed)?;
+    if version != OFFSET_MAP_VERSION {
+      return Err(WasmarinError::new(format!("unsupported offset map version {version}")));
+    }
+    let mut offset_map = Self::default();
+    for _ in 0..reader.read_var_u32().map_err(err_malformed)? {
+      let start = reader.read_var_u32().map_err(err_malformed)? as usize;
+      let body = start..start + reader.read_var_u32().map_err(err_malformed)? as usize;
+      let mut operators = vec![];
+      for _ in 0..reader.read_var_u32().map_err(err_malformed)? {
+        let encoded = start + reader.read_var_u32().map_err(err_malformed)? as usize;
+        let original = reader.read_var_u32().map_err(err_malformed)? as usize;
+        operators.push((encoded, original));
+      }
+      offset_map.add_function(body, operators);
+    }
+    if !reader.eof() {
+      return Err(WasmarinError::new("malformed offset map: unexpected trailing bytes"));
+    }
+    Ok(offset_map)
+  }
+}
@@ -0,0 +1,57 @@
+//! # Parsing operators validated in the same pass
+
+use wasmparser::{BinaryReaderError, FuncValidator, Operator, OperatorsReader, ValType, ValidatorResources, VisitOperator, VisitSimdOperator};
+
+/// Reads the next operator from the reader and validates it, decoding the operator only once.
+pub(crate) fn read_validated_operator<'a>(reader: &mut OperatorsReader<'a>, func_validator: &mut FuncValidator<ValidatorResources>) -> Result<Operator<'a>, BinaryReaderError> {
+  let offset = reader.original_position();
+  let mut visitor = ValidatingVisitor {
+    validator: func_validator.simd_visitor(offset),
+  };
+  reader.visit_operator(&mut visitor)?
+}
+
+/// Returns the types of operands on the stack of the validated function, from the bottom to the top of the stack.
+pub(crate) fn stack_operand_types(func_validator: &FuncValidator<ValidatorResources>) -> impl Iterator<Item = Option<ValType>> + '_ {
+  (0..func_validator.operand_stack_height() as usize)
+    .rev()
+    .map(|depth| func_validator.get_operand_type(depth).flatten())
+}
+
+/// Visitor building operators, each operator is passed to the validator before it is built.
+struct ValidatingVisitor<V> {
+  validator: V,
+}
+
+/// Defines visiting of all operators, generated from the list of operators provided by `wasmparser`.
+macro_rules! define_validating_visit {
+  ($( @$proposal:ident $op:ident $({ $($arg:ident : $argty:ty),* })? => $visit:ident ($($ann:tt)*))*) => {
+    $(
+      fn $visit(&mut self $($(, $arg: $argty)*)?) -> Self::Output {
+        #[allow(clippy::clone_on_copy)]
+        self.validator.$visit($($($arg.clone()),*)?)?;
+        Ok(Operator::$op $({ $($arg),* })?)
+      }
+    )*
+  };
+}
+
+impl<'a, V> VisitOperator<'a> for ValidatingVisitor<V>
+where
+  V: VisitSimdOperator<'a, Output = Result<(), BinaryReaderError>>,
+{
+  type Output = Result<Operator<'a>, BinaryReaderError>;
+
+  fn simd_visitor(&mut self) -> Option<&mut dyn VisitSimdOperator<'a, Output = Self::Output>> {
+    Some(self)
+  }
+
+  wasmparser::for_each_visit_operator!(define_validating_visit);
+}
+
+impl<'a, V> VisitSimdOperator<'a> for ValidatingVisitor<V>
+where
+  V: VisitSimdOperator<'a, Output = Result<(), BinaryReaderError>>,
+{
+  wasmparser::for_each_visit_simd_operator!(define_validating_visit);
+}
@@ -0,0 +1,225 @@
+//! # Owned model, independent of the parsed input
+
+use crate::{CustomSection, Data, Element, Export, Global, Import, MeteringMetadata, Model, NameMap, Names, OperandTypes, Table, WasmarinError, WasmarinResult};
+use wasm_encoder::Encode;
+use wasmparser::Operator;
+
+/// Targets of the `br_table` operator, owned by the operator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedBrTable {
+  /// Branch targets, excluding the default target.
+  targets: Vec<u32>,
+  /// Default branch target.
+  default: u32,
+  /// Binary encoding of the whole `br_table` operator, borrowed by [Operator::BrTable].
+  bytes: Vec<u8>,
+}
+
+impl OwnedBrTable {
+  /// Creates `br_table` targets from the given branch targets and the default target.
+  pub fn new(targets: Vec<u32>, default: u32) -> Self {
+    let mut bytes = vec![];
+    wasm_encoder::Instruction::BrTable(targets.as_slice().into(), default).encode(&mut bytes);
+    Self { targets, default, bytes }
+  }
+
+  /// Returns branch targets, excluding the default target.
+  pub fn targets(&self) -> &[u32] {
+    &self.targets
+  }
+
+  /// Returns the default branch target.
+  pub fn default(&self) -> u32 {
+    self.default
+  }
+
+  /// Returns the `br_table` operator borrowing these targets.
+  pub fn operator(&self) -> Operator<'_> {
+    wasmparser::OperatorsReader::new(wasmparser::BinaryReader::new(&self.bytes, 0))
+      .read()
+      .expect("encoded br_table operator is always valid")
+  }
+}
+
+impl TryFrom<&wasmparser::BrTable<'_>> for OwnedBrTable {
+  type Error = WasmarinError;
+
+  fn try_from(br_table: &wasmparser::BrTable) -> WasmarinResult<Self> {
+    let targets = br_table.targets().collect::<Result<_, _>>().map_err(|e| WasmarinError::new(e.to_string()))?;
+    Ok(Self::new(targets, br_table.default()))
+  }
+}
+
+/// Operator owning all its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OwnedOperator {
+  /// The `br_table` operator, the only operator borrowing the parsed input.
+  BrTable(OwnedBrTable),
+  /// Any other operator.
+  Other(Operator<'static>),
+}
+
+impl OwnedOperator {
+  /// Returns the operator, borrowing the targets of `br_table`.
+  pub fn operator(&self) -> Operator<'_> {
+    match self {
+      Self::BrTable(br_table) => br_table.operator(),
+      Self::Other(operator) => operator.clone(),
+    }
+  }
+}
+
+/// Converts a single operator, selected by the name of the operator.
+macro_rules! owned_operator {
+  (BrTable { $targets:ident }) => {
+    OwnedOperator::BrTable(OwnedBrTable::try_from(&$targets)?)
+  };
+  ($op:ident $({ $($field:ident),* })?) => {
+    OwnedOperator::Other(Operator::$op $({ $($field),* })?)
+  };
+}
+
+/// Defines conversion of all operators, generated from the list of operators provided by `wasmparser`.
+macro_rules! define_owned_operator {
+  ($( @$proposal:ident $op:ident $({ $($field:ident : $field_type:ty),* })? => $visit:ident ($($arity:tt)*))*) => {
+    impl TryFrom<Operator<'_>> for OwnedOperator {
+      type Error = WasmarinError;
+
+      fn try_from(operator: Operator) -> WasmarinResult<Self> {
+        Ok(match operator {
+          $(
+            Operator::$op $({ $($field),* })? => owned_operator!($op $({ $($field),* })?),
+          )*
+          other => return Err(WasmarinError::new(format!("unsupported operator {other:?}"))),
+        })
+      }
+    }
+  };
+}
+
+wasmparser::for_each_operator!(define_owned_operator);
+
+/// Function body owning all its operators.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OwnedCodeSectionEntry {
+  pub locals: Vec<(u32, wasmparser::ValType)>,
+  pub operators: Vec<OwnedOperator>,
+  /// Types of operands on the stack before each operator, see [CodeSectionEntry::operand_types](crate::CodeSectionEntry::operand_types).
+  pub operand_types: Option<OperandTypes>,
+  /// Original binary encoding of the function body, see [CodeSectionEntry::original_body](crate::CodeSectionEntry::original_body).
+  pub original_body: Option<Vec<u8>>,
+  /// Flag indicating the function body was changed, see [CodeSectionEntry::touched](crate::CodeSectionEntry::touched).
+  pub touched: bool,
+  /// Offsets of operators in the original binary, see [CodeSectionEntry::original_offsets](crate::CodeSectionEntry::original_offsets).
+  pub original_offsets: Option<Vec<usize>>,
+  /// Names of locals, see [CodeSectionEntry::local_names](crate::CodeSectionEntry::local_names).
+  pub local_names: NameMap,
+  /// Names of labels, see [CodeSectionEntry::label_names](crate::CodeSectionEntry::label_names).
+  pub label_names: NameMap,
+}
+
+/// Model owning all its data, so it can be cached, modified and sent to other threads.
+///
+/// Converted from the [Model] borrowing the parsed input, the encoder accepts it
+/// through [Encoder::encode_owned](crate::Encoder::encode_owned).
+#[derive(Default, Clone)]
+pub struct OwnedModel {
+  pub custom_sections: Vec<CustomSection>,
+  pub rec_groups: Vec<wasmparser::RecGroup>,
+  pub imports: Vec<Import>,
+  pub function_indexes: Vec<u32>,
+  pub tables: Vec<Table>,
+  pub memory_types: Vec<wasmparser::MemoryType>,
+  pub globals: Vec<Global>,
+  pub exports: Vec<Export>,
+  pub start_function_index: Option<u32>,
+  pub tag_types: Vec<wasmparser::TagType>,
+  pub elements: Vec<Element>,
+  pub data: Vec<Data>,
+  pub data_count: Option<u32>,
+  pub code_section_entries: Vec<OwnedCodeSectionEntry>,
+  /// Original binary encoding of the code section content, see [Model::original_code_section].
+  pub original_code_section: Option<Vec<u8>>,
+  /// Metering metadata of an instrumented module, see [Model::metering].
+  pub metering: Option<MeteringMetadata>,
+  /// Decoded name section, see [Model::names].
+  pub names: Option<Names>,
+}
+
+impl TryFrom<Model<'_>> for OwnedModel {
+  type Error = WasmarinError;
+
+  /// Converts the model borrowing the parsed input into an owned model.
+  fn try_from(model: Model) -> WasmarinResult<Self> {
+    let mut code_section_entries = Vec::with_capacity(model.code_section_entries.len());
+    for code_section_entry in model.code_section_entries {
+      code_section_entries.push(OwnedCodeSectionEntry {
+        locals: code_section_entry.locals,
+        operators: code_section_entry.operators.into_iter().map(OwnedOperator::try_from).collect::<WasmarinResult<_>>()?,
+        operand_types: code_section_entry.operand_types,
+        original_body: code_section_entry.original_body.map(<[u8]>::to_vec),
+        touched: code_section_entry.touched,
+        original_offsets: code_section_entry.original_offsets,
+        local_names: code_section_entry.local_names,
+        label_names: code_section_entry.label_names,
+      });
+    }
+    Ok(Self {
+      custom_sections: model.custom_sections,
+      rec_groups: model.rec_groups,
+      imports: model.imports,
+      function_indexes: model.function_indexes,
+      tables: model.tables,
+      memory_types: model.memory_types,
+      globals: model.globals,
+      exports: model.exports,
+      start_function_index: model.start_function_index,
+      tag_types: model.tag_types,
+      elements: model.elements,
+      data: model.data,
+      data_count: model.data_count,
+      code_section_entries,
+      original_code_section: model.original_code_section.map(<[u8]>::to_vec),
+      metering: model.metering,
+      names: model.names,
+    })
+  }
+}
+
+impl<'a> From<&'a OwnedModel> for Model<'a> {
+  /// Converts the owned model into a model borrowing the owned model.
+  fn from(model: &'a OwnedModel) -> Self {
+    Self {
+      custom_sections: model.custom_sections.clone(),
+      rec_groups: model.rec_groups.clone(),
+      imports: model.imports.clone(),
+      function_indexes: model.function_indexes.clone(),
+      tables: model.tables.clone(),
+      memory_types: model.memory_types.clone(),
+      globals: model.globals.clone(),
+      exports: model.exports.clone(),
+      start_function_index: model.start_function_index,
+      tag_types: model.tag_types.clone(),
+      elements: model.elements.clone(),
+      data: model.data.clone(),
+      data_count: model.data_count,
+      code_section_entries: model
+        .code_section_entries
+        .iter()
+        .map(|code_section_entry| crate::CodeSectionEntry {
+          locals: code_section_entry.locals.clone(),
+          operators: code_section_entry.operators.iter().map(OwnedOperator::operator).collect(),
+          operand_types: code_section_entry.operand_types.clone(),
+          original_body: code_section_entry.original_body.as_deref(),
+          touched: code_section_entry.touched,
+          original_offsets: code_section_entry.original_offsets.clone(),
+          local_names: code_section_entry.local_names.clone(),
+          label_names: code_section_entry.label_names.clone(),
+        })
+        .collect(),
+      original_code_section: model.original_code_section.as_deref(),
+      metering: model.metering.clone(),
+      names: model.names.clone(),
+    }
+  }
+}
@@ -1,7 +1,26 @@
-use crate::{CodeSectionEntry, Features, Model, WasmarinError, WasmarinResult};
+use crate::operators::{read_validated_operator, stack_operand_types};
+use crate::{CodeSectionEntry, CustomSection, Features, MeteringMetadata, Model, Names, OperandTypes, SectionId, WasmarinError, WasmarinResult};
+use crate::{METERING_SECTION_NAME, NAME_SECTION_NAME, REMAINING_POINTS_EXPORT_NAME, START_FUNCTION_EXPORT_NAME};
 use std::ops::Range;
 use std::path::Path;
-use wasmparser::Payload;
+use wasmparser::{ExternalKind, FuncToValidate, FuncValidator, Payload, ValType, ValidatorResources};
+
+/// Handling of input modules already instrumented with metering.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InstrumentedInput {
+  /// Instrumented modules are rejected, like any module defining the reserved exports or the metering section.
+  #[default]
+  Reject,
+  /// Instrumented modules are accepted and encoded without being instrumented again.
+  ///
+  /// Only the consistency of the metering section with the module is checked, the section must locate
+  /// the exported global variable storing remaining points. The injected charges are not verified,
+  /// so a module with a forged metering section and missing or reduced charges is accepted.
+  /// Suitable only for modules instrumented by a trusted party, like modules stored by the host itself.
+  /// Modules from untrusted sources must be rejected, or restored with [uninstrument](crate::uninstrument)
+  /// and instrumented again, which produces the same binary for a genuinely instrumented module.
+  Skip,
+}
 
 /// The WebAssembly parser.
 pub struct Parser {
@@ -11,6 +30,12 @@ pub struct Parser {
   encoding: wasmparser::Encoding,
   /// The WebAssembly header range.
   header_range: Range<usize>,
+  /// Handling of already instrumented modules.
+  instrumented_input: InstrumentedInput,
+  /// Flag indicating if types of operands on the stack are recorded for each operator.
+  operand_types: bool,
+  /// Flag indicating if offsets in the original binary are recorded for each operator.
+  original_offsets: bool,
 }
 
 impl Default for Parser {
@@ -27,9 +52,36 @@ impl Parser {
       version: 0,
       encoding: wasmparser::Encoding::Module,
       header_range: Range::default(),
+      instrumented_input: InstrumentedInput::default(),
+      operand_types: false,
+      original_offsets: false,
     }
   }
 
+  /// Sets the handling of already instrumented modules, by default such modules are rejected.
+  pub fn with_instrumented_input(mut self, instrumented_input: InstrumentedInput) -> Self {
+    self.instrumented_input = instrumented_input;
+    self
+  }
+
+  /// Enables or disables recording types of operands on the stack before each operator,
+  /// see [CodeSectionEntry::operand_types].
+  ///
+  /// Disabled by default.
+  pub fn with_operand_types(mut self, enabled: bool) -> Self {
+    self.operand_types = enabled;
+    self
+  }
+
+  /// Enables or disables recording offsets of operators in the original binary,
+  /// see [CodeSectionEntry::original_offsets].
+  ///
+  /// Disabled by default.
+  pub fn with_original_offsets(mut self, enabled: bool) -> Self {
+    self.original_offsets = enabled;
+    self
+  }
+
   /// Parses WAT file.
   pub fn parse_wat_file(&mut self, file: impl AsRef<Path>) -> WasmarinResult<()> {
     let wasm = wat::parse_file(file).map_err(|e| WasmarinError::new(e.to_string()))?;
@@ -53,16 +105,46 @@ impl Parser {
 
   /// Parses WASM binary.
   pub fn parse_wasm_bytes<'a>(&mut self, data: &'a [u8]) -> WasmarinResult<Model<'a>> {
+    self.parse(data, None)
+  }
+
+  /// Parses WASM binary without decoding and validating function bodies.
+  ///
+  /// Only the original encoding of function bodies is kept in [CodeSectionEntry::original_body], locals and operators
+  /// are left empty. Function bodies must be validated with the returned validators, in order of functions,
+  /// see [Parser::parse_function_body].
+  pub(crate) fn parse_wasm_bytes_lazily<'a>(&mut self, data: &'a [u8]) -> WasmarinResult<(Model<'a>, Vec<FuncToValidate<ValidatorResources>>)> {
+    let mut funcs_to_validate = vec![];
+    let model = self.parse(data, Some(&mut funcs_to_validate))?;
+    Ok((model, funcs_to_validate))
+  }
+
+  /// Parses WASM binary, function bodies are decoded unless their validators are collected for later validation.
+  fn parse<'a>(&mut self, data: &'a [u8], mut funcs_to_validate: Option<&mut Vec<FuncToValidate<ValidatorResources>>>) -> WasmarinResult<Model<'a>> {
     let mut model = Model::default();
 
-    // Validate the input data against requested WebAssembly features.
+    // Validate the input data against requested WebAssembly features,
+    // each payload is validated right before it is parsed, so the input is read only once.
     let requested_features = Features::new();
     let mut validator = wasmparser::Validator::new_with_features(requested_features.into());
-    validator.validate_all(data).map_err(|e| WasmarinError::new(e.to_string()))?;
+    let mut allocations = wasmparser::FuncValidatorAllocations::default();
+
+    // Custom sections are placed after the last known section preceding them.
+    let mut last_section = None;
+    // The name section is decoded after function bodies are parsed, along with its placement.
+    let mut name_section = None;
 
     let parser = wasmparser::Parser::new(0);
     for payload in parser.parse_all(data) {
-      match payload.map_err(|e| WasmarinError::new(e.to_string()))? {
+      let payload = payload.map_err(|e| WasmarinError::new(e.to_string()))?;
+      let func_to_validate = match validator.payload(&payload).map_err(|e| WasmarinError::new(e.to_string()))? {
+        wasmparser::ValidPayload::Func(func_to_validate, _) => Some(func_to_validate),
+        _ => None,
+      };
+      if let Some(section_id) = section_id(&payload) {
+        last_section = Some(section_id);
+      }
+      match payload {
         Payload::Version { num, encoding, range } => {
           self.version = num;
           self.encoding = encoding;
@@ -77,7 +159,7 @@ impl Parser {
         Payload::ImportSection(reader) => {
           for item in reader {
             let import = item.map_err(|e| WasmarinError::new(e.to_string()))?;
-            model.imports.push(import);
+            model.imports.push(import.into());
           }
         }
         Payload::FunctionSection(reader) => {
@@ -89,7 +171,7 @@ impl Parser {
         Payload::TableSection(reader) => {
           for item in reader {
             let table = item.map_err(|e| WasmarinError::new(e.to_string()))?;
-            model.tables.push(table);
+            model.tables.push(table.try_into()?);
           }
         }
         Payload::MemorySection(reader) => {
@@ -107,13 +189,13 @@ impl Parser {
         Payload::GlobalSection(reader) => {
           for item in reader {
             let global = item.map_err(|e| WasmarinError::new(e.to_string()))?;
-            model.globals.push(global);
+            model.globals.push(global.try_into()?);
           }
         }
         Payload::ExportSection(reader) => {
           for item in reader {
             let export = item.map_err(|e| WasmarinError::new(e.to_string()))?;
-            model.exports.push(export);
+            model.exports.push(export.into());
           }
         }
         Payload::StartSection { func, range: _ } => {
@@ -122,7 +204,7 @@ impl Parser {
         Payload::ElementSection(reader) => {
           for item in reader {
             let element = item.map_err(|e| WasmarinError::new(e.to_string()))?;
-            model.elements.push(element);
+            model.elements.push(element.try_into()?);
           }
         }
         Payload::DataCountSection { count, range: _ } => {
@@ -131,25 +213,31 @@ impl Parser {
         Payload::DataSection(reader) => {
           for item in reader {
             let data = item.map_err(|e| WasmarinError::new(e.to_string()))?;
-            model.data.push(data);
+            model.data.push(data.try_into()?);
           }
         }
-        Payload::CodeSectionStart { count: _, range: _, size: _ } => {
+        Payload::CodeSectionStart { count: _, range, size: _ } => {
           // Here we know how many functions we'll be receiving as `CodeSectionEntry`,
           // so we can prepare for that, and afterward we can parse and handle each function individually.
+          model.original_code_section = Some(&data[range]);
         }
         Payload::CodeSectionEntry(body) => {
-          let mut code_section_entry = CodeSectionEntry::default();
-          let locals_reader = body.get_locals_reader().map_err(|e| WasmarinError::new(e.to_string()))?;
-          for item in locals_reader {
-            let (local_index, local_val_type) = item.map_err(|e| WasmarinError::new(e.to_string()))?;
-            code_section_entry.locals.push((local_index, local_val_type));
-          }
-          let operators_reader = body.get_operators_reader().map_err(|e| WasmarinError::new(e.to_string()))?;
-          for item in operators_reader {
-            let operator = item.map_err(|e| WasmarinError::new(e.to_string()))?;
-            code_section_entry.operators.push(operator);
+          // The function body is validated while its locals and operators are parsed.
+          let Some(func_to_validate) = func_to_validate else {
+            return Err(WasmarinError::new("function body was not validated"));
+          };
+          if let Some(funcs_to_validate) = &mut funcs_to_validate {
+            funcs_to_validate.push(func_to_validate);
+            model.code_section_entries.push(CodeSectionEntry {
+              original_body: Some(body.as_bytes()),
+              ..Default::default()
+            });
+            continue;
           }
+          let mut func_validator = func_to_validate.into_validator(std::mem::take(&mut allocations));
+          let mut code_section_entry = CodeSectionEntry::default();
+          self.parse_function_body(&body, &mut func_validator, self.original_offsets, &mut code_section_entry)?;
+          allocations = func_validator.into_allocations();
           model.code_section_entries.push(code_section_entry);
         }
         Payload::ModuleSection { parser: _, unchecked_range: _ } => {
@@ -186,8 +274,21 @@ impl Parser {
         Payload::ComponentExportSection(_reader) => {
           unimplemented!("Payload::ComponentExportSection");
         }
+        Payload::CustomSection(reader) if reader.name() == METERING_SECTION_NAME => {
+          if model.metering.is_some() {
+            return Err(WasmarinError::new(format!("duplicate custom section '{METERING_SECTION_NAME}'")));
+          }
+          model.metering = Some(MeteringMetadata::decode(reader.data())?);
+        }
+        Payload::CustomSection(reader) if reader.name() == NAME_SECTION_NAME && name_section.is_none() => {
+          name_section = Some((reader.data(), last_section, model.custom_sections.len()));
+        }
         Payload::CustomSection(reader) => {
-          model.custom_sections.push((reader.name().to_string(), reader.data().to_vec()));
+          model.custom_sections.push(CustomSection {
+            name: reader.name().to_string(),
+            data: reader.data().to_vec(),
+            after: last_section,
+          });
         }
         Payload::End(_length) => {
           // Once we've reached the end of a parser we either resume at the parent parser
@@ -203,6 +304,121 @@ impl Parser {
         }
       }
     }
+    if let Some((data, after, position)) = name_section {
+      match Names::decode(data, model.imported_function_count(), &mut model.code_section_entries) {
+        Ok(names) => model.names = Some(Names { after, ..names }),
+        Err(_) => {
+          // A malformed name section does not make the module invalid, it is kept as an opaque custom section.
+          model.custom_sections.insert(
+            position,
+            CustomSection {
+              name: NAME_SECTION_NAME.to_string(),
+              data: data.to_vec(),
+              after,
+            },
+          );
+        }
+      }
+    }
+    self.check_instrumentation(&model)?;
     Ok(model)
   }
+
+  /// Parses locals and operators of the function body into the reused code section entry, validating them in the same pass.
+  ///
+  /// Offsets of operators in the original binary are recorded when requested, regardless of the parser settings.
+  pub(crate) fn parse_function_body<'a>(
+    &self,
+    body: &wasmparser::FunctionBody<'a>,
+    func_validator: &mut FuncValidator<ValidatorResources>,
+    original_offsets: bool,
+    code_section_entry: &mut CodeSectionEntry<'a>,
+  ) -> WasmarinResult<()> {
+    code_section_entry.locals.clear();
+    code_section_entry.operators.clear();
+    code_section_entry.touched = false;
+    // Operators take about two bytes on average, reserving the space upfront avoids repeated reallocations.
+    code_section_entry.operators.reserve(body.range().len() / 2);
+    let mut locals_reader = body.get_locals_reader().map_err(|e| WasmarinError::new(e.to_string()))?;
+    for _ in 0..locals_reader.get_count() {
+      let offset = locals_reader.original_position();
+      let (local_index, local_val_type) = locals_reader.read().map_err(|e| WasmarinError::new(e.to_string()))?;
+      func_validator
+        .define_locals(offset, local_index, local_val_type)
+        .map_err(|e| WasmarinError::new(e.to_string()))?;
+      code_section_entry.locals.push((local_index, local_val_type));
+    }
+    let mut operators_reader = body.get_operators_reader().map_err(|e| WasmarinError::new(e.to_string()))?;
+    let mut operand_types = self.operand_types.then(OperandTypes::default);
+    let mut original_offsets = original_offsets.then(Vec::new);
+    while !operators_reader.eof() {
+      if let Some(operand_types) = &mut operand_types {
+        operand_types.push(stack_operand_types(func_validator));
+      }
+      if let Some(original_offsets) = &mut original_offsets {
+        original_offsets.push(operators_reader.original_position());
+      }
+      let operator = read_validated_operator(&mut operators_reader, func_validator).map_err(|e| WasmarinError::new(e.to_string()))?;
+      code_section_entry.operators.push(operator);
+    }
+    operators_reader.finish().map_err(|e| WasmarinError::new(e.to_string()))?;
+    code_section_entry.operand_types = operand_types;
+    code_section_entry.original_offsets = original_offsets;
+    code_section_entry.original_body = Some(body.as_bytes());
+    Ok(())
+  }
+
+  /// Checks whether the module was instrumented and if it is allowed to be.
+  ///
+  /// A module defining the reserved exports without a matching metering section is always rejected.
+  fn check_instrumentation(&self, model: &Model) -> WasmarinResult<()> {
+    match (&model.metering, self.instrumented_input) {
+      (Some(_), InstrumentedInput::Reject) => Err(WasmarinError::new(format!(
+        "module is already instrumented, found custom section '{METERING_SECTION_NAME}'"
+      ))),
+      (Some(metadata), InstrumentedInput::Skip) => {
+        let counter = model.exports.iter().find(|export| export.name == REMAINING_POINTS_EXPORT_NAME);
+        let counter_global = metadata
+          .counter_global_index
+          .checked_sub(model.imported_global_count())
+          .and_then(|index| model.globals.get(index as usize));
+        match (counter, counter_global) {
+          (Some(export), Some(global))
+            if export.kind == ExternalKind::Global && export.index == metadata.counter_global_index && global.ty.content_type == ValType::I64 && global.ty.mutable =>
+          {
+            Ok(())
+          }
+          _ => Err(WasmarinError::new(format!("custom section '{METERING_SECTION_NAME}' does not match the module"))),
+        }
+      }
+      (None, _) => match model
+        .exports
+        .iter()
+        .find(|export| export.name == REMAINING_POINTS_EXPORT_NAME || export.name == START_FUNCTION_EXPORT_NAME)
+      {
+        Some(export) => Err(WasmarinError::new(format!("module defines reserved export '{}'", export.name))),
+        None => Ok(()),
+      },
+    }
+  }
+}
+
+/// Returns the identifier of the known section starting with the payload.
+fn section_id(payload: &Payload) -> Option<SectionId> {
+  match payload {
+    Payload::TypeSection(_) => Some(SectionId::Type),
+    Payload::ImportSection(_) => Some(SectionId::Import),
+    Payload::FunctionSection(_) => Some(SectionId::Function),
+    Payload::TableSection(_) => Some(SectionId::Table),
+    Payload::MemorySection(_) => Some(SectionId::Memory),
+    Payload::TagSection(_) => Some(SectionId::Tag),
+    Payload::GlobalSection(_) => Some(SectionId::Global),
+    Payload::ExportSection(_) => Some(SectionId::Export),
+    Payload::StartSection { .. } => Some(SectionId::Start),
+    Payload::ElementSection(_) => Some(SectionId::Element),
+    Payload::DataCountSection { .. } => Some(SectionId::DataCount),
+    Payload::CodeSectionStart { .. } => Some(SectionId::Code),
+    Payload::DataSection(_) => Some(SectionId::Data),
+    _ => None,
+  }
 }
@@ -0,0 +1,168 @@
+//! # Transformation passes run on the model before encoding
+
+use crate::{CodeSectionEntry, Model, WasmarinError, WasmarinResult};
+
+/// Transformation of the WebAssembly model.
+///
+/// A pass first updates module-level items of the model, like globals, exports or the start function,
+/// and then rewrites operators of every defined function, one function at a time.
+/// Both steps do nothing by default, so a pass implements only the steps it needs.
+pub trait Pass {
+  /// Returns the name of the pass, used in error messages.
+  fn name(&self) -> &str;
+
+  /// Updates module-level items of the model, called once before any function is updated.
+  fn update_model(&mut self, _model: &mut Model) -> WasmarinResult<()> {
+    Ok(())
+  }
+
+  /// Updates a single defined function, called for every defined function in order of function indexes.
+  ///
+  /// The [Pipeline] compares the locals and operators with their state before the call and marks changed functions
+  /// as [touched](CodeSectionEntry::touched), so passes do not have to set the flag themselves.
+  fn update_function(&mut self, _function_index: u32, _code_section_entry: &mut CodeSectionEntry) -> WasmarinResult<()> {
+    Ok(())
+  }
+
+  /// Returns `true` iff the pass reads or changes function bodies in [Pass::update_function].
+  ///
+  /// When no pass needs function bodies, [Encoder::encode_wasm_bytes](crate::Encoder::encode_wasm_bytes) copies them
+  /// without decoding and does not call [Pass::update_function]. Function bodies are needed by default.
+  fn needs_function_bodies(&self) -> bool {
+    true
+  }
+
+  /// Returns an independent copy of the pass, updating functions on another worker thread.
+  ///
+  /// Called after the model was updated. Functions are updated in parallel only by passes that can be copied,
+  /// by default a pass can not be copied, so passes collecting state from functions always run sequentially.
+  fn fork(&self) -> Option<Box<dyn Pass + Send>> {
+    None
+  }
+}
+
+/// Sequence of passes run on the model in order.
+#[derive(Default)]
+pub struct Pipeline {
+  /// Passes to be run, in order.
+  passes: Vec<Box<dyn Pass>>,
+  /// Number of worker threads updating functions, functions are updated sequentially when less than two.
+  workers: usize,
+}
+
+impl Pipeline {
+  /// Creates a new [Pipeline] with no passes.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Appends a pass to the pipeline.
+  pub fn with_pass(mut self, pass: impl Pass + 'static) -> Self {
+    self.passes.push(Box::new(pass));
+    self
+  }
+
+  /// Sets the number of worker threads updating functions of passes that can be copied, see [Pass::fork].
+  ///
+  /// Each worker updates a contiguous range of functions, so the result is the same as when updated sequentially.
+  /// By default, functions are updated sequentially on the calling thread.
+  pub fn with_workers(mut self, workers: usize) -> Self {
+    self.workers = workers;
+    self
+  }
+
+  /// Returns the number of worker threads updating functions.
+  pub fn workers(&self) -> usize {
+    self.workers.max(1)
+  }
+
+  /// Returns the names of all passes, in order.
+  pub fn pass_names(&self) -> Vec<&str> {
+    self.passes.iter().map(|pass| pass.name()).collect()
+  }
+
+  /// Runs all passes on the model.
+  ///
+  /// Each pass updates the model and all its functions before the next pass is run.
+  pub fn run(&mut self, model: &mut Model) -> WasmarinResult<()> {
+    for pass in &mut self.passes {
+      run_pass(pass.as_mut(), model, self.workers).map_err(|e| pass_error(pass.as_ref(), e))?;
+    }
+    Ok(())
+  }
+
+  /// Returns `true` iff any pass reads or changes function bodies, see [Pass::needs_function_bodies].
+  pub(crate) fn needs_function_bodies(&self) -> bool {
+    self.passes.iter().any(|pass| pass.needs_function_bodies())
+  }
+
+  /// Updates module-level items of the model by all passes, in order.
+  pub(crate) fn update_model(&mut self, model: &mut Model) -> WasmarinResult<()> {
+    for pass in &mut self.passes {
+      pass.update_model(model).map_err(|e| pass_error(pass.as_ref(), e))?;
+    }
+    Ok(())
+  }
+
+  /// Updates a single defined function by all passes, in order.
+  pub(crate) fn update_function(&mut self, function_index: u32, code_section_entry: &mut CodeSectionEntry) -> WasmarinResult<()> {
+    for pass in &mut self.passes {
+      update_function(pass.as_mut(), function_index, code_section_entry).map_err(|e| pass_error(pass.as_ref(), e))?;
+    }
+    Ok(())
+  }
+}
+
+/// Returns the error reported by the pass, naming the failed pass.
+fn pass_error(pass: &dyn Pass, e: WasmarinError) -> WasmarinError {
+  WasmarinError::new(format!("pass '{}' failed: {}", pass.name(), e))
+}
+
+/// Runs a single pass on the model and all its functions.
+fn run_pass(pass: &mut dyn Pass, model: &mut Model, workers: usize) -> WasmarinResult<()> {
+  pass.update_model(model)?;
+  let imported_function_count = model.imported_function_count();
+  let chunk_size = model.code_section_entries.len().div_ceil(workers.max(1)).max(1);
+  if workers > 1 && model.code_section_entries.len() > chunk_size {
+    if let Some(forks) = (0..workers).map(|_| pass.fork()).collect::<Option<Vec<_>>>() {
+      return std::thread::scope(|scope| {
+        let handles = model
+          .code_section_entries
+          .chunks_mut(chunk_size)
+          .zip(forks)
+          .enumerate()
+          .map(|(chunk_index, (code_section_entries, mut fork))| {
+            let first_function_index = imported_function_count + (chunk_index * chunk_size) as u32;
+            scope.spawn(move || update_functions(fork.as_mut(), first_function_index, code_section_entries))
+          })
+          .collect::<Vec<_>>();
+        // Results are collected in order of functions, so the reported error is the same as when run sequentially.
+        handles.into_iter().try_for_each(|handle| handle.join().expect("worker thread panicked"))
+      });
+    }
+  }
+  update_functions(pass, imported_function_count, &mut model.code_section_entries)
+}
+
+/// Updates consecutive functions, starting with the given function index.
+fn update_functions(pass: &mut dyn Pass, first_function_index: u32, code_section_entries: &mut [CodeSectionEntry]) -> WasmarinResult<()> {
+  for (index, code_section_entry) in code_section_entries.iter_mut().enumerate() {
+    update_function(pass, first_function_index + index as u32, code_section_entry)?;
+  }
+  Ok(())
+}
+
+/// Updates a single function, marking it as touched when the pass changed its locals or operators.
+fn update_function(pass: &mut dyn Pass, function_index: u32, code_section_entry: &mut CodeSectionEntry) -> WasmarinResult<()> {
+  if code_section_entry.touched {
+    pass.update_function(function_index, code_section_entry)?;
+  } else {
+    // Functions are compared with a snapshot only until touched, later changes are re-encoded anyway.
+    let locals = code_section_entry.locals.clone();
+    let operators = code_section_entry.operators.clone();
+    pass.update_function(function_index, code_section_entry)?;
+    code_section_entry.touched |= code_section_entry.locals != locals || code_section_entry.operators != operators;
+  }
+  code_section_entry.clear_stale_original_offsets();
+  Ok(())
+}
@@ -0,0 +1,444 @@
+//! # Renumbering of indexes referenced in a module
+
+use crate::{ConstExpr, DataKind, ElementItems, ElementKind, Model, Names, WasmarinError, WasmarinResult};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use wasmparser::{BlockType, Catch, ExternalKind, HeapType, Operator, TypeRef, UnpackedIndex};
+
+/// Renumbering of a single index space after inserting or removing items.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IndexMap<'r> {
+  /// Indexes are not changed.
+  #[default]
+  Identity,
+  /// Item was inserted at the given index, the following indexes are shifted up.
+  Insert(u32),
+  /// Item with the given index was removed, the following indexes are shifted down.
+  Remove(u32),
+  /// Items with the given sorted indexes were removed, the following indexes are shifted down.
+  RemoveAll(&'r [u32]),
+}
+
+impl IndexMap<'_> {
+  /// Returns the new index, or `None` when the item with the given index was removed.
+  fn map(&self, index: u32) -> Option<u32> {
+    match *self {
+      Self::Identity => Some(index),
+      Self::Insert(inserted) if index >= inserted => Some(index + 1),
+      Self::Insert(_) => Some(index),
+      Self::Remove(removed) => match index.cmp(&removed) {
+        Ordering::Less => Some(index),
+        Ordering::Equal => None,
+        Ordering::Greater => Some(index - 1),
+      },
+      Self::RemoveAll(removed) => match removed.binary_search(&index) {
+        Ok(_) => None,
+        Err(position) => Some(index - position as u32),
+      },
+    }
+  }
+}
+
+/// Renumbering of all index spaces of a module.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct IndexRemap<'r> {
+  pub types: IndexMap<'r>,
+  pub functions: IndexMap<'r>,
+  pub tables: IndexMap<'r>,
+  pub memories: IndexMap<'r>,
+  pub globals: IndexMap<'r>,
+  pub tags: IndexMap<'r>,
+  pub elements: IndexMap<'r>,
+  pub data: IndexMap<'r>,
+}
+
+/// Visitor of indexes referenced by operators, called with each index by its index space.
+pub(crate) trait IndexVisitor {
+  fn type_(&mut self, index: &mut u32) -> WasmarinResult<()>;
+
+  fn function(&mut self, index: &mut u32) -> WasmarinResult<()>;
+
+  fn table(&mut self, index: &mut u32) -> WasmarinResult<()>;
+
+  fn memory(&mut self, index: &mut u32) -> WasmarinResult<()>;
+
+  fn global(&mut self, index: &mut u32) -> WasmarinResult<()>;
+
+  fn tag(&mut self, index: &mut u32) -> WasmarinResult<()>;
+
+  fn element(&mut self, index: &mut u32) -> WasmarinResult<()>;
+
+  fn data(&mut self, index: &mut u32) -> WasmarinResult<()>;
+
+  fn block_type(&mut self, block_type: &mut BlockType) -> WasmarinResult<()> {
+    match block_type {
+      BlockType::FuncType(type_index) => self.type_(type_index),
+      _ => Ok(()),
+    }
+  }
+
+  fn heap_type(&mut self, heap_type: &mut HeapType) -> WasmarinResult<()> {
+    match heap_type {
+      HeapType::Concrete(UnpackedIndex::Module(type_index)) => self.type_(type_index),
+      _ => Ok(()),
+    }
+  }
+}
+
+/// Visits a single field of an operator, selected by the name of the field.
+macro_rules! remap_field {
+  ($remap:ident, function_index, $value:ident) => {
+    $remap.function($value)?
+  };
+  ($remap:ident, global_index, $value:ident) => {
+    $remap.global($value)?
+  };
+  ($remap:ident, type_index, $value:ident) => {
+    $remap.type_($value)?
+  };
+  ($remap:ident, array_type_index, $value:ident) => {
+    $remap.type_($value)?
+  };
+  ($remap:ident, array_type_index_dst, $value:ident) => {
+    $remap.type_($value)?
+  };
+  ($remap:ident, array_type_index_src, $value:ident) => {
+    $remap.type_($value)?
+  };
+  ($remap:ident, struct_type_index, $value:ident) => {
+    $remap.type_($value)?
+  };
+  ($remap:ident, cont_type_index, $value:ident) => {
+    $remap.type_($value)?
+  };
+  ($remap:ident, table_index, $value:ident) => {
+    $remap.table($value)?
+  };
+  ($remap:ident, table, $value:ident) => {
+    $remap.table($value)?
+  };
+  ($remap:ident, src_table, $value:ident) => {
+    $remap.table($value)?
+  };
+  ($remap:ident, dst_table, $value:ident) => {
+    $remap.table($value)?
+  };
+  ($remap:ident, mem, $value:ident) => {
+    $remap.memory($value)?
+  };
+  ($remap:ident, src_mem, $value:ident) => {
+    $remap.memory($value)?
+  };
+  ($remap:ident, dst_mem, $value:ident) => {
+    $remap.memory($value)?
+  };
+  ($remap:ident, memarg, $value:ident) => {
+    $remap.memory(&mut $value.memory)?
+  };
+  ($remap:ident, tag_index, $value:ident) => {
+    $remap.tag($value)?
+  };
+  ($remap:ident, data_index, $value:ident) => {
+    $remap.data($value)?
+  };
+  ($remap:ident, array_data_index, $value:ident) => {
+    $remap.data($value)?
+  };
+  ($remap:ident, elem_index, $value:ident) => {
+    $remap.element($value)?
+  };
+  ($remap:ident, array_elem_index, $value:ident) => {
+    $remap.element($value)?
+  };
+  ($remap:ident, blockty, $value:ident) => {
+    $remap.block_type($value)?
+  };
+  ($remap:ident, try_table, $value:ident) => {{
+    $remap.block_type(&mut $value.ty)?;
+    for catch in &mut $value.catches {
+      if let Catch::One { tag, .. } | Catch::OneRef { tag, .. } = catch {
+        $remap.tag(tag)?;
+      }
+    }
+  }};
+  ($remap:ident, hty, $value:ident) => {
+    $remap.heap_type($value)?
+  };
+  ($remap:ident, $field:ident, $value:ident) => {
+    let _ = $value;
+  };
+}
+
+/// Defines visiting of indexes of all operators, generated from the list of operators provided by `wasmparser`.
+macro_rules! define_visit_operator {
+  ($( @$proposal:ident $op:ident $({ $($field:ident : $field_type:ty),* })? => $visit:ident ($($arity:tt)*))*) => {
+    /// Visits all indexes referenced by the operator.
+    pub(crate) fn visit_operator(visitor: &mut impl IndexVisitor, operator: &mut Operator) -> WasmarinResult<()> {
+      match operator {
+        $(
+          Operator::$op $({ $($field),* })? => {
+            $($(remap_field!(visitor, $field, $field);)*)?
+          }
+        )*
+        _ => {}
+      }
+      Ok(())
+    }
+  };
+}
+
+wasmparser::for_each_operator!(define_visit_operator);
+
+impl IndexRemap<'_> {
+  /// Renumbers all references in the model.
+  ///
+  /// Fails when the model still references a removed item, the model may be then partially renumbered.
+  pub(crate) fn remap_model(mut self, model: &mut Model) -> WasmarinResult<()> {
+    for import in &mut model.imports {
+      match &mut import.ty {
+        TypeRef::Func(type_index) => self.type_(type_index)?,
+        TypeRef::Tag(tag_type) => self.type_(&mut tag_type.func_type_idx)?,
+        _ => {}
+      }
+    }
+    for type_index in &mut model.function_indexes {
+      self.type_(type_index)?;
+    }
+    for table in &mut model.tables {
+      if let Some(init_expr) = &mut table.init_expr {
+        *init_expr = self.remap_const_expr(init_expr)?;
+      }
+    }
+    for global in &mut model.globals {
+      global.init_expr = self.remap_const_expr(&global.init_expr)?;
+    }
+    for export in &mut model.exports {
+      match export.kind {
+        ExternalKind::Func => self.function(&mut export.index)?,
+        ExternalKind::Table => self.table(&mut export.index)?,
+        ExternalKind::Memory => self.memory(&mut export.index)?,
+        ExternalKind::Global => self.global(&mut export.index)?,
+        ExternalKind::Tag => self.tag(&mut export.index)?,
+      }
+    }
+    if let Some(start_function_index) = &mut model.start_function_index {
+      self.function(start_function_index)?;
+    }
+    for tag_type in &mut model.tag_types {
+      self.type_(&mut tag_type.func_type_idx)?;
+    }
+    for element in &mut model.elements {
+      if let ElementKind::Active { table_index, offset_expr } = &mut element.kind {
+        // Missing table index refers to the first table.
+        let mut index = table_index.unwrap_or_default();
+        self.table(&mut index)?;
+        if table_index.is_some() || index != 0 {
+          *table_index = Some(index);
+        }
+        *offset_expr = self.remap_const_expr(offset_expr)?;
+      }
+      match &mut element.items {
+        ElementItems::Functions(function_indexes) => {
+          for function_index in function_indexes {
+            self.function(function_index)?;
+          }
+        }
+        ElementItems::Expressions(_, const_exprs) => {
+          for const_expr in const_exprs {
+            *const_expr = self.remap_const_expr(const_expr)?;
+          }
+        }
+      }
+    }
+    for data in &mut model.data {
+      if let DataKind::Active { memory_index, offset_expr } = &mut data.kind {
+        self.memory(memory_index)?;
+        *offset_expr = self.remap_const_expr(offset_expr)?;
+      }
+    }
+    for code_section_entry in &mut model.code_section_entries {
+      for operator in &mut code_section_entry.operators {
+        let original = operator.clone();
+        visit_operator(&mut self, operator)?;
+        code_section_entry.touched |= *operator != original;
+      }
+    }
+    if let Some(metadata) = &mut model.metering {
+      self.global(&mut metadata.counter_global_index)?;
+    }
+    if let Some(names) = &mut model.names {
+      self.remap_names(names);
+    }
+    Ok(())
+  }
+
+  /// Checks that all references in the model can be renumbered, without changing the model.
+  ///
+  /// The model is checked before the items are removed, so references from the removed items themselves
+  /// are ignored. Renumbering the model with the items removed never fails when this check succeeds.
+  pub(crate) fn check_model(mut self, model: &Model) -> WasmarinResult<()> {
+    let removed = |index_map: IndexMap, index: u32| index_map.map(index).is_none();
+    // Imported items precede defined items in each index space.
+    let (mut functions, mut tables, mut globals, mut tags, mut memories) = (0, 0, 0, 0, 0);
+    for import in &model.imports {
+      let (index_map, count) = match import.ty {
+        TypeRef::Func(_) => (self.functions, &mut functions),
+        TypeRef::Table(_) => (self.tables, &mut tables),
+        TypeRef::Memory(_) => (self.memories, &mut memories),
+        TypeRef::Global(_) => (self.globals, &mut globals),
+        TypeRef::Tag(_) => (self.tags, &mut tags),
+      };
+      let index = *count;
+      *count += 1;
+      if removed(index_map, index) {
+        continue;
+      }
+      match import.ty {
+        TypeRef::Func(mut type_index) => self.type_(&mut type_index)?,
+        TypeRef::Tag(mut tag_type) => self.type_(&mut tag_type.func_type_idx)?,
+        _ => {}
+      }
+    }
+    for (position, (type_index, code_section_entry)) in model.function_indexes.iter().zip(&model.code_section_entries).enumerate() {
+      if removed(self.functions, functions + position as u32) {
+        continue;
+      }
+      self.type_(&mut type_index.clone())?;
+      for operator in &code_section_entry.operators {
+        visit_operator(&mut self, &mut operator.clone())?;
+      }
+    }
+    for (position, table) in model.tables.iter().enumerate() {
+      if let Some(init_expr) = table.init_expr.as_ref().filter(|_| !removed(self.tables, tables + position as u32)) {
+        self.remap_const_expr(init_expr)?;
+      }
+    }
+    for (position, global) in model.globals.iter().enumerate() {
+      if !removed(self.globals, globals + position as u32) {
+        self.remap_const_expr(&global.init_expr)?;
+      }
+    }
+    for export in &model.exports {
+      let mut index = export.index;
+      match export.kind {
+        ExternalKind::Func => self.function(&mut index)?,
+        ExternalKind::Table => self.table(&mut index)?,
+        ExternalKind::Memory => self.memory(&mut index)?,
+        ExternalKind::Global => self.global(&mut index)?,
+        ExternalKind::Tag => self.tag(&mut index)?,
+      }
+    }
+    if let Some(mut start_function_index) = model.start_function_index {
+      self.function(&mut start_function_index)?;
+    }
+    for (position, tag_type) in model.tag_types.iter().enumerate() {
+      if !removed(self.tags, tags + position as u32) {
+        self.type_(&mut tag_type.func_type_idx.clone())?;
+      }
+    }
+    for (position, element) in model.elements.iter().enumerate() {
+      if removed(self.elements, position as u32) {
+        continue;
+      }
+      if let ElementKind::Active { table_index, offset_expr } = &element.kind {
+        self.table(&mut table_index.unwrap_or_default())?;
+        self.remap_const_expr(offset_expr)?;
+      }
+      match &element.items {
+        ElementItems::Functions(function_indexes) => {
+          for function_index in function_indexes {
+            self.function(&mut function_index.clone())?;
+          }
+        }
+        ElementItems::Expressions(_, const_exprs) => {
+          for const_expr in const_exprs {
+            self.remap_const_expr(const_expr)?;
+          }
+        }
+      }
+    }
+    for (position, data) in model.data.iter().enumerate() {
+      if let DataKind::Active { memory_index, offset_expr } = &data.kind {
+        if !removed(self.data, position as u32) {
+          self.memory(&mut memory_index.clone())?;
+          self.remap_const_expr(offset_expr)?;
+        }
+      }
+    }
+    if let Some(metadata) = &model.metering {
+      self.global(&mut metadata.counter_global_index.clone())?;
+    }
+    Ok(())
+  }
+
+  /// Renumbers named items, names of removed items are dropped.
+  pub(crate) fn remap_names(&self, names: &mut Names) {
+    remap_name_map(self.functions, &mut names.functions);
+    remap_name_map(self.types, &mut names.types);
+    remap_name_map(self.tables, &mut names.tables);
+    remap_name_map(self.memories, &mut names.memories);
+    remap_name_map(self.globals, &mut names.globals);
+    remap_name_map(self.elements, &mut names.elements);
+    remap_name_map(self.data, &mut names.data);
+    remap_name_map(self.tags, &mut names.tags);
+    remap_name_map(self.types, &mut names.fields);
+  }
+
+  /// Returns the constant expression with all references renumbered.
+  pub(crate) fn remap_const_expr(mut self, const_expr: &ConstExpr) -> WasmarinResult<ConstExpr> {
+    let mut operators = const_expr.operators()?;
+    for operator in &mut operators {
+      visit_operator(&mut self, operator)?;
+    }
+    Ok(ConstExpr::new(&operators))
+  }
+}
+
+impl IndexVisitor for IndexRemap<'_> {
+  fn type_(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    remap(self.types, "type", index)
+  }
+
+  fn function(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    remap(self.functions, "function", index)
+  }
+
+  fn table(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    remap(self.tables, "table", index)
+  }
+
+  fn memory(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    remap(self.memories, "memory", index)
+  }
+
+  fn global(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    remap(self.globals, "global", index)
+  }
+
+  fn tag(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    remap(self.tags, "tag", index)
+  }
+
+  fn element(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    remap(self.elements, "element segment", index)
+  }
+
+  fn data(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    remap(self.data, "data segment", index)
+  }
+}
+
+/// Renumbers keys of the map, dropping entries of the removed item.
+fn remap_name_map<T>(index_map: IndexMap<'_>, names: &mut BTreeMap<u32, T>) {
+  if index_map != IndexMap::Identity {
+    *names = std::mem::take(names).into_iter().filter_map(|(index, name)| Some((index_map.map(index)?, name))).collect();
+  }
+}
+
+/// Renumbers a single index, failing when the referenced item was removed.
+fn remap(index_map: IndexMap<'_>, item: &str, index: &mut u32) -> WasmarinResult<()> {
+  *index = index_map
+    .map(*index)
+    .ok_or_else(|| WasmarinError::new(format!("{item} {index} is removed, but it is still referenced")))?;
+  Ok(())
+}
@@ -0,0 +1,79 @@
+//! # Cost schedule used for metering
+
+use crate::{DataKind, ElementItems, ElementKind, Model};
+use std::num::NonZeroU64;
+
+/// Cost schedule used for metering.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CostSchedule {
+  /// Identifier of the schedule, recorded in instrumented modules.
+  pub id: String,
+  /// The size of the memory unit (in bytes) for bulk-memory operations.
+  pub bulk_memory_operation_unit: NonZeroU64,
+  /// The number of elements in a unit charged for processing element segments.
+  ///
+  /// Elements are references stored in tables, so they are charged by count instead of by bytes.
+  pub element_operation_unit: NonZeroU64,
+  /// The base cost of instantiating a module, charged regardless of its content.
+  pub instantiation_base_cost: i64,
+}
+
+impl Default for CostSchedule {
+  /// Creates a default [CostSchedule] instance.
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl CostSchedule {
+  /// Creates a new [CostSchedule] instance.
+  pub fn new() -> Self {
+    Self {
+      id: "default".to_string(),
+      bulk_memory_operation_unit: NonZeroU64::new(32).unwrap(),
+      element_operation_unit: NonZeroU64::new(32).unwrap(),
+      instantiation_base_cost: 0,
+    }
+  }
+
+  /// Returns the static cost of processing a data segment of the given length in bytes.
+  ///
+  /// The cost grows with the size of the segment, so apart from the processing itself,
+  /// each started unit of the segment is charged one point. The same cost applies
+  /// to dropping a segment and to initializing an active segment during instantiation.
+  pub fn segment_cost(&self, length: u64) -> i64 {
+    1 + length.div_ceil(self.bulk_memory_operation_unit.get()) as i64
+  }
+
+  /// Returns the static cost of processing an element segment with the given number of elements.
+  ///
+  /// Charged like [CostSchedule::segment_cost], with units of [CostSchedule::element_operation_unit] elements.
+  pub fn element_segment_cost(&self, count: u64) -> i64 {
+    1 + count.div_ceil(self.element_operation_unit.get()) as i64
+  }
+
+  /// Returns the cost of instantiating the module.
+  ///
+  /// Instantiation copies every active data segment into memory and initializes
+  /// every active element segment in a table before any code is executed,
+  /// so this cost should be charged by the host before instantiating the module.
+  pub fn instantiation_cost(&self, model: &Model) -> i64 {
+    let mut cost = self.instantiation_base_cost;
+    for data in &model.data {
+      if let DataKind::Active { .. } = data.kind {
+        cost += self.segment_cost(data.data.len() as u64);
+      }
+    }
+    for element in &model.elements {
+      if let ElementKind::Active { .. } = element.kind {
+        cost += self.element_segment_cost(element_segment_length(&element.items));
+      }
+    }
+    cost
+  }
+}
+
+/// Returns the number of elements in an element segment.
+pub fn element_segment_length(element_items: &ElementItems) -> u64 {
+  element_items.len() as u64
+}
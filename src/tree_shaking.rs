@@ -0,0 +1,362 @@
+//! # Removal of unreachable functions, globals, types and passive segments
+
+use crate::editing::types_removable;
+use crate::remap::{visit_operator, IndexMap, IndexRemap, IndexVisitor};
+use crate::{ConstExpr, DataKind, Element, ElementItems, ElementKind, Model, Pass, WasmarinError, WasmarinResult};
+use std::collections::BTreeSet;
+use wasmparser::{ExternalKind, Operator, TypeRef};
+
+/// Items removed by [tree_shake], identified by their indexes before removal.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TreeShakingReport {
+  /// Sorted indexes of removed defined functions.
+  pub functions: Vec<u32>,
+  /// Sorted indexes of removed defined globals.
+  pub globals: Vec<u32>,
+  /// Sorted indexes of removed types.
+  pub types: Vec<u32>,
+  /// Sorted indexes of removed passive element segments.
+  pub elements: Vec<u32>,
+  /// Sorted indexes of removed passive data segments.
+  pub data: Vec<u32>,
+}
+
+impl TreeShakingReport {
+  /// Returns `true` iff no item was removed.
+  pub fn is_empty(&self) -> bool {
+    self.functions.is_empty() && self.globals.is_empty() && self.types.is_empty() && self.elements.is_empty() && self.data.is_empty()
+  }
+}
+
+/// Pass removing unreachable items from the model, see [tree_shake].
+///
+/// Run it before [Metering](crate::Metering), so unreachable functions are not instrumented.
+/// The pass needs all function bodies, so it fails in [Encoder::encode_wasm_bytes](crate::Encoder::encode_wasm_bytes).
+/// Call [tree_shake] directly to get the report of removed items.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TreeShaking;
+
+impl TreeShaking {
+  /// Creates a new [TreeShaking] pass.
+  pub fn new() -> Self {
+    Self
+  }
+}
+
+impl Pass for TreeShaking {
+  fn name(&self) -> &str {
+    "tree-shaking"
+  }
+
+  fn update_model(&mut self, model: &mut Model) -> WasmarinResult<()> {
+    tree_shake(model).map(|_| ())
+  }
+}
+
+/// Removes defined functions, defined globals, types and passive segments unreachable from the roots of the module.
+///
+/// Roots are imports, exports, the start function, tags, table initializers, active and declarative element segments,
+/// active data segments and the global storing remaining points of an instrumented module. Items referenced
+/// from reachable functions, globals and segments are reachable as well. All functions listed in a reachable
+/// element segment are reachable, whether they are called indirectly or not. Imported items, tables, memories
+/// and tags are never removed.
+///
+/// Types are removed only when all types are final function types outside of explicit recursion groups
+/// and no value type references a type, otherwise all types are kept.
+///
+/// Functions referenced by `ref.func` in function bodies must be declared by another item of the module.
+/// When all items declaring such functions are removed, a declarative element segment listing them
+/// is appended to the element segments.
+///
+/// All references to the remaining items are renumbered, like by editing operations of the [Model].
+/// Returns the removed items, the model is left unchanged on failure.
+pub fn tree_shake(model: &mut Model) -> WasmarinResult<TreeShakingReport> {
+  if model.code_section_entries.iter().any(|code_section_entry| code_section_entry.operators.is_empty()) {
+    return Err(WasmarinError::new("function bodies must be parsed before tree shaking"));
+  }
+  let mut liveness = Liveness::new(model);
+  liveness.visit_roots(model)?;
+  liveness.visit_reachable(model)?;
+  let types_removable = types_removable(model);
+  let undeclared_functions = liveness.undeclared_functions(model)?;
+  let report = TreeShakingReport {
+    functions: removed(&liveness.functions, model.imported_function_count()),
+    globals: removed(&liveness.globals, model.imported_global_count()),
+    types: if types_removable { removed(&liveness.types, 0) } else { vec![] },
+    elements: removed(&liveness.elements, 0),
+    data: removed(&liveness.data, 0),
+  };
+  if report.is_empty() {
+    return Ok(report);
+  }
+  let imported_function_count = model.imported_function_count() as usize;
+  let imported_global_count = model.imported_global_count() as usize;
+  model.edit(
+    |model| {
+      retain_live(&mut model.function_indexes, &liveness.functions[imported_function_count..]);
+      retain_live(&mut model.code_section_entries, &liveness.functions[imported_function_count..]);
+      retain_live(&mut model.globals, &liveness.globals[imported_global_count..]);
+      if types_removable {
+        // Each type is defined in its own implicit recursion group.
+        retain_live(&mut model.rec_groups, &liveness.types);
+      }
+      retain_live(&mut model.elements, &liveness.elements);
+      if !undeclared_functions.is_empty() {
+        model.elements.push(Element {
+          kind: ElementKind::Declared,
+          items: ElementItems::Functions(undeclared_functions),
+        });
+      }
+      retain_live(&mut model.data, &liveness.data);
+      if model.data_count.is_some() {
+        model.data_count = Some(model.data.len() as u32);
+      }
+    },
+    IndexRemap {
+      functions: IndexMap::RemoveAll(&report.functions),
+      globals: IndexMap::RemoveAll(&report.globals),
+      types: IndexMap::RemoveAll(&report.types),
+      elements: IndexMap::RemoveAll(&report.elements),
+      data: IndexMap::RemoveAll(&report.data),
+      ..Default::default()
+    },
+  )?;
+  Ok(report)
+}
+
+/// Reachability of items in index spaces with removable items, indexed by item indexes.
+struct Liveness {
+  functions: Vec<bool>,
+  globals: Vec<bool>,
+  types: Vec<bool>,
+  elements: Vec<bool>,
+  data: Vec<bool>,
+  /// Reachable functions whose bodies were not visited yet.
+  pending_functions: Vec<u32>,
+  /// Reachable globals whose initializers were not visited yet.
+  pending_globals: Vec<u32>,
+  /// Reachable element segments whose items were not visited yet.
+  pending_elements: Vec<u32>,
+  /// Functions referenced by `ref.func` in reachable function bodies.
+  referenced_functions: BTreeSet<u32>,
+}
+
+impl Liveness {
+  /// Creates the reachability of items of the model, only imported functions and globals are reachable.
+  fn new(model: &Model) -> Self {
+    let imported_function_count = model.imported_function_count() as usize;
+    let imported_global_count = model.imported_global_count() as usize;
+    let mut functions = vec![false; model.function_count() as usize];
+    functions[..imported_function_count].fill(true);
+    let mut globals = vec![false; imported_global_count + model.globals.len()];
+    globals[..imported_global_count].fill(true);
+    Self {
+      functions,
+      globals,
+      types: vec![false; model.type_count() as usize],
+      elements: vec![false; model.elements.len()],
+      data: vec![false; model.data.len()],
+      pending_functions: vec![],
+      pending_globals: vec![],
+      pending_elements: vec![],
+      referenced_functions: BTreeSet::new(),
+    }
+  }
+
+  /// Marks the roots of the module as reachable.
+  fn visit_roots(&mut self, model: &Model) -> WasmarinResult<()> {
+    for import in &model.imports {
+      match import.ty {
+        TypeRef::Func(mut type_index) => self.type_(&mut type_index)?,
+        TypeRef::Tag(mut tag_type) => self.type_(&mut tag_type.func_type_idx)?,
+        _ => {}
+      }
+    }
+    for export in &model.exports {
+      match export.kind {
+        ExternalKind::Func => self.function(&mut export.index.clone())?,
+        ExternalKind::Global => self.global(&mut export.index.clone())?,
+        _ => {}
+      }
+    }
+    if let Some(mut start_function_index) = model.start_function_index {
+      self.function(&mut start_function_index)?;
+    }
+    for mut tag_type in model.tag_types.iter().copied() {
+      self.type_(&mut tag_type.func_type_idx)?;
+    }
+    for table in &model.tables {
+      if let Some(init_expr) = &table.init_expr {
+        self.visit_const_expr(init_expr)?;
+      }
+    }
+    for (index, element) in model.elements.iter().enumerate() {
+      if !matches!(element.kind, ElementKind::Passive) {
+        self.element(&mut (index as u32))?;
+      }
+    }
+    for (index, data) in model.data.iter().enumerate() {
+      if let DataKind::Active { offset_expr, .. } = &data.kind {
+        self.data(&mut (index as u32))?;
+        self.visit_const_expr(offset_expr)?;
+      }
+    }
+    if let Some(metadata) = &model.metering {
+      self.global(&mut metadata.counter_global_index.clone())?;
+    }
+    Ok(())
+  }
+
+  /// Marks all items referenced from reachable items as reachable, until no new item is reached.
+  fn visit_reachable(&mut self, model: &mut Model) -> WasmarinResult<()> {
+    let imported_function_count = model.imported_function_count();
+    let imported_global_count = model.imported_global_count();
+    loop {
+      if let Some(function_index) = self.pending_functions.pop() {
+        if let Some(position) = function_index.checked_sub(imported_function_count).map(|position| position as usize) {
+          self.type_(&mut model.function_indexes[position].clone())?;
+          for operator in &mut model.code_section_entries[position].operators {
+            if let Operator::RefFunc { function_index } = operator {
+              self.referenced_functions.insert(*function_index);
+            }
+            visit_operator(self, operator)?;
+          }
+        }
+      } else if let Some(global_index) = self.pending_globals.pop() {
+        if let Some(position) = global_index.checked_sub(imported_global_count).map(|position| position as usize) {
+          self.visit_const_expr(&model.globals[position].init_expr)?;
+        }
+      } else if let Some(element_index) = self.pending_elements.pop() {
+        let element = &model.elements[element_index as usize];
+        if let ElementKind::Active { offset_expr, .. } = &element.kind {
+          self.visit_const_expr(offset_expr)?;
+        }
+        match &element.items {
+          ElementItems::Functions(function_indexes) => {
+            for function_index in function_indexes {
+              self.function(&mut function_index.clone())?;
+            }
+          }
+          ElementItems::Expressions(_, const_exprs) => {
+            for const_expr in const_exprs {
+              self.visit_const_expr(const_expr)?;
+            }
+          }
+        }
+      } else {
+        return Ok(());
+      }
+    }
+  }
+
+  /// Returns sorted indexes of functions referenced by `ref.func` in reachable function bodies,
+  /// but not declared by any reachable export, global, table or element segment.
+  fn undeclared_functions(&self, model: &Model) -> WasmarinResult<Vec<u32>> {
+    let mut undeclared_functions = self.referenced_functions.clone();
+    if undeclared_functions.is_empty() {
+      return Ok(vec![]);
+    }
+    for export in &model.exports {
+      if export.kind == ExternalKind::Func {
+        undeclared_functions.remove(&export.index);
+      }
+    }
+    let imported_global_count = model.imported_global_count() as usize;
+    let live_globals = model.globals.iter().zip(&self.globals[imported_global_count..]).filter(|(_, live)| **live);
+    let mut const_exprs: Vec<&ConstExpr> = live_globals.map(|(global, _)| &global.init_expr).collect();
+    const_exprs.extend(model.tables.iter().filter_map(|table| table.init_expr.as_ref()));
+    for element in model.elements.iter().zip(&self.elements).filter(|(_, live)| **live).map(|(element, _)| element) {
+      match &element.items {
+        ElementItems::Functions(function_indexes) => {
+          for function_index in function_indexes {
+            undeclared_functions.remove(function_index);
+          }
+        }
+        ElementItems::Expressions(_, items) => const_exprs.extend(items),
+      }
+    }
+    for const_expr in const_exprs {
+      for operator in const_expr.operators()? {
+        if let Operator::RefFunc { function_index } = operator {
+          undeclared_functions.remove(&function_index);
+        }
+      }
+    }
+    Ok(undeclared_functions.into_iter().collect())
+  }
+
+  /// Marks all items referenced from the constant expression as reachable.
+  fn visit_const_expr(&mut self, const_expr: &ConstExpr) -> WasmarinResult<()> {
+    for operator in &mut const_expr.operators()? {
+      visit_operator(self, operator)?;
+    }
+    Ok(())
+  }
+}
+
+impl IndexVisitor for Liveness {
+  fn type_(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    mark(&mut self.types, *index);
+    Ok(())
+  }
+
+  fn function(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    if mark(&mut self.functions, *index) {
+      self.pending_functions.push(*index);
+    }
+    Ok(())
+  }
+
+  fn table(&mut self, _index: &mut u32) -> WasmarinResult<()> {
+    Ok(())
+  }
+
+  fn memory(&mut self, _index: &mut u32) -> WasmarinResult<()> {
+    Ok(())
+  }
+
+  fn global(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    if mark(&mut self.globals, *index) {
+      self.pending_globals.push(*index);
+    }
+    Ok(())
+  }
+
+  fn tag(&mut self, _index: &mut u32) -> WasmarinResult<()> {
+    Ok(())
+  }
+
+  fn element(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    if mark(&mut self.elements, *index) {
+      self.pending_elements.push(*index);
+    }
+    Ok(())
+  }
+
+  fn data(&mut self, index: &mut u32) -> WasmarinResult<()> {
+    mark(&mut self.data, *index);
+    Ok(())
+  }
+}
+
+/// Marks the item as reachable, returns `true` iff it was not reachable before.
+fn mark(live: &mut [bool], index: u32) -> bool {
+  match live.get_mut(index as usize) {
+    Some(live) if !*live => {
+      *live = true;
+      true
+    }
+    _ => false,
+  }
+}
+
+/// Returns sorted indexes of unreachable items, starting at the given index.
+fn removed(live: &[bool], first_index: u32) -> Vec<u32> {
+  (first_index..live.len() as u32).filter(|index| !live[*index as usize]).collect()
+}
+
+/// Keeps only reachable items, in order.
+fn retain_live<T>(items: &mut Vec<T>, live: &[bool]) {
+  let mut live = live.iter();
+  items.retain(|_| live.next().copied().unwrap_or(true));
+}
@@ -0,0 +1,70 @@
+//! # Removal of metering instrumentation
+
+use crate::metering::{Metering, CHARGE_LENGTH};
+use crate::names::renumber_labels;
+use crate::{Model, WasmarinError, WasmarinResult, REMAINING_POINTS_EXPORT_NAME, START_FUNCTION_EXPORT_NAME};
+use wasmparser::Operator;
+
+/// Removes metering instrumentation from the model of an instrumented module.
+///
+/// The model must be parsed with [InstrumentedInput::Skip](crate::InstrumentedInput::Skip),
+/// so it holds the metering metadata locating the global variable storing remaining points.
+/// All charge sequences, the global variable and its export are removed, and the original
+/// start function is restored. Encoding the returned model without metering produces
+/// the same binary as encoding the originally uploaded module.
+///
+/// Fails when the module is not instrumented, or when the global variable storing remaining points
+/// is used in any other way than injected by metering.
+pub fn uninstrument(mut model: Model) -> WasmarinResult<Model> {
+  let metadata = model.metering.take().ok_or_else(|| WasmarinError::new("module is not instrumented"))?;
+  let counter = metadata.counter_global_index;
+  let imported_global_count = model.imported_global_count();
+  if counter.checked_sub(imported_global_count).map(|index| index as usize + 1) != Some(model.globals.len()) {
+    return Err(WasmarinError::new(format!("global {counter} storing remaining points is not the last global")));
+  }
+  let imported_function_count = model.imported_function_count();
+  for (index, code_section_entry) in model.code_section_entries.iter_mut().enumerate() {
+    let instrumented = std::mem::take(&mut code_section_entry.operators);
+    let instrumented_offsets = code_section_entry.original_offsets.take().filter(|offsets| offsets.len() == instrumented.len());
+    let mut original_offsets = instrumented_offsets.as_ref().map(|_| vec![]);
+    let mut position = 0;
+    while position < instrumented.len() {
+      if Metering::is_charge(&instrumented[position..], counter) {
+        position += CHARGE_LENGTH;
+        continue;
+      }
+      if let (Some(original_offsets), Some(instrumented_offsets)) = (&mut original_offsets, &instrumented_offsets) {
+        original_offsets.push(instrumented_offsets[position]);
+      }
+      if let Operator::GlobalGet { global_index } | Operator::GlobalSet { global_index } = instrumented[position] {
+        if global_index == counter {
+          return Err(WasmarinError::new(format!(
+            "global {counter} storing remaining points is accessed outside of charges in function {}",
+            imported_function_count + index as u32
+          )));
+        }
+      }
+      code_section_entry.operators.push(instrumented[position].clone());
+      position += 1;
+    }
+    code_section_entry.original_offsets = original_offsets;
+    if code_section_entry.operators.len() != instrumented.len() {
+      code_section_entry.operand_types = None;
+      code_section_entry.touched = true;
+      let labels = Metering::charge_labels(&instrumented, counter);
+      renumber_labels(&mut code_section_entry.label_names, labels.into_iter().map(|charge| (true, !charge)));
+    }
+  }
+  if let Some(position) = model.exports.iter().position(|export| export.name == START_FUNCTION_EXPORT_NAME) {
+    if model.start_function_index.is_some() {
+      return Err(WasmarinError::new("instrumented module must not have a start function"));
+    }
+    model.start_function_index = Some(model.exports.remove(position).index);
+  }
+  model.exports.retain(|export| export.name != REMAINING_POINTS_EXPORT_NAME);
+  model.globals.pop();
+  if let Some(names) = &mut model.names {
+    names.globals.remove(&counter);
+  }
+  Ok(model)
+}
@@ -0,0 +1,73 @@
+//! # Helpers shared by tests
+
+/// Instruments the module with metering, calls the exported function `fun` with the given arguments
+/// and returns the number of points consumed by this call.
+pub fn consumed_points(wat_str: &str, args: &[wasmtime::Val]) -> i64 {
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = wasmarin::Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let wasm_bytes = wasmarin::Encoder::new_with_metering().encode(model).unwrap();
+  let engine = wasmtime::Engine::default();
+  let module = wasmtime::Module::from_binary(&engine, &wasm_bytes).unwrap();
+  let mut store = wasmtime::Store::new(&engine, ());
+  let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+  let remaining_points = instance.get_global(&mut store, wasmarin::REMAINING_POINTS_EXPORT_NAME).unwrap();
+  remaining_points.set(&mut store, wasmtime::Val::I64(1000)).unwrap();
+  let fun = instance.get_func(&mut store, "fun").unwrap();
+  let mut results = vec![wasmtime::Val::I32(0); fun.ty(&store).results().len()];
+  fun.call(&mut store, args, &mut results).unwrap();
+  1000 - remaining_points.get(&mut store).i64().unwrap()
+}
+
+/// Instruments the module with the given metering and returns the instrumented binary.
+pub fn instrument(wat_str: &str, metering: wasmarin::Metering) -> Vec<u8> {
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = wasmarin::Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  wasmarin::Encoder::new().with_pass(metering).encode(model).unwrap()
+}
+
+/// Returns the number of injected checks of the remaining points.
+pub fn checks(wasm_bytes: &[u8]) -> usize {
+  wasmprinter::print_bytes(wasm_bytes).unwrap().matches("i64.lt_s").count()
+}
+
+/// Calls the function `fun` of the instrumented module with the given points and arguments,
+/// returns its result (`None` when the call traps) and the number of consumed points.
+pub fn call(wasm_bytes: &[u8], points: i64, args: &[wasmtime::Val]) -> (Option<i32>, i64) {
+  let mut config = wasmtime::Config::new();
+  config.wasm_exceptions(true);
+  let engine = wasmtime::Engine::new(&config).unwrap();
+  let module = wasmtime::Module::from_binary(&engine, wasm_bytes).unwrap();
+  let mut store = wasmtime::Store::new(&engine, ());
+  let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+  let remaining_points = instance.get_global(&mut store, wasmarin::REMAINING_POINTS_EXPORT_NAME).unwrap();
+  remaining_points.set(&mut store, wasmtime::Val::I64(points)).unwrap();
+  let fun = instance.get_func(&mut store, "fun").unwrap();
+  let mut results = [wasmtime::Val::I32(0)];
+  let result = fun.call(&mut store, args, &mut results).ok().map(|_| results[0].unwrap_i32());
+  (result, points - remaining_points.get(&mut store).i64().unwrap())
+}
+
+/// Appends custom sections with the given names and sizes to the WASM binary.
+pub fn with_custom_sections(mut wasm_bytes: Vec<u8>, sections: &[(&str, usize)]) -> Vec<u8> {
+  for (name, size) in sections {
+    let mut module = wasm_encoder::Module::new();
+    module.section(&wasm_encoder::CustomSection {
+      name: (*name).into(),
+      data: vec![7; *size].into(),
+    });
+    // Skip the header of the module holding the custom section.
+    wasm_bytes.extend_from_slice(&module.finish()[8..]);
+  }
+  wasm_bytes
+}
+
+/// Returns names of custom sections of the module.
+pub fn custom_section_names(wasm_bytes: &[u8]) -> Vec<String> {
+  wasmparser::Parser::new(0)
+    .parse_all(wasm_bytes)
+    .filter_map(|payload| match payload.unwrap() {
+      wasmparser::Payload::CustomSection(reader) => Some(reader.name().to_string()),
+      _ => None,
+    })
+    .collect()
+}
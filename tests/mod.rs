@@ -1,13 +1,39 @@
 mod bulk_memory_estimations;
 mod estimations;
+mod helpers;
 mod metering;
 mod round_trip;
 mod semantics;
+mod test_admission;
+mod test_call_graph;
+mod test_cfg;
+mod test_charge_placement;
+mod test_counted_loops_metering;
+mod test_custom_section_filter;
 mod test_data_drop_metering;
+mod test_dwarf_sections;
+mod test_elem_drop_metering;
 mod test_features;
+mod test_gas_bound;
 mod test_globals;
+mod test_host_abi;
+mod test_instantiation_metering;
+mod test_interface;
 mod test_memory_copy_metering;
 mod test_metering;
+mod test_metering_section;
+mod test_model_editing;
+mod test_names;
+mod test_offset_map;
+mod test_operand_types;
+mod test_owned_model;
+mod test_parallel_encoding;
 mod test_parsing_globals;
+mod test_pipeline;
+mod test_section_order;
 mod test_simple_round_trip;
 mod test_simple_wasmtime;
+mod test_streaming_encoder;
+mod test_tree_shaking;
+mod test_uninstrument;
+mod test_validation;
@@ -0,0 +1,140 @@
+use wasmarin::{AdmissionPolicy, Limit, Parser, Violation};
+
+const CONTRACT: &str = r#"
+  (module
+    (import "env" "log" (func $log (param i32)))
+    (import "env" "abort" (func $abort))
+    (memory 2 10)
+    (table 4 funcref)
+    (global $g1 (mut i32) (i32.const 0))
+    (global $g2 (mut i32) (i32.const 0))
+    (func $small
+      nop
+    )
+    (func $big (export "big") (param i32) (result i32)
+      (local i32 i64 i64)
+      block
+        block
+          block
+            local.get 0
+            br_table 0 1 2 0
+          end
+        end
+      end
+      i32.const 0
+    )
+    (export "memory" (memory 0))
+  )
+"#;
+
+/// Checks the contract against the given policy.
+fn check(policy: &AdmissionPolicy) -> Vec<Violation> {
+  policy.check_wasm_bytes(&wat::parse_str(CONTRACT).unwrap()).unwrap()
+}
+
+#[test]
+fn contract_within_limits_should_be_admitted() {
+  let policy = AdmissionPolicy {
+    max_functions: Some(2),
+    max_locals_per_function: Some(3),
+    max_total_locals: Some(3),
+    max_function_body_size: Some(100),
+    max_module_size: Some(1000),
+    max_initial_memory_pages: Some(2),
+    max_memory_pages: Some(10),
+    max_table_size: Some(u32::MAX as u64),
+    max_imports: Some(2),
+    max_exports: Some(2),
+    max_globals: Some(2),
+    max_br_table_length: Some(4),
+    max_nesting_depth: Some(3),
+  };
+  assert_eq!(Vec::<Violation>::new(), check(&policy));
+  assert_eq!(Vec::<Violation>::new(), check(&AdmissionPolicy::new()));
+}
+
+#[test]
+fn all_violations_should_be_reported() {
+  let policy = AdmissionPolicy {
+    max_functions: Some(1),
+    max_locals_per_function: Some(2),
+    max_total_locals: Some(2),
+    max_function_body_size: Some(10),
+    max_module_size: Some(100),
+    max_initial_memory_pages: Some(1),
+    max_memory_pages: Some(5),
+    max_table_size: Some(100),
+    max_imports: Some(1),
+    max_exports: Some(1),
+    max_globals: Some(1),
+    max_br_table_length: Some(3),
+    max_nesting_depth: Some(2),
+  };
+  let violations = check(&policy);
+  let limits: Vec<(Limit, Option<u32>)> = violations.iter().map(|violation| (violation.limit, violation.index)).collect();
+  assert_eq!(
+    vec![
+      (Limit::ModuleSize, None),
+      (Limit::Functions, None),
+      (Limit::Imports, None),
+      (Limit::Exports, None),
+      (Limit::Globals, None),
+      (Limit::InitialMemoryPages, Some(0)),
+      (Limit::MaximumMemoryPages, Some(0)),
+      (Limit::TableSize, Some(0)),
+      (Limit::LocalsPerFunction, Some(3)),
+      (Limit::FunctionBodySize, Some(3)),
+      (Limit::NestingDepth, Some(3)),
+      (Limit::BrTableLength, Some(3)),
+      (Limit::TotalLocals, None),
+    ],
+    limits
+  );
+  assert_eq!("number of locals of function 3 is 3, maximum allowed is 2", violations[8].to_string());
+  assert_eq!("control nesting depth of function 3 is 3, maximum allowed is 2", violations[10].to_string());
+  assert_eq!("br_table length of function 3 is 4, maximum allowed is 3", violations[11].to_string());
+  // Table without maximum may grow up to the limit of its index type.
+  assert_eq!(u32::MAX as u64, violations[7].actual);
+}
+
+#[test]
+fn memory_without_maximum_should_violate_memory_limit() {
+  let wasm_bytes = wat::parse_str(r#"(module (import "env" "memory" (memory 1)))"#).unwrap();
+  let policy = AdmissionPolicy {
+    max_memory_pages: Some(16),
+    ..Default::default()
+  };
+  let violations = policy.check_wasm_bytes(&wasm_bytes).unwrap();
+  assert_eq!(1, violations.len());
+  assert_eq!("maximum memory pages of memory 0 is 65536, maximum allowed is 16", violations[0].to_string());
+}
+
+#[test]
+fn function_body_size_should_be_the_original_size() {
+  #[rustfmt::skip]
+  let wasm_bytes = [
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    // Type section: (func (result i32))
+    0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+    // Function section
+    0x03, 0x02, 0x01, 0x00,
+    // Code section: i32.const 0 with the immediate padded to 4 bytes
+    0x0a, 0x09, 0x01, 0x07, 0x00, 0x41, 0x80, 0x80, 0x80, 0x00, 0x0b,
+  ];
+  let policy = AdmissionPolicy {
+    max_function_body_size: Some(4),
+    ..Default::default()
+  };
+  let violations = policy.check_wasm_bytes(&wasm_bytes).unwrap();
+  assert_eq!(1, violations.len());
+  assert_eq!("function body size of function 0 is 7, maximum allowed is 4", violations[0].to_string());
+  // Touched function is encoded again, without padding.
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  model.code_section_entries[0].touched = true;
+  assert_eq!(Vec::<Violation>::new(), policy.check(&model).unwrap());
+}
+
+#[test]
+fn invalid_module_should_fail() {
+  assert!(AdmissionPolicy::new().check_wasm_bytes(&[0, 1, 2, 3]).is_err());
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use wasmarin::{CallGraph, Parser};
+
+/// Builds the call graph of the module.
+fn call_graph(wat_str: &str) -> CallGraph {
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  CallGraph::new(&model)
+}
+
+const ACYCLIC: &str = r#"
+  (module
+    (import "env" "log" (func $log (param i32)))
+    (import "env" "abort" (func $abort))
+    (func $leaf (param i32)
+      local.get 0
+      call $log
+    )
+    (func $middle (param i32)
+      local.get 0
+      call $leaf
+    )
+    (func (export "top") (param i32)
+      local.get 0
+      call $middle
+      local.get 0
+      call $leaf
+    )
+    (func (export "other")
+      return_call $abort
+    )
+  )
+"#;
+
+const RECURSIVE: &str = r#"
+  (module
+    (func $even (param i32) (result i32)
+      local.get 0
+      i32.eqz
+      if (result i32)
+        i32.const 1
+      else
+        local.get 0
+        i32.const 1
+        i32.sub
+        call $odd
+      end
+    )
+    (func $odd (param i32) (result i32)
+      local.get 0
+      i32.eqz
+      if (result i32)
+        i32.const 0
+      else
+        local.get 0
+        i32.const 1
+        i32.sub
+        call $even
+      end
+    )
+    (func $self (export "self")
+      call $self
+    )
+    (func (export "main") (result i32)
+      i32.const 10
+      call $even
+    )
+  )
+"#;
+
+const INDIRECT: &str = r#"
+  (module
+    (import "env" "host" (func $host (result i32)))
+    (type $t (func (result i32)))
+    (table 2 funcref)
+    (elem (i32.const 0) $one $host)
+    (func $one (result i32)
+      i32.const 1
+    )
+    (func $two (param i32) (result i32)
+      local.get 0
+    )
+    (func (export "dispatch") (param i32) (result i32)
+      local.get 0
+      call_indirect (type $t)
+    )
+  )
+"#;
+
+#[test]
+fn direct_calls_should_be_collected() {
+  let call_graph = call_graph(ACYCLIC);
+  assert_eq!(2, call_graph.imported_function_count);
+  assert_eq!(vec![vec![], vec![], vec![0], vec![2], vec![2, 3], vec![1]], call_graph.callees);
+  assert!(call_graph.is_import(1));
+  assert!(!call_graph.is_import(2));
+}
+
+#[test]
+fn acyclic_call_graph_should_have_bounded_depth() {
+  let call_graph = call_graph(ACYCLIC);
+  assert!(!call_graph.is_recursive());
+  assert_eq!(Some(vec![1, 1, 2, 3, 4, 2]), call_graph.call_depths());
+  assert_eq!(Some(4), call_graph.max_call_depth());
+}
+
+#[test]
+fn exports_should_reach_imports() {
+  let wasm_bytes = wat::parse_str(ACYCLIC).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let call_graph = CallGraph::new(&model);
+  let expected = BTreeMap::from([("top", vec![("env", "log")]), ("other", vec![("env", "abort")])]);
+  assert_eq!(expected, call_graph.reachable_imports_by_export(&model));
+}
+
+#[test]
+fn recursion_should_be_detected() {
+  let call_graph = call_graph(RECURSIVE);
+  assert!(call_graph.is_recursive());
+  assert_eq!(vec![0, 1, 2], call_graph.recursive_functions());
+  assert_eq!(vec![vec![0, 1], vec![2], vec![3]], call_graph.strongly_connected_components());
+  assert_eq!(None, call_graph.call_depths());
+  assert_eq!(None, call_graph.max_call_depth());
+  assert_eq!(vec![0, 1], call_graph.reachable_functions(3));
+}
+
+#[test]
+fn indirect_calls_should_be_resolved_through_element_segments() {
+  let call_graph = call_graph(INDIRECT);
+  // Only functions placed in the table and matching the called type are the targets.
+  assert_eq!(vec![0, 1], call_graph.callees[3]);
+  assert_eq!(vec![0], call_graph.reachable_imports(3));
+  assert_eq!(Some(2), call_graph.max_call_depth());
+}
+
+#[test]
+fn indirect_calls_through_host_tables_should_target_all_functions_of_matching_type() {
+  for table in [r#"(table (export "table") 2 funcref)"#, r#"(import "env" "table" (table 2 funcref))"#] {
+    let call_graph = call_graph(&INDIRECT.replace("(table 2 funcref)", table).replace("(elem (i32.const 0) $one $host)", ""));
+    // The host may store any function in the table.
+    assert_eq!(vec![0, 1], call_graph.callees[3]);
+  }
+}
+
+#[test]
+fn deep_call_chain_should_not_overflow() {
+  let functions: String = (0..10_000).map(|index| format!("(func $f{index} call $f{})\n", index + 1)).collect();
+  let call_graph = call_graph(&format!("(module {functions} (func $f10000))"));
+  assert_eq!(Some(10_001), call_graph.max_call_depth());
+}
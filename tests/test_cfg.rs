@@ -0,0 +1,279 @@
+use wasmarin::cfg::{ControlFlowGraph, Edge, EdgeKind, StructureKind};
+use wasmarin::Parser;
+
+/// Builds the control-flow graph of the first function defined in the module.
+fn cfg(wat_str: &str) -> ControlFlowGraph {
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  ControlFlowGraph::try_from(&model.code_section_entries[0]).unwrap()
+}
+
+/// Returns the operators of the first function body, without validation.
+fn operators(wasm_bytes: &[u8]) -> Vec<wasmparser::Operator<'_>> {
+  for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+    if let wasmparser::Payload::CodeSectionEntry(body) = payload.unwrap() {
+      return body.get_operators_reader().unwrap().into_iter().map(Result::unwrap).collect();
+    }
+  }
+  vec![]
+}
+
+/// Returns the successors of the given block as pairs of target and kind.
+fn successors(cfg: &ControlFlowGraph, block: usize) -> Vec<(usize, EdgeKind)> {
+  cfg.blocks[block].successors.iter().map(|Edge { target, kind }| (*target, *kind)).collect()
+}
+
+#[test]
+fn straight_line_code_should_form_single_block() {
+  let cfg = cfg(
+    r#"
+    (module
+      (func (result i32)
+        i32.const 1
+        i32.const 2
+        i32.add
+      )
+    )
+    "#,
+  );
+  assert_eq!(1, cfg.blocks.len());
+  assert_eq!(0..4, cfg.blocks[0].operators);
+  assert!(cfg.blocks[0].successors.is_empty());
+  assert_eq!(1, cfg.structures.len());
+  assert_eq!(0, cfg.max_depth());
+}
+
+#[test]
+fn loop_header_should_be_recognized() {
+  let cfg = cfg(
+    r#"
+    (module
+      (func (param i32)
+        loop
+          local.get 0
+          i32.const 1
+          i32.sub
+          local.tee 0
+          br_if 0
+        end
+      )
+    )
+    "#,
+  );
+  // [loop], [local.get ... br_if], [end], [end]
+  assert_eq!(4, cfg.blocks.len());
+  assert_eq!(vec![1], cfg.loop_headers());
+  assert_eq!(vec![(1, EdgeKind::Fallthrough)], successors(&cfg, 0));
+  assert_eq!(vec![(2, EdgeKind::Fallthrough), (1, EdgeKind::Branch)], successors(&cfg, 1));
+  assert_eq!(vec![0, 1], cfg.blocks[1].predecessors);
+  assert_eq!(Some(1), cfg.blocks[2].innermost_loop);
+  assert_eq!(None, cfg.blocks[3].innermost_loop);
+  assert_eq!(StructureKind::Loop, cfg.structures[cfg.blocks[1].structure].kind);
+  assert_eq!(0, cfg.blocks[3].structure);
+}
+
+#[test]
+fn br_table_should_branch_to_all_targets() {
+  let cfg = cfg(
+    r#"
+    (module
+      (func (param i32) (result i32)
+        block
+          block
+            block
+              local.get 0
+              br_table 0 1 2 0
+            end
+            i32.const 10
+            return
+          end
+          i32.const 20
+          return
+        end
+        i32.const 30
+      )
+    )
+    "#,
+  );
+  // [block block block local.get br_table], [end], [i32.const return], [end], [i32.const return], [end], [i32.const end]
+  assert_eq!(7, cfg.blocks.len());
+  // Branch targets are the blocks following the `end` operators of the labels.
+  assert_eq!(vec![(2, EdgeKind::Branch), (4, EdgeKind::Branch), (6, EdgeKind::Branch)], successors(&cfg, 0));
+  assert_eq!(
+    vec![true, false, true, false, true, false, true],
+    (0..7).map(|block| cfg.is_reachable(block)).collect::<Vec<_>>()
+  );
+  assert_eq!(3, cfg.max_depth());
+  assert_eq!(
+    vec![None, Some(0), Some(1), Some(2)],
+    cfg.structures.iter().map(|structure| structure.parent).collect::<Vec<_>>()
+  );
+  assert_eq!(1..9, cfg.structures[2].operators);
+}
+
+#[test]
+fn if_else_should_branch_to_both_arms() {
+  let cfg = cfg(
+    r#"
+    (module
+      (func (param i32) (result i32)
+        local.get 0
+        if (result i32)
+          i32.const 1
+        else
+          i32.const 2
+        end
+      )
+    )
+    "#,
+  );
+  // [local.get if], [i32.const else], [i32.const end], [end]
+  assert_eq!(4, cfg.blocks.len());
+  assert_eq!(vec![(1, EdgeKind::Branch), (2, EdgeKind::Branch)], successors(&cfg, 0));
+  assert_eq!(vec![(3, EdgeKind::Fallthrough)], successors(&cfg, 1));
+  assert_eq!(vec![(3, EdgeKind::Fallthrough)], successors(&cfg, 2));
+  assert_eq!(StructureKind::If, cfg.structures[cfg.blocks[2].structure].kind);
+}
+
+#[test]
+fn dead_code_should_be_unreachable() {
+  let cfg = cfg(
+    r#"
+    (module
+      (func (result i32)
+        block
+          br 0
+          i32.const 1
+          drop
+        end
+        i32.const 2
+      )
+    )
+    "#,
+  );
+  assert_eq!(3, cfg.blocks.len());
+  assert!(cfg.is_reachable(0));
+  assert!(!cfg.is_reachable(1));
+  assert!(cfg.is_reachable(2));
+  assert_eq!(vec![1, 0], cfg.blocks[2].predecessors);
+}
+
+#[test]
+fn try_table_handlers_should_be_reached_by_exception_edges() {
+  let cfg = cfg(
+    r#"
+    (module
+      (tag $e)
+      (func (result i32)
+        block $handler
+          try_table (catch $e $handler)
+            call $thrower
+          end
+          i32.const 1
+          return
+        end
+        i32.const 2
+      )
+      (func $thrower
+        throw $e
+      )
+    )
+    "#,
+  );
+  // [block try_table call end], [i32.const return], [end], [i32.const end]
+  assert_eq!(4, cfg.blocks.len());
+  assert_eq!(vec![(1, EdgeKind::Fallthrough), (3, EdgeKind::Exception)], successors(&cfg, 0));
+  assert!(!cfg.is_reachable(2));
+  assert_eq!(StructureKind::TryTable, cfg.structures[2].kind);
+  assert_eq!(1..4, cfg.structures[2].operators);
+}
+
+#[test]
+fn legacy_try_handlers_should_be_reached_by_exception_edges() {
+  // Legacy exception handling is not accepted by the parser, so operators are read directly.
+  let wasm_bytes = wat::parse_str(
+    r#"
+    (module
+      (tag $e)
+      (func (result i32)
+        try (result i32)
+          try (result i32)
+            i32.const 0
+            throw $e
+          delegate 0
+        catch $e
+          i32.const 1
+        catch_all
+          i32.const 2
+        end
+      )
+    )
+    "#,
+  )
+  .unwrap();
+  let cfg = ControlFlowGraph::new(&operators(&wasm_bytes)).unwrap();
+  // [try try i32.const throw], [delegate], [catch], [i32.const catch_all], [i32.const end], [end]
+  assert_eq!(6, cfg.blocks.len());
+  // Exception thrown in the inner `try` is delegated to the handlers of the outer `try`.
+  assert_eq!(vec![(3, EdgeKind::Exception), (4, EdgeKind::Exception)], successors(&cfg, 0));
+  // Neither `delegate` nor the first `catch` can be reached, both are preceded by `throw`.
+  assert!(!cfg.is_reachable(1));
+  assert!(!cfg.is_reachable(2));
+  assert!(cfg.is_reachable(5));
+  assert_eq!(
+    vec![StructureKind::Function, StructureKind::Try, StructureKind::Try],
+    cfg.structures.iter().map(|structure| structure.kind).collect::<Vec<_>>()
+  );
+  assert_eq!(1..5, cfg.structures[2].operators);
+}
+
+#[test]
+fn graph_should_be_rendered_in_dot_format() {
+  let cfg = cfg(
+    r#"
+    (module
+      (func (param i32)
+        loop
+          local.get 0
+          br_if 0
+        end
+        return
+        nop
+      )
+    )
+    "#,
+  );
+  let expected = r#"digraph cfg {
+  node [shape=box];
+  b0 [label="b0\n0..1"];
+  b1 [label="b1\n1..3", peripheries=2];
+  b2 [label="b2\n3..4"];
+  b3 [label="b3\n4..5"];
+  b4 [label="b4\n5..7", color=gray, fontcolor=gray];
+  b0 -> b1 [style=dashed];
+  b1 -> b2 [style=dashed];
+  b1 -> b1 [style=solid];
+  b2 -> b3 [style=dashed];
+}
+"#;
+  assert_eq!(expected, cfg.to_dot());
+}
+
+#[test]
+fn malformed_function_bodies_should_be_rejected() {
+  use wasmparser::Operator;
+  // Branch to a label out of scope.
+  assert!(ControlFlowGraph::new(&[Operator::Br { relative_depth: 1 }, Operator::End]).is_err());
+  // Operator following the final `end` operator.
+  assert!(ControlFlowGraph::new(&[Operator::End, Operator::End]).is_err());
+  // Function body not terminated with the `end` operator.
+  assert!(ControlFlowGraph::new(&[
+    Operator::Block {
+      blockty: wasmparser::BlockType::Empty
+    },
+    Operator::End
+  ])
+  .is_err());
+  // `else` without enclosing `if`.
+  assert!(ControlFlowGraph::new(&[Operator::Else, Operator::End]).is_err());
+}
@@ -0,0 +1,142 @@
+use crate::helpers::{call, checks, instrument};
+use wasmarin::{Encoder, Metering, Parser};
+
+const CALLS: &str = r#"
+  (module
+    (func $one (result i32)
+      i32.const 1
+    )
+    (func (export "fun") (param i32) (result i32)
+      call $one
+      call $one
+      i32.add
+      call $one
+      i32.add
+    )
+  )
+"#;
+
+const IF_ELSE: &str = r#"
+  (module
+    (func (export "fun") (param i32) (result i32)
+      local.get 0
+      if (result i32)
+        i32.const 10
+      else
+        i32.const 20
+        i32.const 1
+        i32.add
+      end
+      i32.const 1
+      i32.add
+    )
+  )
+"#;
+
+const EARLY_EXIT: &str = r#"
+  (module
+    (func (export "fun") (param i32) (result i32)
+      block
+        local.get 0
+        br_if 0
+        i32.const 1
+        drop
+        i32.const 2
+        drop
+      end
+      i32.const 7
+    )
+  )
+"#;
+
+const DEAD_CODE: &str = r#"
+  (module
+    (func (export "fun") (param i32) (result i32)
+      block
+        br 0
+        i32.const 1
+        drop
+      end
+      i32.const 7
+    )
+  )
+"#;
+
+const TRY_TABLE: &str = r#"
+  (module
+    (tag $e)
+    (func $thrower (param i32)
+      local.get 0
+      if
+        throw $e
+      end
+    )
+    (func (export "fun") (param i32) (result i32)
+      block $handler
+        try_table (catch $e $handler)
+          local.get 0
+          call $thrower
+        end
+        i32.const 1
+        return
+      end
+      i32.const 2
+    )
+  )
+"#;
+
+#[test]
+fn calls_should_not_be_charged_separately() {
+  let wasm_bytes = instrument(CALLS, Metering::new());
+  // Single check at the entry of each function.
+  assert_eq!(2, checks(&wasm_bytes));
+  // 5 points in the caller, 1 point for each of three calls.
+  assert_eq!((Some(3), 8), call(&wasm_bytes, 1000, &[wasmtime::Val::I32(0)]));
+}
+
+#[test]
+fn if_else_arms_should_be_charged_separately() {
+  let wasm_bytes = instrument(IF_ELSE, Metering::new());
+  // Function entry, both arms and the code following `if`.
+  assert_eq!(4, checks(&wasm_bytes));
+  assert_eq!((Some(11), 6), call(&wasm_bytes, 1000, &[wasmtime::Val::I32(1)]));
+  assert_eq!((Some(22), 7), call(&wasm_bytes, 1000, &[wasmtime::Val::I32(0)]));
+}
+
+#[test]
+fn code_skipped_by_conditional_branch_should_be_charged_in_advance() {
+  let wasm_bytes = instrument(EARLY_EXIT, Metering::new());
+  // Code following `br_if` is merged into the charge at the function entry.
+  assert_eq!(2, checks(&wasm_bytes));
+  assert_eq!((Some(7), 8), call(&wasm_bytes, 1000, &[wasmtime::Val::I32(0)]));
+  // Never less than the exact cost of the path (4 points).
+  assert_eq!((Some(7), 8), call(&wasm_bytes, 1000, &[wasmtime::Val::I32(1)]));
+}
+
+#[test]
+fn unreachable_code_should_not_be_charged() {
+  let wasm_bytes = instrument(DEAD_CODE, Metering::new());
+  assert_eq!(2, checks(&wasm_bytes));
+  assert_eq!((Some(7), 3), call(&wasm_bytes, 1000, &[wasmtime::Val::I32(0)]));
+}
+
+#[test]
+fn exception_handlers_should_be_charged() {
+  let wasm_bytes = instrument(TRY_TABLE, Metering::new());
+  // Caller: function entry and handler, thrower: function entry and `throw`, the code after `if` costs nothing.
+  assert_eq!(4, checks(&wasm_bytes));
+  assert_eq!((Some(1), 8), call(&wasm_bytes, 1000, &[wasmtime::Val::I32(0)]));
+  // Never less than the exact cost of the path (8 points).
+  assert_eq!((Some(2), 10), call(&wasm_bytes, 1000, &[wasmtime::Val::I32(1)]));
+}
+
+#[test]
+fn burner_should_be_instrumented_with_fewer_checks() {
+  let wasm_bytes = std::fs::read("tests/contracts/burner.wasm").unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let instrumented = Encoder::new_with_metering().encode(model).unwrap();
+  wasmparser::Validator::new().validate_all(&instrumented).unwrap();
+  // Charging before every branching operator injected 13666 checks, calls and ends
+  // of blocks not targeted by any branch are not charged anymore.
+  assert_eq!(5967, checks(&instrumented));
+}
@@ -0,0 +1,149 @@
+use crate::helpers::{call, checks, instrument};
+use wasmarin::Metering;
+
+const COUNTED_LOOP: &str = r#"
+  (module
+    (func (export "fun") (result i32)
+      (local $i i32) (local $sum i32)
+      i32.const 0
+      local.set $i
+      loop
+        local.get $sum
+        local.get $i
+        i32.add
+        local.set $sum
+        local.get $i
+        i32.const 1
+        i32.add
+        local.tee $i
+        i32.const 10
+        i32.lt_s
+        br_if 0
+      end
+      local.get $sum
+    )
+  )
+"#;
+
+const COUNTDOWN_LOOP: &str = r#"
+  (module
+    (func (export "fun") (result i32)
+      (local $i i32) (local $sum i32)
+      i32.const 5
+      local.set $i
+      loop
+        local.get $sum
+        i32.const 3
+        i32.add
+        local.set $sum
+        local.get $i
+        i32.const 1
+        i32.sub
+        local.tee $i
+        br_if 0
+      end
+      local.get $sum
+    )
+  )
+"#;
+
+const REVERSED_LOOP: &str = r#"
+  (module
+    (func (export "fun") (result i32)
+      (local $i i32) (local $sum i32)
+      i32.const 0
+      local.set $i
+      i32.const 7
+      local.set $sum
+      loop
+        local.get $sum
+        local.get $i
+        i32.add
+        local.set $sum
+        i32.const 10
+        local.get $i
+        i32.const 1
+        i32.add
+        local.tee $i
+        i32.gt_s
+        br_if 0
+      end
+      local.get $sum
+    )
+  )
+"#;
+
+const UNCOUNTED_LOOP: &str = r#"
+  (module
+    (func (export "fun") (param $n i32) (result i32)
+      (local $i i32) (local $sum i32)
+      i32.const 0
+      local.set $i
+      loop
+        local.get $sum
+        local.get $i
+        i32.add
+        local.set $sum
+        local.get $i
+        i32.const 1
+        i32.add
+        local.tee $i
+        local.get $n
+        i32.lt_s
+        br_if 0
+      end
+      local.get $sum
+    )
+  )
+"#;
+
+#[test]
+fn counted_loop_should_be_charged_once() {
+  let wasm_bytes = instrument(COUNTED_LOOP, Metering::new().with_counted_loop_hoisting(true));
+  // Single check before the loop and single check at the end of the function.
+  assert_eq!(2, checks(&wasm_bytes));
+  // 3 points before the loop, 10 iterations of 11 points each, 1 point after the loop.
+  assert_eq!((Some(45), 114), call(&wasm_bytes, 1000, &[]));
+}
+
+#[test]
+fn counted_loop_should_consume_the_same_points_without_hoisting() {
+  let wasm_bytes = instrument(COUNTED_LOOP, Metering::new().with_counted_loop_hoisting(false));
+  assert_eq!(3, checks(&wasm_bytes));
+  assert_eq!((Some(45), 114), call(&wasm_bytes, 1000, &[]));
+}
+
+#[test]
+fn counted_loop_should_not_start_without_enough_points() {
+  let wasm_bytes = instrument(COUNTED_LOOP, Metering::new().with_counted_loop_hoisting(true));
+  // The whole loop is charged before entering it.
+  assert_eq!((None, 113), call(&wasm_bytes, 100, &[]));
+}
+
+#[test]
+fn countdown_loop_should_be_charged_once() {
+  let wasm_bytes = instrument(COUNTDOWN_LOOP, Metering::new().with_counted_loop_hoisting(true));
+  assert_eq!(2, checks(&wasm_bytes));
+  // 3 points before the loop, 5 iterations of 9 points each, 1 point after the loop.
+  assert_eq!((Some(15), 49), call(&wasm_bytes, 1000, &[]));
+}
+
+#[test]
+fn loop_with_limit_before_update_should_be_charged_once() {
+  let wasm_bytes = instrument(REVERSED_LOOP, Metering::new().with_counted_loop_hoisting(true));
+  assert_eq!(2, checks(&wasm_bytes));
+  // 5 points before the loop, 10 iterations of 11 points each, 1 point after the loop.
+  assert_eq!((Some(52), 116), call(&wasm_bytes, 1000, &[]));
+  assert_eq!(
+    (Some(52), 116),
+    call(&instrument(REVERSED_LOOP, Metering::new().with_counted_loop_hoisting(false)), 1000, &[])
+  );
+}
+
+#[test]
+fn uncounted_loop_should_be_charged_on_every_iteration() {
+  let wasm_bytes = instrument(UNCOUNTED_LOOP, Metering::new().with_counted_loop_hoisting(true));
+  assert_eq!(3, checks(&wasm_bytes));
+  // 3 points before the loop, 10 iterations of 11 points each, 1 point after the loop.
+  assert_eq!((Some(45), 114), call(&wasm_bytes, 1000, &[wasmtime::Val::I32(10)]));
+}
@@ -0,0 +1,58 @@
+use crate::helpers::{custom_section_names, with_custom_sections};
+use wasmarin::{CustomSectionFilter, Encoder, Parser, METERING_SECTION_NAME, NAME_SECTION_NAME};
+
+/// Returns a named module with a single function followed by the given custom sections.
+fn module_with_custom_sections(sections: &[(&str, usize)]) -> Vec<u8> {
+  let wasm_bytes = wat::parse_str(r#"(module $contract (func $run (export "run") (param $n i32) (result i32) (local.get $n)))"#).unwrap();
+  with_custom_sections(wasm_bytes, sections)
+}
+
+#[test]
+fn only_allowed_custom_sections_should_be_kept() {
+  let wasm_bytes = module_with_custom_sections(&[("producers", 40), ("contract.abi", 10), (".debug_info", 300)]);
+  let filter = CustomSectionFilter::new().with_allowed("contract.abi");
+  let mut encoder = Encoder::new().with_custom_section_filter(filter);
+  let encoded = encoder.encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  assert_eq!(vec!["contract.abi"], custom_section_names(&encoded));
+  let stripped = encoder.stripped_sections().unwrap();
+  assert_eq!(
+    vec![NAME_SECTION_NAME, "producers", ".debug_info"],
+    stripped.sections.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>()
+  );
+  assert_eq!(wasm_bytes.len() - encoded.len(), stripped.bytes_saved());
+  assert_eq!(1 + 2 + 1 + ".debug_info".len() + 300, stripped.sections[2].1);
+}
+
+#[test]
+fn metering_section_should_never_be_stripped() {
+  let wasm_bytes = module_with_custom_sections(&[("producers", 10)]);
+  let mut encoder = Encoder::new_with_metering().with_custom_section_filter(CustomSectionFilter::new().with_allowed(NAME_SECTION_NAME));
+  let encoded = encoder.encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap();
+  assert_eq!(vec![NAME_SECTION_NAME, METERING_SECTION_NAME], custom_section_names(&encoded));
+  assert_eq!(vec![("producers".to_string(), 22)], encoder.stripped_sections().unwrap().sections);
+  assert!(CustomSectionFilter::new().is_allowed(METERING_SECTION_NAME));
+  // Parsed again, the instrumented module still carries its metering metadata.
+  let model = Parser::new().with_instrumented_input(wasmarin::InstrumentedInput::Skip).parse_wasm_bytes(&encoded).unwrap();
+  assert!(model.metering.is_some());
+}
+
+#[test]
+fn filter_should_strip_local_names_with_name_section() {
+  let wasm_bytes = module_with_custom_sections(&[]);
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let stripped = CustomSectionFilter::new().apply(&mut model);
+  assert_eq!(1, stripped.sections.len());
+  assert!(model.names.is_none());
+  assert!(model.code_section_entries[0].local_names.is_empty());
+  let encoded = Encoder::new().encode(model).unwrap();
+  assert!(custom_section_names(&encoded).is_empty());
+  assert_eq!(wasm_bytes.len() - encoded.len(), stripped.bytes_saved());
+}
+
+#[test]
+fn custom_sections_should_be_kept_without_filter() {
+  let wasm_bytes = module_with_custom_sections(&[("producers", 10)]);
+  let mut encoder = Encoder::new();
+  assert_eq!(wasm_bytes, encoder.encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap());
+  assert!(encoder.stripped_sections().is_none());
+}
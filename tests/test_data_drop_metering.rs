@@ -1,3 +1,5 @@
+use crate::helpers::consumed_points;
+
 #[test]
 fn data_drop_metering_should_work() {
   let wat_str = r#"
@@ -8,19 +10,38 @@ fn data_drop_metering_should_work() {
       )
     )
     "#;
-  let wasm_bytes = wat::parse_str(wat_str).unwrap();
-  let mut parser = wasmarin::Parser::new();
-  let model = parser.parse_wasm_bytes(&wasm_bytes).unwrap();
-  let mut encoder = wasmarin::Encoder::new_with_metering();
-  let wasm_bytes = encoder.encode(model).unwrap();
-  println!("{}", wasmprinter::print_bytes(&wasm_bytes).unwrap());
+  // The operator itself and a single started unit of 18 bytes.
+  assert_eq!(2, consumed_points(wat_str, &[]));
+}
+
+#[test]
+fn data_drop_metering_should_depend_on_segment_length() {
+  let wat_str = format!(
+    r#"
+    (module
+      (data "A")
+      (data "{}")
+      (func (export "fun")
+        data.drop 1    ;; Drop the longer data segment.
+      )
+    )
+    "#,
+    "B".repeat(100)
+  );
+  // The operator itself and 4 started units of 100 bytes.
+  assert_eq!(5, consumed_points(&wat_str, &[]));
+}
 
-  /*
-  let engine = wasmtime::Engine::default();
-  let module = wasmtime::Module::from_binary(&engine, &wasm_bytes).unwrap();
-  let mut store = wasmtime::Store::new(&engine, ());
-  let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
-  let fun = instance.get_typed_func::<(), ()>(&mut store, "fun").unwrap();
-  fun.call(&mut store, ()).unwrap();
-  */
+#[test]
+fn data_drop_metering_of_empty_segment_should_work() {
+  let wat_str = r#"
+    (module
+      (data "")
+      (func (export "fun")
+        data.drop 0    ;; Drop the empty data.
+      )
+    )
+    "#;
+  // The operator itself only.
+  assert_eq!(1, consumed_points(wat_str, &[]));
 }
@@ -0,0 +1,91 @@
+use crate::helpers::{custom_section_names, with_custom_sections};
+use wasmarin::{DwarfSections, Encoder, OwnedModel, Parser, DWARF_SECTION_PREFIX};
+
+/// Returns a module with a single function and the given custom sections.
+fn module_with_custom_sections(names: &[&str]) -> Vec<u8> {
+  let wasm_bytes = wat::parse_str(r#"(module (func (export "run") (result i32) (i32.add (i32.const 1) (i32.const 2))))"#).unwrap();
+  with_custom_sections(wasm_bytes, &names.iter().map(|name| (*name, 3)).collect::<Vec<_>>())
+}
+
+#[test]
+fn dwarf_sections_should_be_dropped_with_warning_by_default() {
+  let wasm_bytes = module_with_custom_sections(&[".debug_info", "producers", ".debug_line"]);
+  let mut encoder = Encoder::new_with_metering();
+  let instrumented = encoder.encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  assert_eq!(vec!["producers", "wasmarin.metering"], custom_section_names(&instrumented));
+  assert_eq!(vec!["DWARF sections .debug_info, .debug_line dropped, the code section was changed"], encoder.warnings());
+  // Streamed, the DWARF sections are dropped the same way.
+  let mut encoder = Encoder::new_with_metering().with_dwarf_sections(DwarfSections::Drop);
+  assert_eq!(instrumented, encoder.encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap());
+  assert_eq!(1, encoder.warnings().len());
+}
+
+#[test]
+fn dwarf_sections_should_be_kept_with_warning_when_requested() {
+  let wasm_bytes = module_with_custom_sections(&[".debug_info", ".debug_line", "producers"]);
+  let mut encoder = Encoder::new_with_metering().with_dwarf_sections(DwarfSections::Keep);
+  let instrumented = encoder.encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  assert_eq!(vec![".debug_info", ".debug_line", "producers", "wasmarin.metering"], custom_section_names(&instrumented));
+  assert_eq!(
+    vec!["DWARF sections .debug_info, .debug_line kept with code offsets of the original function bodies"],
+    encoder.warnings()
+  );
+}
+
+#[test]
+fn dwarf_sections_should_be_dropped_when_function_is_removed() {
+  let wasm_bytes = wat::parse_str(
+    r#"(module
+      (func (export "first") (result i32) (i32.const 1))
+      (func $unused (result i32) (i32.const 2))
+      (func (export "last") (result i32) (i32.const 3)))"#,
+  )
+  .unwrap();
+  let wasm_bytes = with_custom_sections(wasm_bytes, &[(".debug_info", 3)]);
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  // Remaining function bodies are copied unchanged, but their offsets are shifted.
+  model.remove_function(1).unwrap();
+  let mut encoder = Encoder::new();
+  let encoded = encoder.encode(model).unwrap();
+  assert_eq!(vec!["name"], custom_section_names(&encoded));
+  assert_eq!(vec!["DWARF sections .debug_info dropped, the code section was changed"], encoder.warnings());
+}
+
+#[test]
+fn dwarf_sections_should_be_kept_when_function_bodies_are_unchanged() {
+  let wasm_bytes = module_with_custom_sections(&[".debug_info", ".debug_str"]);
+  for dwarf_sections in [DwarfSections::Keep, DwarfSections::Drop] {
+    let mut encoder = Encoder::new().with_dwarf_sections(dwarf_sections);
+    assert_eq!(wasm_bytes, encoder.encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap());
+    assert!(encoder.warnings().is_empty());
+    let owned_model = OwnedModel::try_from(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+    assert_eq!(wasm_bytes, encoder.encode_owned(&owned_model).unwrap());
+    assert!(encoder.warnings().is_empty());
+    assert_eq!(wasm_bytes, encoder.encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap());
+    assert!(encoder.warnings().is_empty());
+  }
+}
+
+#[test]
+fn dwarf_sections_should_be_dropped_when_function_bodies_are_reordered() {
+  let wasm_bytes = wat::parse_str(r#"(module (func (result i32) (i32.const 1)) (func (result i32) (i32.const 2)))"#).unwrap();
+  let wasm_bytes = with_custom_sections(wasm_bytes, &[(".debug_info", 3)]);
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  // Both function bodies are copied unchanged, but in the swapped order.
+  model.code_section_entries.swap(0, 1);
+  let mut encoder = Encoder::new();
+  encoder.encode(model).unwrap();
+  assert_eq!(vec!["DWARF sections .debug_info dropped, the code section was changed"], encoder.warnings());
+}
+
+#[test]
+fn warnings_should_be_cleared_on_next_encoding() {
+  let mut encoder = Encoder::new_with_metering();
+  encoder
+    .encode(Parser::new().parse_wasm_bytes(&module_with_custom_sections(&[".debug_info"])).unwrap())
+    .unwrap();
+  assert_eq!(1, encoder.warnings().len());
+  encoder.encode(Parser::new().parse_wasm_bytes(&module_with_custom_sections(&[])).unwrap()).unwrap();
+  assert!(encoder.warnings().is_empty());
+  assert!(".debug_abbrev".starts_with(DWARF_SECTION_PREFIX));
+}
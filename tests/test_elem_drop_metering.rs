@@ -0,0 +1,35 @@
+use crate::helpers::consumed_points;
+
+#[test]
+fn elem_drop_metering_should_work() {
+  let wat_str = r#"
+    (module
+      (func $f)
+      (elem func $f $f $f)
+      (func (export "fun")
+        elem.drop 0    ;; Drop the element segment.
+      )
+    )
+    "#;
+  // The operator itself and a single started unit of 3 elements.
+  assert_eq!(2, consumed_points(wat_str, &[]));
+}
+
+#[test]
+fn elem_drop_metering_should_depend_on_segment_length() {
+  let wat_str = format!(
+    r#"
+    (module
+      (func $f)
+      (elem func $f)
+      (elem func {})
+      (func (export "fun")
+        elem.drop 1    ;; Drop the longer element segment.
+      )
+    )
+    "#,
+    "$f ".repeat(65)
+  );
+  // The operator itself and 3 started units of 65 elements.
+  assert_eq!(4, consumed_points(&wat_str, &[]));
+}
@@ -0,0 +1,189 @@
+use crate::helpers::consumed_points;
+use std::collections::BTreeMap;
+use wasmarin::{export_gas_bounds, CostSchedule, GasBound, Parser, UnboundedReason};
+
+/// Returns gas bounds of all exported functions.
+fn bounds(wat_str: &str) -> BTreeMap<String, GasBound> {
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  export_gas_bounds(&model, &CostSchedule::default())
+    .unwrap()
+    .into_iter()
+    .map(|(name, bound)| (name.to_string(), bound))
+    .collect()
+}
+
+/// Returns the gas bound of the single exported function.
+fn bound(wat_str: &str) -> GasBound {
+  bounds(wat_str).into_values().next().unwrap()
+}
+
+const BRANCHES: &str = r#"
+  (module
+    (func $double (param i32) (result i32)
+      local.get 0
+      i32.const 2
+      i32.mul
+    )
+    (func (export "fun") (param i32) (result i32)
+      local.get 0
+      if (result i32)
+        i32.const 10
+      else
+        i32.const 20
+        call $double
+        i32.const 1
+        i32.add
+      end
+      i32.const 1
+      i32.add
+    )
+  )
+"#;
+
+const COUNTED_LOOP: &str = r#"
+  (module
+    (func (export "fun") (param i32) (result i32)
+      (local $i i32)
+      i32.const 0
+      local.set $i
+      loop
+        local.get $i
+        i32.const 1
+        i32.add
+        local.tee $i
+        i32.const 10
+        i32.lt_s
+        br_if 0
+      end
+      local.get $i
+    )
+  )
+"#;
+
+const UNCOUNTED_LOOP: &str = r#"
+  (module
+    (func $spin (param i32)
+      loop
+        local.get 0
+        i32.const 1
+        i32.sub
+        local.tee 0
+        br_if 0
+      end
+    )
+    (func (export "fun") (param i32) (result i32)
+      local.get 0
+      call $spin
+      i32.const 0
+    )
+  )
+"#;
+
+#[test]
+fn bound_should_cover_the_most_expensive_path() {
+  // The `else` arm calls another function and is more expensive.
+  assert_eq!(GasBound::Bounded(11), bound(BRANCHES));
+  assert_eq!(6, consumed_points(BRANCHES, &[wasmtime::Val::I32(1)]));
+  assert_eq!(11, consumed_points(BRANCHES, &[wasmtime::Val::I32(0)]));
+}
+
+#[test]
+fn counted_loop_should_be_bounded() {
+  assert_eq!(GasBound::Bounded(74), bound(COUNTED_LOOP));
+  assert_eq!(74, consumed_points(COUNTED_LOOP, &[wasmtime::Val::I32(0)]));
+}
+
+#[test]
+fn uncounted_loop_should_be_unbounded() {
+  let bounds = bounds(UNCOUNTED_LOOP);
+  // The reason points to the function containing the loop.
+  let reason = UnboundedReason::Loop { function_index: 0, position: 0 };
+  assert_eq!(GasBound::Unbounded(reason.clone()), bounds["fun"]);
+  assert_eq!("loop at operator 0 in function 0", reason.to_string());
+}
+
+#[test]
+fn recursion_should_be_unbounded() {
+  let bound = bound(
+    r#"
+    (module
+      (func $fun (export "fun") (param i32) (result i32)
+        local.get 0
+        if (result i32)
+          local.get 0
+          i32.const 1
+          i32.sub
+          call $fun
+        else
+          i32.const 0
+        end
+      )
+    )
+    "#,
+  );
+  assert_eq!(GasBound::Unbounded(UnboundedReason::Recursion { function_index: 0 }), bound);
+}
+
+#[test]
+fn data_dependent_bulk_operation_should_be_unbounded() {
+  let bound = bound(
+    r#"
+    (module
+      (memory 1)
+      (func (export "fun") (param i32) (result i32)
+        i32.const 0
+        i32.const 100
+        local.get 0
+        memory.fill
+        i32.const 0
+      )
+    )
+    "#,
+  );
+  assert_eq!(GasBound::Unbounded(UnboundedReason::BulkOperation { function_index: 0, position: 3 }), bound);
+}
+
+#[test]
+fn indirect_call_should_be_bounded_by_the_most_expensive_target() {
+  let wat_str = r#"
+    (module
+      (type $t (func (result i32)))
+      (table 2 funcref)
+      (elem (i32.const 0) $cheap $expensive)
+      (func $cheap (result i32)
+        i32.const 1
+      )
+      (func $expensive (result i32)
+        i32.const 1
+        i32.const 2
+        i32.add
+        i32.const 3
+        i32.add
+      )
+      (func (export "fun") (param i32) (result i32)
+        local.get 0
+        call_indirect (type $t)
+      )
+    )
+  "#;
+  assert_eq!(GasBound::Bounded(7), bound(wat_str));
+  assert_eq!(7, consumed_points(wat_str, &[wasmtime::Val::I32(1)]));
+}
+
+#[test]
+fn indirect_call_through_host_table_should_be_unbounded() {
+  let bound = bound(
+    r#"
+    (module
+      (type $t (func (result i32)))
+      (import "env" "table" (table 1 funcref))
+      (func (export "fun") (param i32) (result i32)
+        local.get 0
+        call_indirect (type $t)
+      )
+    )
+    "#,
+  );
+  assert_eq!(GasBound::Unbounded(UnboundedReason::HostTable { function_index: 0, position: 1 }), bound);
+}
@@ -0,0 +1,93 @@
+use wasmarin::{HostAbi, Parser, WasmarinResult};
+use wasmparser::{GlobalType, MemoryType, ValType};
+
+/// Returns the host ABI used in tests.
+fn host_abi() -> HostAbi {
+  HostAbi::new()
+    .with_function("env", "log", &[ValType::I32, ValType::I32], &[])
+    .with_function("env", "now", &[], &[ValType::I64])
+    .with_global(
+      "env",
+      "chain_id",
+      GlobalType {
+        content_type: ValType::I32,
+        mutable: false,
+        shared: false,
+      },
+    )
+    .with_memory(
+      "env",
+      "memory",
+      MemoryType {
+        memory64: false,
+        shared: false,
+        initial: 1,
+        maximum: Some(16),
+        page_size_log2: None,
+      },
+    )
+}
+
+/// Checks the module imports against the host ABI.
+fn check(wat_str: &str) -> WasmarinResult<()> {
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  host_abi().check(&model)
+}
+
+#[test]
+fn known_imports_should_be_accepted() {
+  let result = check(
+    r#"
+    (module
+      (import "env" "log" (func (param i32 i32)))
+      (import "env" "now" (func (result i64)))
+      (import "env" "chain_id" (global i32))
+      (import "env" "memory" (memory 1))
+    )
+    "#,
+  );
+  assert_eq!(Ok(()), result);
+}
+
+#[test]
+fn unknown_import_should_be_rejected() {
+  let result = check(r#"(module (import "env" "random" (func (result i64))))"#);
+  assert_eq!("unknown import env::random", result.unwrap_err().to_string());
+}
+
+#[test]
+fn mismatched_function_signature_should_be_rejected() {
+  let result = check(r#"(module (import "env" "now" (func (result i32))))"#);
+  assert_eq!(
+    "import env::now has type (func (result i32)), expected (func (result i64))",
+    result.unwrap_err().to_string()
+  );
+}
+
+#[test]
+fn mismatched_global_type_should_be_rejected() {
+  let result = check(r#"(module (import "env" "chain_id" (global (mut i32))))"#);
+  assert_eq!("import env::chain_id has type (mut i32), expected i32", result.unwrap_err().to_string());
+}
+
+#[test]
+fn memory_exceeding_provided_limits_should_be_rejected() {
+  let result = check(r#"(module (import "env" "memory" (memory 2)))"#);
+  assert!(result.unwrap_err().to_string().starts_with("import env::memory has type"));
+}
+
+#[test]
+fn table_not_provided_should_be_rejected() {
+  let result = check(r#"(module (import "env" "table" (table 1 funcref)))"#);
+  assert_eq!("unknown import env::table", result.unwrap_err().to_string());
+}
+
+#[test]
+fn wasi_imports_should_be_rejected() {
+  let result = check(r#"(module (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32))))"#);
+  assert_eq!(
+    "WASI imports are not supported, contract imports wasi_snapshot_preview1::fd_write",
+    result.unwrap_err().to_string()
+  );
+}
@@ -0,0 +1,120 @@
+use std::num::NonZeroU64;
+use wasmarin::{CostSchedule, Encoder, Parser, REMAINING_POINTS_EXPORT_NAME, START_FUNCTION_EXPORT_NAME};
+
+#[test]
+fn instantiation_cost_should_include_active_segments_only() {
+  let wat_str = format!(
+    r#"
+    (module
+      (memory 1)
+      (table 10 funcref)
+      (func $f)
+      (data (i32.const 0) "{}")     ;; Active data segment, 100 bytes.
+      (data "passive")                ;; Passive data segment, not copied during instantiation.
+      (elem (i32.const 0) func $f $f) ;; Active element segment, 2 elements.
+      (elem func $f)                  ;; Passive element segment, not initialized during instantiation.
+    )
+    "#,
+    "A".repeat(100)
+  );
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  // (1 + 4 units of 100 bytes) + (1 + 1 unit of 2 elements)
+  assert_eq!(7, CostSchedule::new().instantiation_cost(&model));
+  let cost_schedule = CostSchedule {
+    bulk_memory_operation_unit: NonZeroU64::new(10).unwrap(),
+    instantiation_base_cost: 100,
+    ..Default::default()
+  };
+  // 100 + (1 + 10 units of 100 bytes) + (1 + 1 unit of 2 elements)
+  assert_eq!(113, cost_schedule.instantiation_cost(&model));
+  let cost_schedule = CostSchedule {
+    element_operation_unit: NonZeroU64::MIN,
+    ..Default::default()
+  };
+  // (1 + 4 units of 100 bytes) + (1 + 2 units of 2 elements)
+  assert_eq!(8, cost_schedule.instantiation_cost(&model));
+}
+
+#[test]
+fn instantiation_cost_of_empty_module_should_be_base_cost() {
+  let wasm_bytes = wat::parse_str("(module)").unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert_eq!(0, CostSchedule::new().instantiation_cost(&model));
+}
+
+#[test]
+fn start_function_should_be_exported_when_metered() {
+  let wat_str = r#"
+    (module
+      (global $counter (export "counter") (mut i32) i32.const 0)
+      (func $start
+        global.get $counter
+        i32.const 1
+        i32.add
+        global.set $counter
+      )
+      (start $start)
+    )
+    "#;
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let wasm_bytes = Encoder::new_with_metering().encode(model).unwrap();
+
+  // Instantiation does not execute the start function, so there is no trap caused by missing points.
+  let engine = wasmtime::Engine::default();
+  let module = wasmtime::Module::from_binary(&engine, &wasm_bytes).unwrap();
+  let mut store = wasmtime::Store::new(&engine, ());
+  let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+  let counter = instance.get_global(&mut store, "counter").unwrap();
+  assert_eq!(0, counter.get(&mut store).i32().unwrap());
+
+  // The host sets the remaining points and then calls the original start function.
+  let remaining_points = instance.get_global(&mut store, REMAINING_POINTS_EXPORT_NAME).unwrap();
+  remaining_points.set(&mut store, wasmtime::Val::I64(10)).unwrap();
+  let start = instance.get_typed_func::<(), ()>(&mut store, START_FUNCTION_EXPORT_NAME).unwrap();
+  start.call(&mut store, ()).unwrap();
+  assert_eq!(1, counter.get(&mut store).i32().unwrap());
+  assert_eq!(6, remaining_points.get(&mut store).i64().unwrap());
+}
+
+#[test]
+fn start_function_should_be_metered() {
+  let wat_str = r#"
+    (module
+      (func $start
+        i32.const 1
+        drop
+      )
+      (start $start)
+    )
+    "#;
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let wasm_bytes = Encoder::new_with_metering().encode(model).unwrap();
+  let engine = wasmtime::Engine::default();
+  let module = wasmtime::Module::from_binary(&engine, &wasm_bytes).unwrap();
+  let mut store = wasmtime::Store::new(&engine, ());
+  let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+  let remaining_points = instance.get_global(&mut store, REMAINING_POINTS_EXPORT_NAME).unwrap();
+  remaining_points.set(&mut store, wasmtime::Val::I64(1)).unwrap();
+  let start = instance.get_typed_func::<(), ()>(&mut store, START_FUNCTION_EXPORT_NAME).unwrap();
+  // Not enough points to execute the start function.
+  start.call(&mut store, ()).unwrap_err();
+}
+
+#[test]
+fn start_function_should_be_preserved_when_not_metered() {
+  let wat_str = r#"
+    (module
+      (func $start)
+      (start $start)
+    )
+    "#;
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let wasm_bytes = Encoder::new().encode(model).unwrap();
+  let wat = wasmprinter::print_bytes(&wasm_bytes).unwrap();
+  assert!(wat.contains("(start $start)"));
+  assert!(!wat.contains(START_FUNCTION_EXPORT_NAME));
+}
@@ -0,0 +1,130 @@
+use wasmarin::{ContractInterface, Export, InterfaceViolation, Parser, REMAINING_POINTS_EXPORT_NAME};
+use wasmparser::{ExternalKind, ValType};
+
+/// Returns the contract interface used in tests.
+fn contract_interface() -> ContractInterface {
+  ContractInterface::new()
+    .with_function("execute", &[ValType::I32, ValType::I32], &[ValType::I32])
+    .with_function("allocate", &[ValType::I32], &[ValType::I32])
+    .with_optional_function("migrate", &[], &[])
+    .with_memory("memory")
+}
+
+/// Checks the module exports against the contract interface.
+fn check(wat_str: &str) -> Vec<InterfaceViolation> {
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  contract_interface().check(&model)
+}
+
+#[test]
+fn complete_interface_should_be_accepted() {
+  let violations = check(
+    r#"
+    (module
+      (import "env" "log" (func (param i32)))
+      (memory (export "memory") 1)
+      (func (export "execute") (param i32 i32) (result i32) local.get 0)
+      (func (export "allocate") (param i32) (result i32) local.get 0)
+      (func (export "migrate"))
+    )
+    "#,
+  );
+  assert_eq!(Vec::<InterfaceViolation>::new(), violations);
+}
+
+#[test]
+fn missing_exports_should_be_reported() {
+  let violations = check(r#"(module (func (export "execute") (param i32 i32) (result i32) local.get 0))"#);
+  let messages: Vec<String> = violations.iter().map(|violation| violation.to_string()).collect();
+  assert_eq!(vec!["missing func export 'allocate'", "missing memory export 'memory'"], messages);
+}
+
+#[test]
+fn wrong_signature_should_be_reported() {
+  let violations = check(
+    r#"
+    (module
+      (memory (export "memory") 1)
+      (func (export "execute") (param i32) (result i64) i64.const 0)
+      (func (export "allocate") (param i32) (result i32) local.get 0)
+      (func (export "migrate") (result i32) i32.const 0)
+    )
+    "#,
+  );
+  let messages: Vec<String> = violations.iter().map(|violation| violation.to_string()).collect();
+  assert_eq!(
+    vec![
+      "exported function 'execute' has type (func (param i32) (result i64)), expected (func (param i32 i32) (result i32))",
+      "exported function 'migrate' has type (func (result i32)), expected (func)",
+    ],
+    messages
+  );
+}
+
+#[test]
+fn wrong_kind_should_be_reported() {
+  let violations = check(
+    r#"
+    (module
+      (global (export "memory") i32 (i32.const 0))
+      (func (export "execute") (param i32 i32) (result i32) local.get 0)
+      (func (export "allocate") (param i32) (result i32) local.get 0)
+    )
+    "#,
+  );
+  assert_eq!(
+    vec![InterfaceViolation::WrongKind {
+      name: "memory".to_string(),
+      expected: ExternalKind::Memory,
+      actual: ExternalKind::Global,
+    }],
+    violations
+  );
+  assert_eq!("export 'memory' is a global, expected a memory", violations[0].to_string());
+}
+
+#[test]
+fn extra_exports_should_be_forbidden() {
+  let wat_str = r#"
+    (module
+      (memory (export "memory") 1)
+      (func (export "execute") (param i32 i32) (result i32) local.get 0)
+      (func (export "allocate") (param i32) (result i32) local.get 0)
+      (func (export "debug"))
+    )
+    "#;
+  let violations = check(wat_str);
+  assert_eq!("forbidden func export 'debug'", violations[0].to_string());
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert!(contract_interface().with_extra_exports(true).check(&model).is_empty());
+}
+
+#[test]
+fn reserved_exports_should_be_rejected() {
+  let wat_str = r#"
+    (module
+      (memory (export "memory") 1)
+      (global (mut i64) (i64.const 0))
+      (func (export "execute") (param i32 i32) (result i32) local.get 0)
+      (func (export "allocate") (param i32) (result i32) local.get 0)
+    )
+    "#;
+  let wasm_bytes = wat::parse_str(wat_str).unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  // The parser itself rejects reserved exports, so the export is added to the model directly.
+  model.exports.push(Export {
+    name: REMAINING_POINTS_EXPORT_NAME.to_string(),
+    kind: ExternalKind::Global,
+    index: 0,
+  });
+  let violations = contract_interface().with_extra_exports(true).check(&model);
+  assert_eq!(
+    vec![InterfaceViolation::Reserved {
+      name: REMAINING_POINTS_EXPORT_NAME.to_string(),
+      kind: ExternalKind::Global,
+    }],
+    violations
+  );
+}
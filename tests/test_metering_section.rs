@@ -0,0 +1,141 @@
+use std::num::NonZeroU64;
+use wasmarin::{uninstrument, CostSchedule, Encoder, InstrumentedInput, Metering, MeteringMetadata, Parser, METERING_SECTION_NAME};
+
+const CONTRACT: &str = r#"
+  (module
+    (import "env" "chain_id" (global i32))
+    (global (mut i32) (i32.const 0))
+    (func (export "run") (result i32)
+      global.get 0
+    )
+  )
+"#;
+
+/// Returns the contract instrumented with metering using the given encoder.
+fn instrument(mut encoder: Encoder) -> Vec<u8> {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  encoder.encode(model).unwrap()
+}
+
+/// Returns the metadata stored in the metering section of the module.
+fn metadata(wasm_bytes: &[u8]) -> MeteringMetadata {
+  let model = Parser::new().with_instrumented_input(InstrumentedInput::Skip).parse_wasm_bytes(wasm_bytes).unwrap();
+  model.metering.unwrap()
+}
+
+/// Restores the original module and instruments it again.
+fn reinstrument(wasm_bytes: &[u8]) -> Vec<u8> {
+  let model = Parser::new().with_instrumented_input(InstrumentedInput::Skip).parse_wasm_bytes(wasm_bytes).unwrap();
+  Encoder::new_with_metering().encode(uninstrument(model).unwrap()).unwrap()
+}
+
+/// Appends a custom section to the WASM binary.
+fn with_custom_section(mut wasm_bytes: Vec<u8>, name: &str, data: &[u8]) -> Vec<u8> {
+  let mut content = vec![name.len() as u8];
+  content.extend_from_slice(name.as_bytes());
+  content.extend_from_slice(data);
+  wasm_bytes.push(0);
+  wasm_bytes.push(content.len() as u8);
+  wasm_bytes.extend(content);
+  wasm_bytes
+}
+
+#[test]
+fn metering_section_should_be_embedded() {
+  let wasm_bytes = instrument(Encoder::new_with_metering());
+  wasmparser::validate(&wasm_bytes).unwrap();
+  let metadata = metadata(&wasm_bytes);
+  assert_eq!("default", metadata.schedule_id);
+  // The counter follows the imported and the defined global.
+  assert_eq!(2, metadata.counter_global_index);
+  assert_eq!(metadata, MeteringMetadata::decode(&metadata.encode()).unwrap());
+}
+
+#[test]
+fn unmetered_module_should_have_no_metering_section() {
+  let wasm_bytes = instrument(Encoder::new());
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert!(model.metering.is_none());
+}
+
+#[test]
+fn config_hash_should_depend_on_configuration() {
+  let hash = metadata(&instrument(Encoder::new_with_metering())).config_hash;
+  assert_eq!(hash, metadata(&instrument(Encoder::new_with_metering())).config_hash);
+  assert_ne!(
+    hash,
+    metadata(&instrument(Encoder::new().with_pass(Metering::new().with_counted_loop_hoisting(true)))).config_hash
+  );
+  let cost_schedule = CostSchedule {
+    bulk_memory_operation_unit: NonZeroU64::new(64).unwrap(),
+    ..Default::default()
+  };
+  assert_ne!(hash, metadata(&instrument(Encoder::new_with_cost_schedule(cost_schedule))).config_hash);
+  let cost_schedule = CostSchedule {
+    element_operation_unit: NonZeroU64::new(8).unwrap(),
+    ..Default::default()
+  };
+  assert_ne!(hash, metadata(&instrument(Encoder::new_with_cost_schedule(cost_schedule))).config_hash);
+}
+
+#[test]
+fn instrumented_module_should_be_rejected_by_default() {
+  let wasm_bytes = instrument(Encoder::new_with_metering());
+  let result = Parser::new().parse_wasm_bytes(&wasm_bytes);
+  assert_eq!(
+    "module is already instrumented, found custom section 'wasmarin.metering'",
+    result.err().unwrap().to_string()
+  );
+}
+
+#[test]
+fn instrumented_module_should_not_be_instrumented_twice() {
+  let wasm_bytes = instrument(Encoder::new_with_metering());
+  let model = Parser::new().with_instrumented_input(InstrumentedInput::Skip).parse_wasm_bytes(&wasm_bytes).unwrap();
+  let reencoded = Encoder::new_with_metering().encode(model).unwrap();
+  assert_eq!(wasm_bytes, reencoded);
+}
+
+#[test]
+fn reserved_export_should_be_rejected() {
+  let wasm_bytes = wat::parse_str(r#"(module (global (export "wasmarin_metering_remaining_points") (mut i64) (i64.const 0)))"#).unwrap();
+  for instrumented_input in [InstrumentedInput::Reject, InstrumentedInput::Skip] {
+    let result = Parser::new().with_instrumented_input(instrumented_input).parse_wasm_bytes(&wasm_bytes);
+    assert_eq!("module defines reserved export 'wasmarin_metering_remaining_points'", result.err().unwrap().to_string());
+  }
+}
+
+#[test]
+fn forged_metering_section_should_be_rejected() {
+  let forged = MeteringMetadata {
+    schedule_id: "default".to_string(),
+    config_hash: 0,
+    counter_global_index: 1,
+  };
+  let wasm_bytes = with_custom_section(wat::parse_str(CONTRACT).unwrap(), METERING_SECTION_NAME, &forged.encode());
+  let result = Parser::new().with_instrumented_input(InstrumentedInput::Skip).parse_wasm_bytes(&wasm_bytes);
+  assert_eq!("custom section 'wasmarin.metering' does not match the module", result.err().unwrap().to_string());
+  let wasm_bytes = with_custom_section(wat::parse_str(CONTRACT).unwrap(), METERING_SECTION_NAME, &[1, 2, 3]);
+  let result = Parser::new().with_instrumented_input(InstrumentedInput::Skip).parse_wasm_bytes(&wasm_bytes);
+  assert!(result.err().unwrap().to_string().starts_with("malformed metering section"));
+}
+
+#[test]
+fn forged_metering_section_should_be_detected_by_instrumenting_again() {
+  let wasm_bytes = instrument(Encoder::new_with_metering());
+  assert_eq!(wasm_bytes, reinstrument(&wasm_bytes));
+  // Metering section matching the module, but no charges injected.
+  let forged = MeteringMetadata {
+    schedule_id: "default".to_string(),
+    config_hash: metadata(&wasm_bytes).config_hash,
+    counter_global_index: 2,
+  };
+  let wat_str = CONTRACT.replace(
+    "(global (mut i32) (i32.const 0))",
+    r#"(global (mut i32) (i32.const 0)) (global (export "wasmarin_metering_remaining_points") (mut i64) (i64.const 0))"#,
+  );
+  let wasm_bytes = with_custom_section(wat::parse_str(wat_str).unwrap(), METERING_SECTION_NAME, &forged.encode());
+  assert!(Parser::new().with_instrumented_input(InstrumentedInput::Skip).parse_wasm_bytes(&wasm_bytes).is_ok());
+  assert_ne!(wasm_bytes, reinstrument(&wasm_bytes));
+}
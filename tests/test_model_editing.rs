@@ -0,0 +1,336 @@
+use wasmarin::{ConstExpr, DataKind, Encoder, Model, Parser};
+use wasmparser::{BlockType, ExternalKind, FuncType, GlobalType, Operator, PackedIndex, RefType, TypeRef, ValType};
+
+const CONTRACT: &str = r#"
+  (module
+    (type (func))
+    (type (func (result i32)))
+    (import "env" "log" (func (type 0)))
+    (import "env" "base" (global $a i32))
+    (global $b (mut i32) (i32.const 2))
+    (global $c i32 (global.get $a))
+    (table 2 funcref)
+    (memory 1)
+    (elem (i32.const 0) func $one $two)
+    (data (i32.const 0) "ab")
+    (data (i32.const 2) "cd")
+    (func $unused (type 0)
+      nop
+    )
+    (func $one (type 1)
+      global.get $b
+      global.get $c
+      i32.add
+      global.set $b
+      i32.const 1
+    )
+    (func $two (type 1)
+      call $one
+      ref.func $one
+      drop
+    )
+    (func $init (type 0)
+      call 0
+      i32.const 0
+      i32.const 0
+      i32.const 2
+      memory.init 1
+    )
+    (export "one" (func $one))
+    (export "two" (func $two))
+    (start $init)
+  )
+"#;
+
+/// Encodes the model and returns its text representation, checking the module is valid.
+fn print(model: Model) -> String {
+  let wasm_bytes = Encoder::new().encode(model).unwrap();
+  wasmparser::validate(&wasm_bytes).unwrap();
+  wasmprinter::print_bytes(&wasm_bytes).unwrap()
+}
+
+/// Returns the text representation of the expected module, without the `name` section dropped by editing.
+fn expected(wat: &str) -> String {
+  let wasm_bytes = wat::parse_str(wat).unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  model.custom_sections.clear();
+  print(model)
+}
+
+#[test]
+fn removing_function_should_renumber_references() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  model.remove_function(1).unwrap();
+  assert_eq!(
+    expected(
+      r#"
+      (module
+        (type (func))
+        (type (func (result i32)))
+        (import "env" "log" (func (type 0)))
+        (import "env" "base" (global $a i32))
+        (global $b (mut i32) (i32.const 2))
+        (global $c i32 (global.get $a))
+        (table 2 funcref)
+        (memory 1)
+        (elem (i32.const 0) func $one $two)
+        (data (i32.const 0) "ab")
+        (data (i32.const 2) "cd")
+        (func $one (type 1)
+          global.get $b
+          global.get $c
+          i32.add
+          global.set $b
+          i32.const 1
+        )
+        (func $two (type 1)
+          call $one
+          ref.func $one
+          drop
+        )
+        (func $init (type 0)
+          call 0
+          i32.const 0
+          i32.const 0
+          i32.const 2
+          memory.init 1
+        )
+        (export "one" (func $one))
+        (export "two" (func $two))
+        (start $init)
+      )
+    "#
+    ),
+    print(model)
+  );
+}
+
+#[test]
+fn removing_referenced_items_should_fail_and_leave_model_unchanged() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert_eq!("function 2 is removed, but it is still referenced", model.remove_function(2).unwrap_err().to_string());
+  assert_eq!("global 1 is removed, but it is still referenced", model.remove_global(1).unwrap_err().to_string());
+  assert_eq!("data segment 1 is removed, but it is still referenced", model.remove_data(1).unwrap_err().to_string());
+  assert_eq!("type 1 is removed, but it is still referenced", model.remove_type(1).unwrap_err().to_string());
+  assert_eq!(
+    "function 0 is removed, but it is still referenced",
+    model.remove_import("env", "log").unwrap_err().to_string()
+  );
+  assert_eq!("function 0 is imported, remove the import instead", model.remove_function(0).unwrap_err().to_string());
+  assert_eq!("unknown function 5", model.remove_function(5).unwrap_err().to_string());
+  assert_eq!(wasmprinter::print_bytes(wat::parse_str(CONTRACT).unwrap()).unwrap(), print(model));
+}
+
+#[test]
+fn removing_items_referenced_only_by_themselves_should_succeed() {
+  let wasm_bytes = wat::parse_str(
+    r#"(module
+      (global $g (mut i32) (i32.const 0))
+      (func $recursive (result i32) (global.set $g (i32.const 1)) (call $recursive))
+      (func (export "run") (result i32) (i32.const 1)))"#,
+  )
+  .unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert_eq!("global 0 is removed, but it is still referenced", model.remove_global(0).unwrap_err().to_string());
+  model.remove_function(0).unwrap();
+  model.remove_global(0).unwrap();
+  assert_eq!(expected(r#"(module (func (export "run") (result i32) (i32.const 1)))"#), print(model));
+}
+
+#[test]
+fn adding_import_should_renumber_defined_items() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let type_index = model.add_type(FuncType::new([ValType::I64], []));
+  assert_eq!(2, type_index);
+  assert_eq!(1, model.add_import("env", "charge", TypeRef::Func(type_index)).unwrap());
+  let global_type = GlobalType {
+    content_type: ValType::I32,
+    mutable: false,
+    shared: false,
+  };
+  assert_eq!(1, model.add_import("env", "limit", TypeRef::Global(global_type)).unwrap());
+  assert_eq!(
+    expected(
+      r#"
+      (module
+        (type (func))
+        (type (func (result i32)))
+        (type (func (param i64)))
+        (import "env" "log" (func (type 0)))
+        (import "env" "base" (global $a i32))
+        (import "env" "charge" (func (type 2)))
+        (import "env" "limit" (global i32))
+        (global $b (mut i32) (i32.const 2))
+        (global $c i32 (global.get $a))
+        (table 2 funcref)
+        (memory 1)
+        (elem (i32.const 0) func $one $two)
+        (data (i32.const 0) "ab")
+        (data (i32.const 2) "cd")
+        (func $unused (type 0)
+          nop
+        )
+        (func $one (type 1)
+          global.get $b
+          global.get $c
+          i32.add
+          global.set $b
+          i32.const 1
+        )
+        (func $two (type 1)
+          call $one
+          ref.func $one
+          drop
+        )
+        (func $init (type 0)
+          call 0
+          i32.const 0
+          i32.const 0
+          i32.const 2
+          memory.init 1
+        )
+        (export "one" (func $one))
+        (export "two" (func $two))
+        (start $init)
+      )
+    "#
+    ),
+    print(model)
+  );
+}
+
+#[test]
+fn removing_global_and_data_should_renumber_references() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let global_type = GlobalType {
+    content_type: ValType::I32,
+    mutable: false,
+    shared: false,
+  };
+  assert_eq!(3, model.add_global(global_type, ConstExpr::i32_const(7)));
+  model.remove_function(1).unwrap();
+  model.remove_global(3).unwrap();
+  model.remove_data(0).unwrap();
+  assert_eq!(1, model.add_data(DataKind::Passive, b"ef".to_vec()));
+  model.remove_export("two").unwrap();
+  model.add_export("unused", ExternalKind::Global, 1).unwrap();
+  assert_eq!("duplicate export 'one'", model.add_export("one", ExternalKind::Func, 1).unwrap_err().to_string());
+  assert_eq!("unknown export 'two'", model.remove_export("two").unwrap_err().to_string());
+  assert_eq!(
+    expected(
+      r#"
+      (module
+        (type (func))
+        (type (func (result i32)))
+        (import "env" "log" (func (type 0)))
+        (import "env" "base" (global $a i32))
+        (global $b (mut i32) (i32.const 2))
+        (global $c i32 (global.get $a))
+        (table 2 funcref)
+        (memory 1)
+        (elem (i32.const 0) func $one $two)
+        (data (i32.const 2) "cd")
+        (data "ef")
+        (func $one (type 1)
+          global.get $b
+          global.get $c
+          i32.add
+          global.set $b
+          i32.const 1
+        )
+        (func $two (type 1)
+          call $one
+          ref.func $one
+          drop
+        )
+        (func $init (type 0)
+          call 0
+          i32.const 0
+          i32.const 0
+          i32.const 2
+          memory.init 0
+        )
+        (export "one" (func $one))
+        (export "unused" (global $b))
+        (start $init)
+      )
+    "#
+    ),
+    print(model)
+  );
+}
+
+#[test]
+fn model_should_be_built_from_scratch() {
+  let mut model = Model::new();
+  let type_index = model.add_type(FuncType::new([], [ValType::I32]));
+  let global_type = GlobalType {
+    content_type: ValType::I32,
+    mutable: false,
+    shared: false,
+  };
+  let global_index = model.add_global(global_type, ConstExpr::i32_const(42));
+  let function_index = model.add_function(type_index, vec![], vec![Operator::GlobalGet { global_index }, Operator::End]).unwrap();
+  model.add_export("answer", ExternalKind::Func, function_index).unwrap();
+  assert_eq!(
+    expected(
+      r#"
+      (module
+        (global i32 (i32.const 42))
+        (func (export "answer") (result i32)
+          global.get 0
+        )
+      )
+    "#
+    ),
+    print(model)
+  );
+}
+
+#[test]
+fn adding_function_with_malformed_body_should_fail() {
+  let mut model = Model::new();
+  let type_index = model.add_type(FuncType::new([], []));
+  let block = Operator::Block { blockty: BlockType::Empty };
+  for (operators, message) in [
+    (vec![Operator::End, Operator::End], "operator at position 1 follows the end of the function body"),
+    (vec![block.clone()], "function body is not terminated with the `end` operator"),
+    (
+      vec![block, Operator::Else, Operator::End, Operator::End],
+      "`else` at position 1 does not match the enclosing control structure",
+    ),
+    (
+      vec![Operator::CatchAll, Operator::End],
+      "`catch_all` at position 0 does not match the enclosing control structure",
+    ),
+    (vec![Operator::Br { relative_depth: 1 }, Operator::End], "unknown label 1 at position 0"),
+  ] {
+    assert_eq!(message, model.add_function(type_index, vec![], operators).unwrap_err().to_string());
+  }
+  assert_eq!("unknown function type 1", model.add_function(1, vec![], vec![Operator::End]).unwrap_err().to_string());
+  assert_eq!(0, model.function_count());
+  // The model without malformed functions can be instrumented.
+  model.add_function(type_index, vec![], vec![Operator::End]).unwrap();
+  wasmparser::validate(&Encoder::new_with_metering().encode(model).unwrap()).unwrap();
+}
+
+#[test]
+fn removing_type_referenced_by_value_type_should_fail() {
+  let mut model = Model::new();
+  model.add_type(FuncType::new([], []));
+  let type_index = model.add_type(FuncType::new([], [ValType::I32]));
+  // The first type is not used, but the local references the second type, which would have to be renumbered.
+  let ref_type = RefType::concrete(true, PackedIndex::from_module_index(type_index).unwrap());
+  model
+    .add_function(type_index, vec![(1, ValType::Ref(ref_type))], vec![Operator::I32Const { value: 0 }, Operator::End])
+    .unwrap();
+  assert_eq!(
+    "type 0 can not be removed, types are referenced by value types",
+    model.remove_type(0).unwrap_err().to_string()
+  );
+  assert_eq!(2, model.type_count());
+}
@@ -0,0 +1,103 @@
+use wasmarin::{uninstrument, Encoder, InstrumentedInput, Parser, NAME_SECTION_NAME, REMAINING_POINTS_GLOBAL_NAME};
+
+const CONTRACT: &str = r#"
+  (module $contract
+    (import "env" "log" (func $log (param i32)))
+    (global $limit i32 (i32.const 10))
+    (func $run (export "run") (param $n i32) (result i32) (local $acc i32)
+      (block $outer
+        (loop $inner
+          (local.set $acc (i32.add (local.get $acc) (i32.const 1)))
+          (br_if $outer (i32.ge_u (local.get $acc) (global.get $limit)))
+          (br $inner)))
+      (local.get $acc))
+    (func $helper (call $log (i32.const 1)))
+  )
+"#;
+
+#[test]
+fn names_should_be_parsed() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let names = model.names.as_ref().unwrap();
+  assert_eq!(Some("contract"), names.module.as_deref());
+  assert_eq!(
+    vec![(0, "log"), (1, "run"), (2, "helper")],
+    names.functions.iter().map(|(index, name)| (*index, name.as_str())).collect::<Vec<_>>()
+  );
+  assert_eq!(Some("limit"), names.globals.get(&0).map(String::as_str));
+  let run = &model.code_section_entries[0];
+  assert_eq!(Some("n"), run.local_names.get(&0).map(String::as_str));
+  assert_eq!(Some("acc"), run.local_names.get(&1).map(String::as_str));
+  assert_eq!(Some("outer"), run.label_names.get(&0).map(String::as_str));
+  assert_eq!(Some("inner"), run.label_names.get(&1).map(String::as_str));
+  assert!(model.custom_sections.iter().all(|custom_section| custom_section.name != NAME_SECTION_NAME));
+}
+
+#[test]
+fn names_should_round_trip() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  assert_eq!(wasm_bytes, Encoder::new().encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap());
+}
+
+#[test]
+fn metering_should_name_remaining_points_and_keep_labels() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let instrumented = Encoder::new_with_metering().encode(model).unwrap();
+  let wat = wasmprinter::print_bytes(&instrumented).unwrap();
+  assert!(wat.contains(&format!("(global ${REMAINING_POINTS_GLOBAL_NAME} (;1;) (mut i64)")), "{wat}");
+  assert!(wat.contains("block $outer"), "{wat}");
+  assert!(wat.contains("loop $inner"), "{wat}");
+  assert!(wat.contains("br_if $outer"), "{wat}");
+  assert!(wat.contains("br $inner"), "{wat}");
+  // Instrumented in parallel and streamed, names are the same.
+  let parallel = Encoder::new_with_metering()
+    .with_workers(2)
+    .encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap())
+    .unwrap();
+  assert_eq!(instrumented, parallel);
+  let streamed = Encoder::new_with_metering().encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap();
+  assert_eq!(instrumented, streamed);
+}
+
+#[test]
+fn uninstrument_should_restore_names() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let instrumented = Encoder::new_with_metering().encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  let model = Parser::new().with_instrumented_input(InstrumentedInput::Skip).parse_wasm_bytes(&instrumented).unwrap();
+  let model = uninstrument(model).unwrap();
+  assert_eq!(None, model.names.as_ref().unwrap().globals.get(&1));
+  assert_eq!(Some("inner"), model.code_section_entries[0].label_names.get(&1).map(String::as_str));
+  assert_eq!(wasm_bytes, Encoder::new().encode(model).unwrap());
+}
+
+#[test]
+fn names_should_be_renumbered_on_editing() {
+  let wasm_bytes = wat::parse_str(CONTRACT.replace("(call $log (i32.const 1))", "")).unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  model.remove_import("env", "log").unwrap();
+  let names = model.names.as_ref().unwrap();
+  assert_eq!(
+    vec![(0, "run"), (1, "helper")],
+    names.functions.iter().map(|(index, name)| (*index, name.as_str())).collect::<Vec<_>>()
+  );
+  assert_eq!(Some("acc"), model.code_section_entries[0].local_names.get(&1).map(String::as_str));
+  let wat = wasmprinter::print_bytes(Encoder::new().encode(model).unwrap()).unwrap();
+  assert!(wat.contains("(func $run (;0;)"), "{wat}");
+  assert!(wat.contains("(func $helper (;1;)"), "{wat}");
+}
+
+#[test]
+fn malformed_name_section_should_be_kept() {
+  let mut module = wasm_encoder::Module::new();
+  module.section(&wasm_encoder::CustomSection {
+    name: NAME_SECTION_NAME.into(),
+    data: vec![1, 200].into(),
+  });
+  let wasm_bytes = module.finish();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert!(model.names.is_none());
+  assert_eq!(vec![1, 200], model.custom_sections[0].data);
+  assert_eq!(wasm_bytes, Encoder::new().encode(model).unwrap());
+}
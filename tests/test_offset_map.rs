@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use wasmarin::{Encoder, OffsetMap, Parser};
+
+const CONTRACT: &str = r#"
+  (module
+    (memory 1)
+    (func $sum (export "sum") (param i32) (result i32) (local i32)
+      (block
+        (loop
+          (br_if 1 (i32.eqz (local.get 0)))
+          (local.set 1 (i32.add (local.get 1) (i32.load (local.get 0))))
+          (local.set 0 (i32.sub (local.get 0) (i32.const 4)))
+          (br 0)))
+      (local.get 1))
+    (func (export "noop"))
+  )
+"#;
+
+/// Returns operators of all function bodies keyed by their offsets in the module.
+fn operators(wasm_bytes: &[u8]) -> BTreeMap<usize, String> {
+  let mut operators = BTreeMap::new();
+  for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+    if let wasmparser::Payload::CodeSectionEntry(body) = payload.unwrap() {
+      let mut operators_reader = body.get_operators_reader().unwrap();
+      while !operators_reader.eof() {
+        let offset = operators_reader.original_position();
+        operators.insert(offset, format!("{:?}", operators_reader.read().unwrap()));
+      }
+    }
+  }
+  operators
+}
+
+/// Returns the offset map of the metered contract.
+fn metered_offset_map(wasm_bytes: &[u8]) -> (Vec<u8>, OffsetMap) {
+  let model = Parser::new().with_original_offsets(true).parse_wasm_bytes(wasm_bytes).unwrap();
+  let mut encoder = Encoder::new_with_metering().with_offset_map(true);
+  let instrumented = encoder.encode(model).unwrap();
+  (instrumented, encoder.offset_map().unwrap().clone())
+}
+
+#[test]
+fn offsets_should_map_to_original_operators() {
+  for wasm_bytes in [wat::parse_str(CONTRACT).unwrap(), std::fs::read("tests/contracts/burner.wasm").unwrap()] {
+    let (instrumented, offset_map) = metered_offset_map(&wasm_bytes);
+    let original_operators = operators(&wasm_bytes);
+    let instrumented_operators = operators(&instrumented);
+    assert_eq!(
+      instrumented_operators.keys().copied().collect::<Vec<_>>(),
+      offset_map.offsets().map(|(offset, _)| offset).collect::<Vec<_>>()
+    );
+    // Charges are inserted before the operator they map to, so the last operator mapped to each original operator is the same operator.
+    let mut last = BTreeMap::new();
+    for (offset, original_offset) in offset_map.offsets() {
+      assert_eq!(Some(original_offset), offset_map.original_offset(offset));
+      last.insert(original_offset, instrumented_operators[&offset].clone());
+    }
+    assert_eq!(original_operators, last);
+  }
+}
+
+#[test]
+fn charges_should_map_to_following_operator() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let (instrumented, offset_map) = metered_offset_map(&wasm_bytes);
+  let instrumented_operators = operators(&instrumented);
+  let original_operators = operators(&wasm_bytes);
+  let (first_offset, first_original_offset) = offset_map.offsets().next().unwrap();
+  assert_eq!("GlobalGet { global_index: 0 }", instrumented_operators[&first_offset]);
+  assert_eq!(original_operators.keys().next(), Some(&first_original_offset));
+  // Offsets inside the encoding of an operator map to the operator, offsets outside of function bodies are not mapped.
+  assert_eq!(Some(first_original_offset), offset_map.original_offset(first_offset + 1));
+  assert_eq!(None, offset_map.original_offset(0));
+  assert_eq!(None, offset_map.original_offset(instrumented.len()));
+}
+
+#[test]
+fn offset_map_should_round_trip() {
+  let (_, offset_map) = metered_offset_map(&std::fs::read("tests/contracts/burner.wasm").unwrap());
+  assert!(!offset_map.is_empty());
+  assert_eq!(offset_map, OffsetMap::decode(&offset_map.encode()).unwrap());
+  assert_eq!(OffsetMap::default(), OffsetMap::decode(&OffsetMap::default().encode()).unwrap());
+  assert_eq!("unsupported offset map version 7", OffsetMap::decode(&[7]).unwrap_err().to_string());
+  let mut trailing = offset_map.encode();
+  trailing.push(0);
+  assert_eq!("malformed offset map: unexpected trailing bytes", OffsetMap::decode(&trailing).unwrap_err().to_string());
+}
+
+#[test]
+fn parallel_and_streaming_encoding_should_build_same_offset_map() {
+  let wasm_bytes = std::fs::read("tests/contracts/burner.wasm").unwrap();
+  let (instrumented, offset_map) = metered_offset_map(&wasm_bytes);
+  let model = Parser::new().with_original_offsets(true).parse_wasm_bytes(&wasm_bytes).unwrap();
+  let mut parallel = Encoder::new_with_metering().with_offset_map(true).with_workers(4);
+  assert_eq!(instrumented, parallel.encode(model).unwrap());
+  assert_eq!(Some(&offset_map), parallel.offset_map());
+  let mut streaming = Encoder::new_with_metering().with_offset_map(true);
+  assert_eq!(instrumented, streaming.encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap());
+  assert_eq!(Some(&offset_map), streaming.offset_map());
+}
+
+#[test]
+fn unchanged_functions_should_map_to_themselves() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let model = Parser::new().with_original_offsets(true).parse_wasm_bytes(&wasm_bytes).unwrap();
+  let mut encoder = Encoder::new().with_offset_map(true);
+  assert_eq!(wasm_bytes, encoder.encode(model).unwrap());
+  let offset_map = encoder.offset_map().unwrap();
+  assert!(offset_map.offsets().all(|(offset, original_offset)| offset == original_offset));
+  assert_eq!(operators(&wasm_bytes).len(), offset_map.offsets().count());
+}
+
+#[test]
+fn offset_map_should_be_empty_without_original_offsets() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let mut encoder = Encoder::new_with_metering().with_offset_map(true);
+  encoder.encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  assert!(encoder.offset_map().unwrap().is_empty());
+  let mut encoder = Encoder::new_with_metering();
+  encoder.encode(Parser::new().with_original_offsets(true).parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  assert!(encoder.offset_map().is_none());
+}
@@ -0,0 +1,75 @@
+use wasmarin::{Encoder, Metering, Parser, Pass};
+use wasmparser::{Operator, ValType};
+
+const CONTRACT: &str = r#"
+  (module
+    (import "env" "memory" (memory 1))
+    (memory i64 1)
+    (table 1 funcref)
+    (table i64 1 funcref)
+    (func (export "pick") (param i32 i64) (result i64)
+      local.get 1
+      i64.const 7
+      local.get 0
+      select
+      i64.const 1
+      i64.add
+    )
+  )
+"#;
+
+#[test]
+fn operand_types_should_be_recorded_before_each_operator() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let model = Parser::new().with_operand_types(true).parse_wasm_bytes(&wasm_bytes).unwrap();
+  let code_section_entry = &model.code_section_entries[0];
+  let operand_types = code_section_entry.operand_types.as_ref().unwrap();
+  assert_eq!(code_section_entry.operators.len(), operand_types.len());
+  assert_eq!(Some(Operator::Select), code_section_entry.operators.get(3).cloned());
+  assert_eq!(Some(&[][..]), operand_types.at(0));
+  assert_eq!(Some(&[Some(ValType::I64), Some(ValType::I64), Some(ValType::I32)][..]), operand_types.at(3));
+  assert_eq!(Some(&[Some(ValType::I64), Some(ValType::I64)][..]), operand_types.at(5));
+  assert_eq!(Some(&[Some(ValType::I64)][..]), operand_types.at(6));
+  assert_eq!(None, operand_types.at(7));
+}
+
+#[test]
+fn operand_types_of_unreachable_code_should_be_unknown() {
+  let wasm_bytes = wat::parse_str(r#"(module (func (result i32) unreachable i32.eqz))"#).unwrap();
+  let model = Parser::new().with_operand_types(true).parse_wasm_bytes(&wasm_bytes).unwrap();
+  let operand_types = model.code_section_entries[0].operand_types.as_ref().unwrap();
+  assert_eq!(Some(&[][..]), operand_types.at(1));
+  assert_eq!(Some(&[Some(ValType::I32)][..]), operand_types.at(2));
+}
+
+#[test]
+fn operand_types_should_not_be_recorded_by_default() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert_eq!(None, model.code_section_entries[0].operand_types);
+}
+
+#[test]
+fn operand_types_should_be_cleared_by_metering() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let mut model = Parser::new().with_operand_types(true).parse_wasm_bytes(&wasm_bytes).unwrap();
+  let mut metering = Metering::new();
+  metering.update_model(&mut model).unwrap();
+  metering.update_function(0, &mut model.code_section_entries[0]).unwrap();
+  assert_eq!(None, model.code_section_entries[0].operand_types);
+  Encoder::new().encode(model).unwrap();
+}
+
+#[test]
+fn index_types_should_include_imports() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert_eq!(Some(ValType::I32), model.memory_index_type(0));
+  assert_eq!(Some(ValType::I64), model.memory_index_type(1));
+  assert_eq!(None, model.memory_index_type(2));
+  assert_eq!(Some(ValType::I32), model.table_index_type(0));
+  assert_eq!(Some(ValType::I64), model.table_index_type(1));
+  assert_eq!(None, model.table_index_type(2));
+  assert_eq!(1, model.memory_type(0).unwrap().initial);
+  assert_eq!("(func (param i32 i64) (result i64))", model.function_type(0).unwrap().to_string());
+}
@@ -0,0 +1,68 @@
+use wasmarin::{Encoder, OwnedBrTable, OwnedModel, OwnedOperator, Parser};
+
+const CONTRACT: &str = r#"
+  (module
+    (memory 1)
+    (data (i32.const 0) "abc")
+    (func (export "select") (param i32) (result i32)
+      block
+        block
+          local.get 0
+          br_table 0 1
+        end
+        i32.const 1
+        return
+      end
+      i32.const 2
+    )
+  )
+"#;
+
+/// Parses the contract into an owned model, the parsed input is dropped afterwards.
+fn owned_model() -> OwnedModel {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  OwnedModel::try_from(model).unwrap()
+}
+
+#[test]
+fn owned_model_should_be_send_sync_and_clone() {
+  fn assert_owned<T: Send + Sync + Clone + 'static>() {}
+  assert_owned::<OwnedModel>();
+}
+
+#[test]
+fn owned_model_should_be_encoded_as_model() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let expected = Encoder::new_with_metering().encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  let owned_model = owned_model();
+  assert_eq!(expected, Encoder::new_with_metering().encode_owned(&owned_model).unwrap());
+  // Encoding does not consume the owned model.
+  assert_eq!(expected, Encoder::new_with_metering().encode_owned(&owned_model).unwrap());
+}
+
+#[test]
+fn owned_model_should_be_encoded_in_other_thread() {
+  let owned_model = owned_model();
+  let expected = Encoder::new_with_metering().encode_owned(&owned_model).unwrap();
+  let handle = std::thread::spawn(move || Encoder::new_with_metering().encode_owned(&owned_model).unwrap());
+  assert_eq!(expected, handle.join().unwrap());
+}
+
+#[test]
+fn owned_model_should_allow_modifying_br_table_and_data() {
+  let mut owned_model = owned_model();
+  owned_model.code_section_entries[0].touched = true;
+  let operators = &mut owned_model.code_section_entries[0].operators;
+  let position = operators.iter().position(|operator| matches!(operator, OwnedOperator::BrTable(_))).unwrap();
+  let OwnedOperator::BrTable(br_table) = &operators[position] else { unreachable!() };
+  assert_eq!([0], br_table.targets());
+  assert_eq!(1, br_table.default());
+  operators[position] = OwnedOperator::BrTable(OwnedBrTable::new(vec![1, 0, 1], 0));
+  owned_model.data[0].data = b"xyz!".to_vec();
+  let wasm_bytes = Encoder::new().encode_owned(&owned_model).unwrap();
+  wasmparser::validate(&wasm_bytes).unwrap();
+  let wat = wasmprinter::print_bytes(&wasm_bytes).unwrap();
+  assert!(wat.contains("br_table 1 (;@1;) 0 (;@2;) 1 (;@1;) 0 (;@2;)"), "{wat}");
+  assert!(wat.contains("\"xyz!\""), "{wat}");
+}
@@ -0,0 +1,101 @@
+use wasmarin::{CodeSectionEntry, Encoder, Metering, Parser, Pass, Pipeline, WasmarinError, WasmarinResult};
+
+/// Returns a module with many functions of different sizes.
+fn many_functions(count: usize) -> Vec<u8> {
+  let mut wat = String::from("(module (memory 1)\n");
+  for index in 0..count {
+    wat.push_str(&format!("(func (export \"f{index}\") (param i32) (result i32) local.get 0\n"));
+    for _ in 0..index % 7 {
+      wat.push_str("(if (result i32) (i32.eqz (local.get 0)) (then (i32.const 1)) (else (i32.load (local.get 0)))) i32.add\n");
+    }
+    wat.push_str(")\n");
+  }
+  wat.push(')');
+  wat::parse_str(wat).unwrap()
+}
+
+/// Pass rejecting functions with the given function indexes, copied to worker threads.
+#[derive(Clone)]
+struct RejectFunctions(Vec<u32>);
+
+impl Pass for RejectFunctions {
+  fn name(&self) -> &str {
+    "reject-functions"
+  }
+
+  fn update_function(&mut self, function_index: u32, _code_section_entry: &mut CodeSectionEntry) -> WasmarinResult<()> {
+    match self.0.contains(&function_index) {
+      true => Err(WasmarinError::new(format!("function {function_index} rejected"))),
+      false => Ok(()),
+    }
+  }
+
+  fn fork(&self) -> Option<Box<dyn Pass + Send>> {
+    Some(Box::new(self.clone()))
+  }
+}
+
+/// Pass recording the indexes of updated functions, can not be copied to worker threads.
+#[derive(Default)]
+struct RecordFunctions(std::rc::Rc<std::cell::RefCell<Vec<u32>>>);
+
+impl Pass for RecordFunctions {
+  fn name(&self) -> &str {
+    "record-functions"
+  }
+
+  fn update_function(&mut self, function_index: u32, _code_section_entry: &mut CodeSectionEntry) -> WasmarinResult<()> {
+    self.0.borrow_mut().push(function_index);
+    Ok(())
+  }
+}
+
+#[test]
+fn pipeline_should_be_sequential_by_default() {
+  assert_eq!(1, Pipeline::new().workers());
+  assert_eq!(1, Pipeline::new().with_workers(0).workers());
+  assert_eq!(4, Pipeline::new().with_workers(4).workers());
+}
+
+#[test]
+fn parallel_metering_should_equal_sequential() {
+  for wasm_bytes in [std::fs::read("tests/contracts/burner.wasm").unwrap(), many_functions(100)] {
+    let expected = Encoder::new_with_metering().encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+    for workers in [2, 3, 4, 8, 1000] {
+      let parallel = Encoder::new_with_metering()
+        .with_workers(workers)
+        .encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap())
+        .unwrap();
+      assert_eq!(expected, parallel, "workers = {workers}");
+    }
+  }
+}
+
+#[test]
+fn parallel_encoding_without_passes_should_equal_sequential() {
+  let wasm_bytes = many_functions(50);
+  let expected = Encoder::new().encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  let parallel = Encoder::new().with_workers(4).encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  assert_eq!(expected, parallel);
+  assert_eq!(wasm_bytes, parallel);
+}
+
+#[test]
+fn parallel_pass_should_report_first_error() {
+  let wasm_bytes = many_functions(40);
+  let pipeline = Pipeline::new().with_pass(RejectFunctions(vec![37, 12, 25])).with_workers(4);
+  let error = Encoder::new_with_pipeline(pipeline)
+    .encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap())
+    .unwrap_err();
+  assert_eq!("pass 'reject-functions' failed: function 12 rejected", error.to_string());
+}
+
+#[test]
+fn pass_without_fork_should_update_functions_sequentially() {
+  let wasm_bytes = many_functions(20);
+  let pass = RecordFunctions::default();
+  let function_indexes = pass.0.clone();
+  let mut pipeline = Pipeline::new().with_pass(pass).with_pass(Metering::new()).with_workers(4);
+  pipeline.run(&mut Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  assert_eq!((0..20).collect::<Vec<u32>>(), *function_indexes.borrow());
+}
@@ -0,0 +1,98 @@
+use wasmarin::{CodeSectionEntry, Encoder, Metering, Model, Parser, Pass, Pipeline, WasmarinError, WasmarinResult, REMAINING_POINTS_EXPORT_NAME};
+use wasmparser::Operator;
+
+const CONTRACT: &str = r#"
+  (module
+    (import "env" "log" (func (param i32)))
+    (func (export "run") (result i32)
+      nop
+      i32.const 1
+      nop
+    )
+  )
+"#;
+
+/// Pass removing all `nop` operators, recording the indexes of updated functions.
+#[derive(Default)]
+struct RemoveNops {
+  function_indexes: Vec<u32>,
+}
+
+impl Pass for RemoveNops {
+  fn name(&self) -> &str {
+    "remove-nops"
+  }
+
+  fn update_function(&mut self, function_index: u32, code_section_entry: &mut CodeSectionEntry) -> WasmarinResult<()> {
+    self.function_indexes.push(function_index);
+    code_section_entry.operators.retain(|operator| !matches!(operator, Operator::Nop));
+    Ok(())
+  }
+}
+
+/// Pass rejecting modules with exports.
+struct NoExports;
+
+impl Pass for NoExports {
+  fn name(&self) -> &str {
+    "no-exports"
+  }
+
+  fn update_model(&mut self, model: &mut Model) -> WasmarinResult<()> {
+    match model.exports.first() {
+      Some(export) => Err(WasmarinError::new(format!("unexpected export '{}'", export.name))),
+      None => Ok(()),
+    }
+  }
+}
+
+#[test]
+fn pass_should_update_functions_in_order() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let mut pass = RemoveNops::default();
+  pass.update_function(1, &mut model.code_section_entries[0]).unwrap();
+  assert_eq!(vec![1], pass.function_indexes);
+  assert_eq!(vec![Operator::I32Const { value: 1 }, Operator::End], model.code_section_entries[0].operators);
+}
+
+#[test]
+fn passes_should_run_in_order() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let mut pipeline = Pipeline::new().with_pass(RemoveNops::default()).with_pass(Metering::new());
+  assert_eq!(vec!["remove-nops", "metering"], pipeline.pass_names());
+  pipeline.run(&mut model).unwrap();
+  // Metering charges the function after removing both `nop` operators.
+  assert_eq!(Operator::I64Const { value: 1 }, model.code_section_entries[0].operators[1]);
+  assert_eq!(REMAINING_POINTS_EXPORT_NAME, model.exports[1].name);
+  assert_eq!(Some(0), model.metering.map(|metadata| metadata.counter_global_index));
+}
+
+#[test]
+fn pass_should_rewrite_functions_without_metering() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let encoded = Encoder::new()
+    .with_pass(RemoveNops::default())
+    .encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap())
+    .unwrap();
+  assert_eq!(wat::parse_str(CONTRACT.replace("nop", "")).unwrap(), encoded);
+}
+
+#[test]
+fn metering_pass_should_be_composable_with_encoder() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let pipeline = Pipeline::new().with_pass(RemoveNops::default()).with_pass(Metering::new());
+  let metered = Encoder::new_with_pipeline(pipeline).encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  let expected = Encoder::new_with_metering()
+    .encode(Parser::new().parse_wasm_bytes(&wat::parse_str(CONTRACT.replace("nop", "")).unwrap()).unwrap())
+    .unwrap();
+  assert_eq!(expected, metered);
+}
+
+#[test]
+fn failing_pass_should_be_named_in_error() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let result = Encoder::new().with_pass(NoExports).encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap());
+  assert_eq!("pass 'no-exports' failed: unexpected export 'run'", result.err().unwrap().to_string());
+}
@@ -0,0 +1,78 @@
+use wasmarin::{CustomSection, Encoder, Parser, SectionId, METERING_SECTION_NAME};
+
+/// Returns the names of custom sections and identifiers of known sections, in the order of appearance.
+fn sections(wasm_bytes: &[u8]) -> Vec<String> {
+  let mut sections = vec![];
+  for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+    match payload.unwrap() {
+      wasmparser::Payload::CustomSection(reader) => sections.push(reader.name().to_string()),
+      payload => {
+        if let Some((id, _)) = payload.as_section() {
+          sections.push(id.to_string());
+        }
+      }
+    }
+  }
+  sections
+}
+
+/// Builds a module with custom sections placed before, between and after known sections.
+fn module_with_custom_sections() -> Vec<u8> {
+  let custom_section = |name: &'static str| wasm_encoder::CustomSection {
+    name: name.into(),
+    data: vec![1, 2, 3].into(),
+  };
+  let mut module = wasm_encoder::Module::new();
+  module.section(&custom_section("first"));
+  let mut type_section = wasm_encoder::TypeSection::new();
+  type_section.ty().function([], []);
+  module.section(&type_section);
+  module.section(&custom_section("after-type"));
+  let mut function_section = wasm_encoder::FunctionSection::new();
+  function_section.function(0);
+  module.section(&function_section);
+  let mut code_section = wasm_encoder::CodeSection::new();
+  let mut function = wasm_encoder::Function::new([]);
+  function.instruction(&wasm_encoder::Instruction::End);
+  code_section.function(&function);
+  module.section(&code_section);
+  module.section(&custom_section("last"));
+  module.finish()
+}
+
+#[test]
+fn unmetered_round_trip_should_be_byte_identical() {
+  let wasm_bytes = std::fs::read("tests/contracts/burner.wasm").unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert_eq!(wasm_bytes, Encoder::new().encode(model).unwrap());
+}
+
+#[test]
+fn custom_sections_should_keep_their_placement() {
+  let wasm_bytes = module_with_custom_sections();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let placements: Vec<_> = model.custom_sections.iter().map(|custom_section| custom_section.after).collect();
+  assert_eq!(vec![None, Some(SectionId::Type), Some(SectionId::Code)], placements);
+  assert_eq!(wasm_bytes, Encoder::new().encode(model).unwrap());
+}
+
+#[test]
+fn empty_sections_should_not_be_encoded() {
+  let wasm_bytes = wat::parse_str("(module (memory 1))").unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let encoded = Encoder::new().encode(model).unwrap();
+  assert_eq!(vec!["5"], sections(&encoded));
+  assert_eq!(wasm_bytes, encoded);
+}
+
+#[test]
+fn added_custom_sections_should_be_placed_at_the_end() {
+  let wasm_bytes = module_with_custom_sections();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  model.custom_sections.insert(0, CustomSection::new("added", vec![]));
+  let encoded = Encoder::new_with_metering().encode(model).unwrap();
+  assert_eq!(
+    vec!["first", "1", "after-type", "3", "6", "7", "10", "last", "added", METERING_SECTION_NAME],
+    sections(&encoded)
+  );
+}
@@ -0,0 +1,99 @@
+use wasmarin::{CodeSectionEntry, Encoder, InstrumentedInput, Parser, Pass, Pipeline, WasmarinResult};
+use wasmparser::Operator;
+
+/// Contracts encoded by both the streaming and the model encoder.
+const CONTRACTS: [&str; 3] = ["tests/contracts/burner.wasm", "tests/contracts/big_locals.wat", "tests/contracts/unmetered_call_frame.wasm"];
+
+/// Pass removing all `nop` operators.
+struct RemoveNops;
+
+impl Pass for RemoveNops {
+  fn name(&self) -> &str {
+    "remove-nops"
+  }
+
+  fn update_function(&mut self, _function_index: u32, code_section_entry: &mut CodeSectionEntry) -> WasmarinResult<()> {
+    code_section_entry.operators.retain(|operator| !matches!(operator, Operator::Nop));
+    Ok(())
+  }
+}
+
+/// Pass updating only module-level items.
+struct ModelOnly;
+
+impl Pass for ModelOnly {
+  fn name(&self) -> &str {
+    "model-only"
+  }
+
+  fn update_function(&mut self, _function_index: u32, _code_section_entry: &mut CodeSectionEntry) -> WasmarinResult<()> {
+    panic!("function bodies are not needed");
+  }
+
+  fn needs_function_bodies(&self) -> bool {
+    false
+  }
+}
+
+#[test]
+fn streaming_without_passes_should_copy_input() {
+  for path in CONTRACTS {
+    let wasm_bytes = wat::parse_file(path).unwrap();
+    assert_eq!(wasm_bytes, Encoder::new().encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap(), "{path}");
+  }
+}
+
+#[test]
+fn streaming_with_metering_should_equal_model_encoding() {
+  for path in CONTRACTS {
+    let wasm_bytes = wat::parse_file(path).unwrap();
+    let expected = Encoder::new_with_metering().encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+    assert_eq!(expected, Encoder::new_with_metering().encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap(), "{path}");
+  }
+}
+
+#[test]
+fn streaming_should_run_all_passes_on_each_function() {
+  let wasm_bytes = wat::parse_str(r#"(module (func (export "run") (result i32) nop i32.const 1 nop) (func nop))"#).unwrap();
+  let pipeline = || Pipeline::new().with_pass(RemoveNops).with_pass(wasmarin::Metering::new());
+  let expected = Encoder::new_with_pipeline(pipeline()).encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  let streamed = Encoder::new_with_pipeline(pipeline()).encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap();
+  assert_eq!(expected, streamed);
+  assert!(!wasmprinter::print_bytes(&streamed).unwrap().contains("nop"));
+}
+
+#[test]
+fn functions_changed_by_pass_alone_should_be_re_encoded() {
+  let wasm_bytes = wat::parse_str(r#"(module (func (export "run") (result i32) nop i32.const 1 nop) (func nop))"#).unwrap();
+  let expected = wat::parse_str(r#"(module (func (export "run") (result i32) i32.const 1) (func))"#).unwrap();
+  let encoded = Encoder::new().with_pass(RemoveNops).encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  assert_eq!(expected, encoded);
+  assert_eq!(expected, Encoder::new().with_pass(RemoveNops).encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap());
+}
+
+#[test]
+fn streaming_should_copy_instrumented_input() {
+  let wasm_bytes = std::fs::read("tests/contracts/burner.wasm").unwrap();
+  let instrumented = Encoder::new_with_metering().encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap();
+  let mut parser = Parser::new().with_instrumented_input(InstrumentedInput::Skip);
+  assert_eq!(instrumented, Encoder::new_with_metering().encode_wasm_bytes(&mut parser, &instrumented).unwrap());
+  assert!(Encoder::new_with_metering().encode_wasm_bytes(&mut Parser::new(), &instrumented).is_err());
+}
+
+#[test]
+fn streaming_should_validate_function_bodies() {
+  let wasm_bytes = wat::parse_str("(module (func (result i32) i64.const 1))").unwrap();
+  for mut encoder in [Encoder::new(), Encoder::new_with_metering()] {
+    let error = encoder.encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap_err();
+    assert!(error.to_string().contains("type mismatch"), "{error}");
+  }
+}
+
+#[test]
+fn streaming_should_not_decode_bodies_not_needed_by_passes() {
+  for path in CONTRACTS {
+    let wasm_bytes = wat::parse_file(path).unwrap();
+    let mut encoder = Encoder::new().with_pass(ModelOnly);
+    assert_eq!(wasm_bytes, encoder.encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap(), "{path}");
+  }
+}
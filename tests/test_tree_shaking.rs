@@ -0,0 +1,178 @@
+use wasmarin::{tree_shake, Encoder, Parser, Pipeline, TreeShaking, TreeShakingReport};
+
+const CONTRACT: &str = r#"
+  (module
+    (type (func))
+    (type (func (param i32) (result i32)))
+    (type (func (param i64)))
+    (import "env" "log" (func (type 1)))
+    (memory 1)
+    (table 2 funcref)
+    (global (mut i32) (i32.const 0))
+    (global i32 (i32.const 7))
+    (global i32 (i32.const 8))
+    (export "run" (func 1))
+    (export "limit" (global 2))
+    (elem (i32.const 0) func 3)
+    (elem func 4)
+    (data (i32.const 0) "a")
+    (data "dead")
+    (data "live")
+    (func (type 1)
+      (global.set 0 (call 2 (local.get 0)))
+      (memory.init 2 (i32.const 0) (i32.const 0) (i32.const 4))
+      (data.drop 2)
+      (call_indirect (type 0) (i32.const 0))
+      (global.get 0))
+    (func (type 1) (call 0 (local.get 0)))
+    (func (type 0))
+    (func (type 0) (drop (global.get 1)))
+    (func (type 0) (call 4))
+  )
+"#;
+
+const SHAKEN: &str = r#"
+  (module
+    (type (func))
+    (type (func (param i32) (result i32)))
+    (import "env" "log" (func (type 1)))
+    (memory 1)
+    (table 2 funcref)
+    (global (mut i32) (i32.const 0))
+    (global i32 (i32.const 8))
+    (export "run" (func 1))
+    (export "limit" (global 1))
+    (elem (i32.const 0) func 3)
+    (data (i32.const 0) "a")
+    (data "live")
+    (func (type 1)
+      (global.set 0 (call 2 (local.get 0)))
+      (memory.init 1 (i32.const 0) (i32.const 0) (i32.const 4))
+      (data.drop 1)
+      (call_indirect (type 0) (i32.const 0))
+      (global.get 0))
+    (func (type 1) (call 0 (local.get 0)))
+    (func (type 0))
+  )
+"#;
+
+#[test]
+fn unreachable_items_should_be_removed() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let report = tree_shake(&mut model).unwrap();
+  assert_eq!(
+    TreeShakingReport {
+      functions: vec![4, 5],
+      globals: vec![1],
+      types: vec![2],
+      elements: vec![1],
+      data: vec![1],
+    },
+    report
+  );
+  assert_eq!(wat::parse_str(SHAKEN).unwrap(), Encoder::new().encode(model).unwrap());
+}
+
+#[test]
+fn tree_shaking_should_be_idempotent() {
+  let wasm_bytes = wat::parse_str(SHAKEN).unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert!(tree_shake(&mut model).unwrap().is_empty());
+  assert_eq!(wasm_bytes, Encoder::new().encode(model).unwrap());
+}
+
+#[test]
+fn names_of_removed_items_should_be_dropped() {
+  let wasm_bytes = wat::parse_str(
+    r#"(module
+      (func $dead (call $also_dead))
+      (func $also_dead (call $dead))
+      (func $run (export "run") (local $x i32) (call $helper))
+      (func $helper))"#,
+  )
+  .unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert_eq!(vec![0, 1], tree_shake(&mut model).unwrap().functions);
+  let wat = wasmprinter::print_bytes(Encoder::new().encode(model).unwrap()).unwrap();
+  assert!(wat.contains("(func $run (;0;)"), "{wat}");
+  assert!(wat.contains("(local $x i32)"), "{wat}");
+  assert!(wat.contains("call $helper"), "{wat}");
+  assert!(wat.contains("(func $helper (;1;)"), "{wat}");
+  assert!(!wat.contains("dead"), "{wat}");
+}
+
+#[test]
+fn tree_shaking_pass_should_run_before_metering() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let pipeline = Pipeline::new().with_pass(TreeShaking::new());
+  let mut encoder = Encoder::new_with_pipeline(pipeline).with_pass(wasmarin::Metering::new());
+  let instrumented = encoder.encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  let expected = Encoder::new_with_metering()
+    .encode(Parser::new().parse_wasm_bytes(&wat::parse_str(SHAKEN).unwrap()).unwrap())
+    .unwrap();
+  assert_eq!(expected, instrumented);
+}
+
+#[test]
+fn tree_shaking_pass_should_fail_on_streaming() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let mut encoder = Encoder::new_with_pipeline(Pipeline::new().with_pass(TreeShaking::new()));
+  assert_eq!(
+    "pass 'tree-shaking' failed: function bodies must be parsed before tree shaking",
+    encoder.encode_wasm_bytes(&mut Parser::new(), &wasm_bytes).unwrap_err().to_string()
+  );
+}
+
+#[test]
+fn metering_global_should_be_kept() {
+  let wasm_bytes = wat::parse_str(CONTRACT).unwrap();
+  let instrumented = Encoder::new_with_metering().encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  let mut model = Parser::new()
+    .with_instrumented_input(wasmarin::InstrumentedInput::Skip)
+    .parse_wasm_bytes(&instrumented)
+    .unwrap();
+  let report = tree_shake(&mut model).unwrap();
+  assert_eq!(vec![1], report.globals);
+  // The global storing remaining points follows the removed global, so it is renumbered.
+  assert_eq!(2, model.metering.as_ref().unwrap().counter_global_index);
+  let reparsed = Encoder::new().encode(model).unwrap();
+  let model = Parser::new()
+    .with_instrumented_input(wasmarin::InstrumentedInput::Skip)
+    .parse_wasm_bytes(&reparsed)
+    .unwrap();
+  assert_eq!(2, model.metering.unwrap().counter_global_index);
+}
+
+#[test]
+fn burner_should_remain_valid() {
+  let wasm_bytes = std::fs::read("tests/contracts/burner.wasm").unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let exports = model.exports.iter().map(|export| export.name.clone()).collect::<Vec<_>>();
+  tree_shake(&mut model).unwrap();
+  let shaken = Encoder::new().encode(model).unwrap();
+  assert!(shaken.len() <= wasm_bytes.len());
+  let model = Parser::new().parse_wasm_bytes(&shaken).unwrap();
+  assert_eq!(exports, model.exports.iter().map(|export| export.name.clone()).collect::<Vec<_>>());
+}
+
+#[test]
+fn functions_referenced_by_removed_items_should_remain_declared() {
+  let wasm_bytes = wat::parse_str(
+    r#"(module
+      (func $f)
+      (global $g funcref (ref.func $f))
+      (func (export "run") (result funcref) (ref.func $f)))"#,
+  )
+  .unwrap();
+  let mut model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert_eq!(vec![0], tree_shake(&mut model).unwrap().globals);
+  let shaken = Encoder::new().encode(model).unwrap();
+  wasmparser::Validator::new().validate_all(&shaken).unwrap();
+  let wat = wasmprinter::print_bytes(&shaken).unwrap();
+  assert!(wat.contains("(elem (;0;) declare func $f)"), "{wat}");
+  // The declarative segment is a root, so tree shaking the output again keeps it.
+  let mut model = Parser::new().parse_wasm_bytes(&shaken).unwrap();
+  assert!(tree_shake(&mut model).unwrap().is_empty());
+  assert_eq!(shaken, Encoder::new().encode(model).unwrap());
+}
@@ -0,0 +1,170 @@
+use wasmarin::{uninstrument, Encoder, InstrumentedInput, Metering, Parser};
+
+/// Number of randomly generated modules checked by property tests.
+const CASES: u64 = 200;
+
+/// Simple xorshift generator, so generated modules are reproducible from the seed.
+struct Random(u64);
+
+impl Random {
+  fn new(seed: u64) -> Self {
+    Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+  }
+
+  /// Returns a random number in range `0..n`.
+  fn below(&mut self, n: u64) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0 % n
+  }
+
+  fn chance(&mut self, percent: u64) -> bool {
+    self.below(100) < percent
+  }
+}
+
+/// Generates a random valid module, exercising the features affected by metering.
+fn random_module(random: &mut Random) -> String {
+  let mut wat = String::from("(module\n");
+  if random.chance(50) {
+    wat.push_str("(import \"env\" \"log\" (func $log (param i32)))\n");
+  }
+  if random.chance(50) {
+    wat.push_str("(import \"env\" \"chain_id\" (global i32))\n");
+  }
+  wat.push_str("(memory (export \"memory\") 1)\n");
+  wat.push_str("(data $d \"0123456789abcdefghijklmnopqrstuvwxyz\")\n");
+  wat.push_str("(global $g (mut i32) (i32.const 0))\n");
+  let function_count = 1 + random.below(4);
+  for index in 0..function_count {
+    wat.push_str(&format!("(func $f{index} (param i32) (local i32 i32)\n"));
+    statements(random, &mut wat, function_count, 1, 3);
+    wat.push_str(")\n");
+    if random.chance(60) {
+      wat.push_str(&format!("(export \"f{index}\" (func $f{index}))\n"));
+    }
+  }
+  if random.chance(40) {
+    wat.push_str("(func $start (local i32 i32 i32)\n");
+    statements(random, &mut wat, function_count, 1, 2);
+    wat.push_str(")\n(start $start)\n");
+  }
+  wat.push_str(")\n");
+  wat
+}
+
+/// Generates a random sequence of stack-neutral statements.
+fn statements(random: &mut Random, wat: &mut String, function_count: u64, depth: u64, nesting: u64) {
+  for _ in 0..random.below(6) {
+    match random.below(11) {
+      0 => wat.push_str("nop\n"),
+      1 => wat.push_str(&format!("(local.set 1 (i32.add (local.get 1) (i32.const {})))\n", random.below(100))),
+      2 => wat.push_str(&format!("(global.set $g (i32.const {}))\n", random.below(100))),
+      3 => wat.push_str(&format!("(br_if {} (local.get 0))\n", random.below(depth))),
+      4 => wat.push_str(&format!("(call $f{} (local.get 1))\n", random.below(function_count))),
+      5 => wat.push_str("data.drop $d\n"),
+      6 => wat.push_str(&format!(
+        "(local.set 2 (i32.const 0)) (loop (local.set 1 (i32.add (local.get 1) (local.get 2))) (br_if 0 (i32.lt_s (local.tee 2 (i32.add (local.get 2) (i32.const 1))) (i32.const {}))))\n",
+        1 + random.below(10)
+      )),
+      7 if nesting > 0 => {
+        wat.push_str("(block\n");
+        statements(random, wat, function_count, depth + 1, nesting - 1);
+        wat.push_str(")\n");
+      }
+      8 if nesting > 0 => {
+        wat.push_str("(loop\n");
+        statements(random, wat, function_count, depth + 1, nesting - 1);
+        wat.push_str(")\n");
+      }
+      9 if nesting > 0 => {
+        wat.push_str("(if (local.get 0) (then\n");
+        statements(random, wat, function_count, depth + 1, nesting - 1);
+        wat.push_str(") (else\n");
+        statements(random, wat, function_count, depth + 1, nesting - 1);
+        wat.push_str("))\n");
+      }
+      10 if random.chance(20) => wat.push_str("return\n"),
+      _ => wat.push_str("(drop (i32.const 1))\n"),
+    }
+  }
+}
+
+/// Returns the module encoded without metering.
+fn normalize(wasm_bytes: &[u8]) -> Vec<u8> {
+  Encoder::new().encode(Parser::new().parse_wasm_bytes(wasm_bytes).unwrap()).unwrap()
+}
+
+/// Returns the instrumented module with metering removed, encoded without metering.
+fn strip(instrumented: &[u8]) -> Vec<u8> {
+  let model = Parser::new().with_instrumented_input(InstrumentedInput::Skip).parse_wasm_bytes(instrumented).unwrap();
+  Encoder::new().encode(uninstrument(model).unwrap()).unwrap()
+}
+
+#[test]
+fn uninstrumented_module_should_equal_original() {
+  for seed in 0..CASES {
+    let wat = random_module(&mut Random::new(seed));
+    let wasm_bytes = wat::parse_str(&wat).unwrap();
+    let original = normalize(&wasm_bytes);
+    for mut encoder in [Encoder::new_with_metering(), Encoder::new().with_pass(Metering::new().with_counted_loop_hoisting(true))] {
+      let instrumented = encoder.encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+      assert_ne!(original, instrumented, "seed {seed}");
+      assert_eq!(original, strip(&instrumented), "seed {seed}:\n{wat}");
+    }
+  }
+}
+
+#[test]
+fn reinstrumented_module_should_equal_instrumented() {
+  for seed in 0..CASES {
+    let wasm_bytes = wat::parse_str(random_module(&mut Random::new(seed))).unwrap();
+    let instrumented = Encoder::new_with_metering().encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+    let stripped = strip(&instrumented);
+    let reinstrumented = Encoder::new_with_metering().encode(Parser::new().parse_wasm_bytes(&stripped).unwrap()).unwrap();
+    assert_eq!(instrumented, reinstrumented, "seed {seed}");
+  }
+}
+
+#[test]
+fn burner_contract_should_round_trip() {
+  let wasm_bytes = std::fs::read("tests/contracts/burner.wasm").unwrap();
+  let instrumented = Encoder::new_with_metering().encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  // Uninstrumented functions are re-encoded, so they are compared with an explicitly re-encoded baseline.
+  assert_eq!(reencode(&wasm_bytes), strip(&instrumented));
+}
+
+/// Returns the module encoded without metering, with all function bodies re-encoded instead of copied.
+///
+/// Compilers emit padded LEB128 immediates (like relocatable call indexes), which are kept in copied bodies,
+/// but are encoded in the shortest form in re-encoded bodies.
+fn reencode(wasm_bytes: &[u8]) -> Vec<u8> {
+  let mut model = Parser::new().parse_wasm_bytes(wasm_bytes).unwrap();
+  for code_section_entry in &mut model.code_section_entries {
+    code_section_entry.original_body = None;
+  }
+  Encoder::new().encode(model).unwrap()
+}
+
+#[test]
+fn module_not_instrumented_should_fail() {
+  let wasm_bytes = wat::parse_str("(module)").unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  assert_eq!("module is not instrumented", uninstrument(model).err().unwrap().to_string());
+}
+
+#[test]
+fn counter_accessed_outside_of_charges_should_fail() {
+  let wasm_bytes = wat::parse_str(r#"(module (func (export "run") (result i32) i32.const 1))"#).unwrap();
+  let instrumented = Encoder::new_with_metering().encode(Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap()).unwrap();
+  let mut model = Parser::new().with_instrumented_input(InstrumentedInput::Skip).parse_wasm_bytes(&instrumented).unwrap();
+  // Tamper with the module by reading the counter after the charge.
+  let operators = &mut model.code_section_entries[0].operators;
+  operators.insert(operators.len() - 1, wasmparser::Operator::Drop);
+  operators.insert(operators.len() - 2, wasmparser::Operator::GlobalGet { global_index: 0 });
+  assert_eq!(
+    "global 0 storing remaining points is accessed outside of charges in function 0",
+    uninstrument(model).err().unwrap().to_string()
+  );
+}
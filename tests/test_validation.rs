@@ -0,0 +1,48 @@
+use wasmarin::Parser;
+
+/// Parses the module given in text format, returning the error message.
+fn parse_err(wat: &str) -> String {
+  let wasm_bytes = wat::parse_str(wat).unwrap();
+  Parser::new().parse_wasm_bytes(&wasm_bytes).err().unwrap().to_string()
+}
+
+#[test]
+fn invalid_function_body_should_be_rejected() {
+  let message = parse_err(r#"(module (func (result i32) i64.const 0))"#);
+  assert!(message.starts_with("type mismatch: expected i32, found i64"), "{message}");
+}
+
+#[test]
+fn unknown_local_should_be_rejected() {
+  let message = parse_err(r#"(module (func (local i32) local.get 1 drop))"#);
+  assert!(message.starts_with("unknown local 1"), "{message}");
+}
+
+#[test]
+fn invalid_simd_operator_should_be_rejected() {
+  let message = parse_err(r#"(module (func (result v128) i32.const 0 i8x16.splat i8x16.extract_lane_s 0))"#);
+  assert!(message.starts_with("type mismatch"), "{message}");
+}
+
+#[test]
+fn invalid_module_structure_should_be_rejected() {
+  let message = parse_err(r#"(module (func call 1))"#);
+  assert!(message.starts_with("unknown function 1"), "{message}");
+}
+
+#[test]
+fn parsed_operators_should_match_function_bodies() {
+  let wasm_bytes = std::fs::read("tests/contracts/burner.wasm").unwrap();
+  let model = Parser::new().parse_wasm_bytes(&wasm_bytes).unwrap();
+  let mut function_bodies = vec![];
+  for payload in wasmparser::Parser::new(0).parse_all(&wasm_bytes) {
+    if let wasmparser::Payload::CodeSectionEntry(body) = payload.unwrap() {
+      let operators: Vec<_> = body.get_operators_reader().unwrap().into_iter().map(Result::unwrap).collect();
+      function_bodies.push(operators);
+    }
+  }
+  assert_eq!(function_bodies.len(), model.code_section_entries.len());
+  for (operators, code_section_entry) in function_bodies.iter().zip(&model.code_section_entries) {
+    assert_eq!(operators, &code_section_entry.operators);
+  }
+}